@@ -1,4 +1,4 @@
-use crate::utility::*;
+use crate::{sampler::Sampler, utility::*};
 
 use std::f64;
 
@@ -49,11 +49,58 @@ impl Camera {
         }
     }
 
-    pub fn get_ray(&self, s: f64, t: f64, time0: f64, time1: f64) -> Ray {
-        let rd = self.lens_radius * Vec3::random_in_unit_disk();
+    pub fn get_ray(
+        &self,
+        s: f64,
+        t: f64,
+        time0: f64,
+        time1: f64,
+        sampler: &mut dyn Sampler,
+    ) -> Ray {
+        let rd = self.lens_radius * Vec3::random_in_unit_disk(sampler);
         let offset = self.u * rd.x + self.v * rd.y;
         let orig = self.origin + offset;
         let dir = self.lower_left_corner + s * self.horizontal + t * self.vertical - orig;
-        Ray::new(&orig, &dir, randrange(time0, time1))
+        Ray::new(&orig, &dir, lerp(time0, time1, sampler.get_1d()))
+    }
+
+    // the direction the camera looks in
+    pub fn forward(&self) -> Vec3 {
+        -cross(&self.u, &self.v)
+    }
+
+    // a point of the lens, for paths from the lights to reach the camera
+    pub fn sample_lens(&self, sampler: &mut dyn Sampler) -> Point3 {
+        let rd = self.lens_radius * Vec3::random_in_unit_disk(sampler);
+        self.origin + self.u * rd.x + self.v * rd.y
+    }
+
+    // (s, t) of the camera ray from `lens` through `p`, the inverse of `get_ray`; None behind
+    // the camera
+    pub fn raster(&self, p: &Point3, lens: &Point3) -> Option<(f64, f64)> {
+        let dir = *p - *lens;
+        let forward = self.forward();
+        let focus_plane = self.lower_left_corner + self.horizontal / 2. + self.vertical / 2.;
+        let depth = dot(&dir, &forward);
+        if depth <= 0. {
+            return None;
+        }
+        let q =
+            *lens + dot(&(focus_plane - *lens), &forward) / depth * dir - self.lower_left_corner;
+        Some((
+            dot(&q, &self.horizontal) / self.horizontal.length_squared(),
+            dot(&q, &self.vertical) / self.vertical.length_squared(),
+        ))
+    }
+
+    // area of (s, t) per solid angle of the camera rays around `dir`, whatever the lens point
+    pub fn film_density(&self, dir: &Vec3) -> f64 {
+        let cos = dot(&dir.unit(), &self.forward());
+        if cos <= 0. {
+            return 0.;
+        }
+        let focus_dist = dot(&(self.origin - self.lower_left_corner), &-self.forward());
+        let film_area = self.horizontal.length() * self.vertical.length();
+        focus_dist * focus_dist / (film_area * cos * cos * cos)
     }
 }
@@ -0,0 +1,652 @@
+use crate::{
+    integrator::{ClampMode, IntegratorKind, MisHeuristic, RadianceClamp},
+    render::{
+        AdaptiveSampling, Aov, ColorPipeline, Denoiser, Filter, FilterKind, OutputFormat, Tile,
+        ToneMap, Transfer, DEFAULT_TILE_SIZE,
+    },
+    sampler::SamplerKind,
+    scene::{SceneConfig, BUILTIN_SCENES},
+    utility::*,
+};
+use clap::{error::ErrorKind, CommandFactory, Parser};
+use std::{
+    path::{Path, PathBuf},
+    thread,
+    time::Duration,
+};
+
+const CHECKPOINT_PASS_SPP: u32 = 16;
+const TIME_BUDGET_PASS_SPP: u32 = 4;
+const DEFAULT_MIN_SPP: u32 = 16;
+
+#[derive(Parser, Debug)]
+#[command(about = "A toy ray tracer in Rust", version)]
+pub struct Args {
+    /// Built-in scene name or path of a .json / .toml scene file
+    #[arg(short, long, default_value = "scifi1", value_parser = parse_scene)]
+    pub scene: String,
+
+    /// Path of the output image
+    #[arg(short, long, default_value = "output/test/test5.jpg")]
+    pub output: PathBuf,
+
+    /// Format of the output image [default: from the extension of the output path]
+    #[arg(short, long, value_enum)]
+    pub format: Option<OutputFormat>,
+
+    /// Write the scene, with the options below applied, to a .json / .toml file instead of rendering
+    #[arg(long, value_name = "FILE")]
+    pub export: Option<PathBuf>,
+
+    /// Tone mapping operator of 8-bit outputs
+    #[arg(long, value_enum, default_value_t = ToneMap::None)]
+    pub tonemap: ToneMap,
+
+    /// Exposure compensation in stops
+    #[arg(long, default_value_t = 0., value_parser = parse_f64, allow_hyphen_values = true)]
+    pub exposure: f64,
+
+    /// Luminance mapped to white by reinhard-extended [default: brightest pixel]
+    #[arg(long, value_parser = parse_positive)]
+    pub white_point: Option<f64>,
+
+    /// Color temperature in kelvin of the light to render as neutral white
+    #[arg(long, value_parser = parse_kelvin)]
+    pub white_balance: Option<f64>,
+
+    /// Transfer function of 8-bit outputs
+    #[arg(long, value_enum, default_value_t = Transfer::Srgb)]
+    pub transfer: Transfer,
+
+    /// Image width in pixels [default: preset of the scene]
+    #[arg(short, long, value_parser = clap::value_parser!(u32).range(2..))]
+    pub width: Option<u32>,
+
+    /// Image height in pixels [default: width / aspect ratio]
+    #[arg(long, value_parser = clap::value_parser!(u32).range(2..))]
+    pub height: Option<u32>,
+
+    /// Render only the pixels [X0, X1) x [Y0, Y1), counted from the top left corner
+    #[arg(long, value_name = "X0,Y0,X1,Y1", value_parser = parse_crop, conflicts_with = "crop_window")]
+    pub crop: Option<[u32; 4]>,
+
+    /// Render only this window of the image, in fractions of its width and height from the top left
+    #[arg(long, value_name = "X0,Y0,X1,Y1", value_parser = parse_crop_window)]
+    pub crop_window: Option<[f64; 4]>,
+
+    /// Write the whole image with only the crop rendered, instead of the crop alone
+    #[arg(long)]
+    pub full_frame: bool,
+
+    /// Samples per pixel [default: preset of the scene]
+    #[arg(long, value_parser = clap::value_parser!(u32).range(1..))]
+    pub spp: Option<u32>,
+
+    /// Render progressively in passes of this many samples per pixel, writing the image after each pass
+    #[arg(long, value_parser = clap::value_parser!(u32).range(1..))]
+    pub pass_spp: Option<u32>,
+
+    /// Keep the image of every pass as a numbered snapshot next to the output, instead of overwriting it
+    #[arg(long, requires = "pass_spp")]
+    pub snapshots: bool,
+
+    /// Save the accumulated samples to this file after every pass [default pass size with it: 16 spp]
+    #[arg(long, value_name = "FILE")]
+    pub checkpoint: Option<PathBuf>,
+
+    /// Continue the render saved in the checkpoint file, if it exists, up to the target spp
+    #[arg(long, requires = "checkpoint")]
+    pub resume: bool,
+
+    /// Keep rendering passes for this long, as 90, 90s, 5m or 1h; `--spp` then caps the samples per pixel
+    #[arg(long, value_name = "DURATION", value_parser = parse_duration)]
+    pub time_budget: Option<Duration>,
+
+    /// Sample adaptively, until the 95% confidence interval of a pixel is within this fraction of its
+    /// luminance; `--spp` is then the average budget
+    #[arg(long, value_name = "THRESHOLD", value_parser = parse_positive)]
+    pub adaptive: Option<f64>,
+
+    /// Samples every pixel gets with adaptive sampling [default: 16, at most --spp]
+    #[arg(long, requires = "adaptive", value_parser = clap::value_parser!(u32).range(2..))]
+    pub min_spp: Option<u32>,
+
+    /// Most samples a pixel gets with adaptive sampling [default: 8 * --spp]
+    #[arg(long, requires = "adaptive", value_parser = clap::value_parser!(u32).range(2..))]
+    pub max_spp: Option<u32>,
+
+    /// Write an image of the samples each pixel got, from black (none) to white (the most)
+    #[arg(long, value_name = "FILE")]
+    pub spp_heatmap: Option<PathBuf>,
+
+    /// Extra layers to write, comma separated: layers of an EXR output, separate images otherwise
+    #[arg(long, value_enum, value_delimiter = ',', value_name = "AOVS")]
+    pub aov: Vec<Aov>,
+
+    /// Also write a denoised image, guided by the albedo, normal and depth of the first hits
+    #[arg(long, value_enum)]
+    pub denoise: Option<Denoiser>,
+
+    /// Light transport algorithm
+    #[arg(long, value_enum, default_value_t = IntegratorKind::Path)]
+    pub integrator: IntegratorKind,
+
+    /// Photons traced for every sample per pixel by the photon and sppm integrators
+    #[arg(long, default_value_t = 100000, value_parser = clap::value_parser!(u32).range(1..))]
+    pub photons: u32,
+
+    /// Radius photons are gathered from, the first one with sppm [default: reaching about 50 photons around a typical one]
+    #[arg(long, value_parser = parse_positive)]
+    pub photon_radius: Option<f64>,
+
+    /// Heuristic combining the sampling strategies of a path
+    #[arg(long, value_enum, default_value_t = MisHeuristic::Power)]
+    pub mis: MisHeuristic,
+
+    /// Sampler of the pixel, lens, time and path dimensions
+    #[arg(long, value_enum, default_value_t = SamplerKind::Independent)]
+    pub sampler: SamplerKind,
+
+    /// Reconstruction filter weighting the samples into the pixels around them
+    #[arg(long, value_enum, default_value_t = FilterKind::Box)]
+    pub filter: FilterKind,
+
+    /// Radius of the filter in pixels [default: 0.5 box, 1 tent, 1.5 gaussian, 2 mitchell, 3 lanczos]
+    #[arg(long, value_parser = parse_positive)]
+    pub filter_radius: Option<f64>,
+
+    /// Maximum number of bounces of a path
+    #[arg(long, default_value_t = 20, value_parser = clap::value_parser!(i32).range(1..))]
+    pub max_depth: i32,
+
+    /// Bounces of a path before Russian roulette may end it; paths past it continue with a
+    /// probability as high as their throughput
+    #[arg(long, default_value_t = 3, value_parser = clap::value_parser!(i32).range(1..))]
+    pub rr_depth: i32,
+
+    /// Largest radiance a sample may carry, to suppress fireflies
+    #[arg(long, value_parser = parse_positive)]
+    pub clamp: Option<f64>,
+
+    /// Which light `--clamp` applies to
+    #[arg(long, value_enum, default_value_t = ClampMode::Indirect, requires = "clamp")]
+    pub clamp_mode: ClampMode,
+
+    /// Report where NaN, infinite and negative samples come from
+    #[arg(long)]
+    pub diagnostics: bool,
+
+    /// Print the rays traced, intersection tests and time of each phase at the end
+    #[arg(long)]
+    pub stats: bool,
+
+    /// Write the render statistics as JSON
+    #[arg(long, value_name = "FILE")]
+    pub stats_json: Option<PathBuf>,
+
+    /// Number of render threads [default: number of cores]
+    #[arg(short = 'j', long, value_parser = parse_threads)]
+    pub threads: Option<usize>,
+
+    /// Side of the square tiles the image is split into, in pixels
+    #[arg(long, default_value_t = DEFAULT_TILE_SIZE, value_parser = clap::value_parser!(u32).range(1..))]
+    pub tile_size: u32,
+
+    /// Seed of the random generator, for reproducible renders
+    #[arg(long)]
+    pub seed: Option<u64>,
+
+    /// Camera position, as "x,y,z"
+    #[arg(long, value_parser = parse_vec3, allow_hyphen_values = true)]
+    pub lookfrom: Option<Point3>,
+
+    /// Point the camera looks at, as "x,y,z"
+    #[arg(long, value_parser = parse_vec3, allow_hyphen_values = true)]
+    pub lookat: Option<Point3>,
+
+    /// Up direction of the camera, as "x,y,z"
+    #[arg(long, value_parser = parse_vec3, allow_hyphen_values = true)]
+    pub vup: Option<Vec3>,
+
+    /// Vertical field of view in degrees
+    #[arg(long, value_parser = parse_fov)]
+    pub vfov: Option<f64>,
+
+    /// Lens aperture, 0 for a pinhole camera
+    #[arg(long, value_parser = parse_non_negative)]
+    pub aperture: Option<f64>,
+
+    /// Distance to the plane in focus
+    #[arg(long, value_parser = parse_positive)]
+    pub focus_dist: Option<f64>,
+
+    /// Width / height of the image
+    #[arg(long, value_parser = parse_positive)]
+    pub aspect_ratio: Option<f64>,
+}
+
+impl Args {
+    // checks between arguments that clap cannot express
+    pub fn validate(&self) {
+        if self.width.is_some() && self.height.is_some() && self.aspect_ratio.is_some() {
+            Self::command()
+                .error(
+                    ErrorKind::ArgumentConflict,
+                    "at most two of '--width', '--height' and '--aspect-ratio' can be given",
+                )
+                .exit();
+        }
+        if let (Some(lookfrom), Some(lookat)) = (self.lookfrom, self.lookat) {
+            if (lookfrom - lookat).near_zero() {
+                Self::command()
+                    .error(
+                        ErrorKind::ValueValidation,
+                        "'--lookfrom' and '--lookat' must be different points",
+                    )
+                    .exit();
+            }
+        }
+        if let Some(vup) = self.vup {
+            if vup.near_zero() {
+                Self::command()
+                    .error(ErrorKind::ValueValidation, "'--vup' must be non-zero")
+                    .exit();
+            }
+        }
+        if let Some(export) = &self.export {
+            let extension = export.extension().and_then(|ext| ext.to_str());
+            if !matches!(extension, Some("json" | "toml")) {
+                Self::command()
+                    .error(
+                        ErrorKind::ValueValidation,
+                        format!(
+                            "cannot export to '{}', expected a .json or .toml file",
+                            export.display()
+                        ),
+                    )
+                    .exit();
+            }
+            return;
+        }
+        if self.format.is_none() && OutputFormat::from_path(&self.output).is_none() {
+            Self::command()
+                .error(
+                    ErrorKind::ValueValidation,
+                    format!(
+                        "unknown output format of '{}', expected a .jpg, .png, .ppm, .hdr or .exr file, or '--format'",
+                        self.output.display()
+                    ),
+                )
+                .exit();
+        }
+        // the variance of a pixel comes from its own samples, and leaves out the light paths of
+        // the bidirectional integrator that land on it
+        if self.integrator == IntegratorKind::Bdpt {
+            let flag = match (self.adaptive, self.denoise) {
+                (Some(_), _) => Some("--adaptive"),
+                (_, Some(_)) => Some("--denoise"),
+                _ => None,
+            };
+            if let Some(flag) = flag {
+                Self::command()
+                    .error(
+                        ErrorKind::ArgumentConflict,
+                        format!("'{}' cannot be used with '--integrator bdpt'", flag),
+                    )
+                    .exit();
+            }
+        }
+    }
+
+    pub fn color_pipeline(&self) -> ColorPipeline {
+        ColorPipeline {
+            exposure: self.exposure,
+            white_balance: self.white_balance,
+            tonemap: self.tonemap,
+            white_point: self.white_point,
+            transfer: self.transfer,
+        }
+    }
+
+    pub fn adaptive_sampling(&self, samples_per_pixel: u32) -> Option<AdaptiveSampling> {
+        let threshold = self.adaptive?;
+        let min_spp = self
+            .min_spp
+            .unwrap_or_else(|| DEFAULT_MIN_SPP.min(samples_per_pixel).max(2));
+        let max_spp = self
+            .max_spp
+            .unwrap_or_else(|| samples_per_pixel.saturating_mul(8))
+            .max(min_spp);
+        Some(AdaptiveSampling {
+            threshold,
+            min_spp,
+            max_spp,
+        })
+    }
+
+    // samples per pixel to render: unlimited with a time budget, unless `--spp` is given
+    pub fn target_spp(&self, samples_per_pixel: u32) -> u32 {
+        match (self.time_budget, self.spp) {
+            (Some(_), None) => u32::MAX,
+            _ => samples_per_pixel,
+        }
+    }
+
+    // samples per pixel of each render pass, or per unconverged pixel with adaptive sampling
+    pub fn pass_spp(&self, samples_per_pixel: u32) -> u32 {
+        let target = self.target_spp(samples_per_pixel);
+        match (self.pass_spp, self.adaptive_sampling(samples_per_pixel)) {
+            (Some(pass_spp), _) => pass_spp,
+            (None, Some(adaptive)) => adaptive.min_spp,
+            (None, None) if self.time_budget.is_some() => TIME_BUDGET_PASS_SPP.min(target),
+            (None, None) if self.checkpoint.is_some() => CHECKPOINT_PASS_SPP.min(target),
+            (None, None) => target,
+        }
+    }
+
+    // whether a pass as long as the last one would end after the time budget
+    pub fn out_of_time(&self, elapsed: Duration, last_pass: Duration) -> bool {
+        self.time_budget
+            .map_or(false, |time_budget| elapsed + last_pass > time_budget)
+    }
+
+    // the pixels to render, in a width x height image
+    pub fn crop(&self, width: u32, height: u32) -> Option<Tile> {
+        let [x0, y0, x1, y1] = match (self.crop, self.crop_window) {
+            (Some(crop), _) => crop,
+            (None, Some([x0, y0, x1, y1])) => {
+                let (w, h) = (width as f64, height as f64);
+                [
+                    (x0 * w).floor() as u32,
+                    (y0 * h).floor() as u32,
+                    (x1 * w).ceil() as u32,
+                    (y1 * h).ceil() as u32,
+                ]
+            }
+            (None, None) => return None,
+        };
+        if x1 > width || y1 > height {
+            Self::command()
+                .error(
+                    ErrorKind::ValueValidation,
+                    format!("the crop is outside of the {}x{} image", width, height),
+                )
+                .exit();
+        }
+        Some(Tile {
+            index: 0,
+            x0,
+            y0,
+            x1,
+            y1,
+        })
+    }
+
+    pub fn filter(&self) -> Filter {
+        Filter::new(self.filter, self.filter_radius)
+    }
+
+    pub fn radiance_clamp(&self) -> Option<RadianceClamp> {
+        self.clamp.map(|max| RadianceClamp {
+            max,
+            mode: self.clamp_mode,
+        })
+    }
+
+    pub fn threads(&self) -> usize {
+        self.threads.unwrap_or_else(|| {
+            thread::available_parallelism()
+                .map(|n| n.get())
+                .unwrap_or(1)
+        })
+    }
+
+    pub fn output_format(&self) -> OutputFormat {
+        self.format
+            .or_else(|| OutputFormat::from_path(&self.output))
+            .unwrap()
+    }
+
+    // override the presets of the scene with the given options
+    pub fn apply(&self, config: &mut SceneConfig) {
+        if let Some(aspect_ratio) = self.aspect_ratio {
+            config.aspect_ratio = aspect_ratio;
+        }
+        match (self.width, self.height) {
+            (Some(width), Some(height)) => {
+                config.width = width;
+                config.height = Some(height);
+                config.aspect_ratio = width as f64 / height as f64;
+            }
+            (None, Some(height)) => {
+                config.width = ((height as f64 * config.aspect_ratio) as u32).max(2);
+                config.height = Some(height);
+            }
+            (Some(width), None) => {
+                config.width = width;
+                config.height = None;
+            }
+            (None, None) => {
+                if self.aspect_ratio.is_some() {
+                    config.height = None;
+                }
+            }
+        }
+        if let Some(spp) = self.spp {
+            config.samples_per_pixel = spp;
+        }
+        if let Some(lookfrom) = self.lookfrom {
+            config.lookfrom = lookfrom;
+        }
+        if let Some(lookat) = self.lookat {
+            config.lookat = lookat;
+        }
+        if let Some(vup) = self.vup {
+            config.vup = vup;
+        }
+        if let Some(vfov) = self.vfov {
+            config.vfov = vfov;
+        }
+        if let Some(aperture) = self.aperture {
+            config.aperture = aperture;
+        }
+        if let Some(focus_dist) = self.focus_dist {
+            config.focus_dist = focus_dist;
+        }
+    }
+}
+
+fn parse_scene(s: &str) -> Result<String, String> {
+    if BUILTIN_SCENES.contains(&s) {
+        return Ok(s.to_owned());
+    }
+    let path = Path::new(s);
+    if !matches!(
+        path.extension().and_then(|ext| ext.to_str()),
+        Some("json" | "toml")
+    ) {
+        return Err(format!(
+            "expected a .json / .toml scene file or one of the built-in scenes: {}",
+            BUILTIN_SCENES.join(", ")
+        ));
+    }
+    if !path.is_file() {
+        return Err(format!("cannot find scene file \"{}\"", s));
+    }
+    Ok(s.to_owned())
+}
+
+fn parse_vec3(s: &str) -> Result<Vec3, String> {
+    let components = s
+        .split(',')
+        .map(|x| x.trim().parse::<f64>())
+        .collect::<Result<Vec<f64>, _>>()
+        .map_err(|e| format!("{} (expected \"x,y,z\")", e))?;
+    if components.len() != 3 {
+        return Err(format!(
+            "expected 3 comma-separated numbers \"x,y,z\", found {}",
+            components.len()
+        ));
+    }
+    Ok(Vec3::from_array(&components))
+}
+
+fn parse_rect<T: std::str::FromStr + PartialOrd + Copy>(s: &str) -> Result<[T; 4], String>
+where
+    T::Err: std::fmt::Display,
+{
+    let bounds = s
+        .split(',')
+        .map(|x| x.trim().parse::<T>())
+        .collect::<Result<Vec<T>, _>>()
+        .map_err(|e| format!("{} (expected \"x0,y0,x1,y1\")", e))?;
+    if bounds.len() != 4 {
+        return Err(format!(
+            "expected 4 comma-separated numbers \"x0,y0,x1,y1\", found {}",
+            bounds.len()
+        ));
+    }
+    if bounds[0] >= bounds[2] || bounds[1] >= bounds[3] {
+        return Err("x0 must be less than x1, and y0 less than y1".to_owned());
+    }
+    Ok([bounds[0], bounds[1], bounds[2], bounds[3]])
+}
+
+fn parse_crop(s: &str) -> Result<[u32; 4], String> {
+    parse_rect(s)
+}
+
+fn parse_crop_window(s: &str) -> Result<[f64; 4], String> {
+    let window: [f64; 4] = parse_rect(s)?;
+    if window.iter().any(|x| !(0. ..=1.).contains(x)) {
+        return Err("the window must be within [0, 1]".to_owned());
+    }
+    Ok(window)
+}
+
+// seconds, or a number of seconds, minutes or hours with the suffix s, m or h
+fn parse_duration(s: &str) -> Result<Duration, String> {
+    let s = s.trim();
+    let (number, unit) = match s.char_indices().last() {
+        Some((i, 's')) => (&s[..i], 1.),
+        Some((i, 'm')) => (&s[..i], 60.),
+        Some((i, 'h')) => (&s[..i], 3600.),
+        _ => (s, 1.),
+    };
+    let seconds = parse_positive(number)? * unit;
+    Ok(Duration::from_secs_f64(seconds))
+}
+
+fn parse_f64(s: &str) -> Result<f64, String> {
+    let x = s.parse::<f64>().map_err(|e| e.to_string())?;
+    if !x.is_finite() {
+        return Err(format!("{} is not a finite number", s));
+    }
+    Ok(x)
+}
+
+fn parse_positive(s: &str) -> Result<f64, String> {
+    let x = parse_f64(s)?;
+    if x <= 0. {
+        return Err(format!("{} is not positive", s));
+    }
+    Ok(x)
+}
+
+fn parse_non_negative(s: &str) -> Result<f64, String> {
+    let x = parse_f64(s)?;
+    if x < 0. {
+        return Err(format!("{} is negative", s));
+    }
+    Ok(x)
+}
+
+fn parse_fov(s: &str) -> Result<f64, String> {
+    let x = parse_f64(s)?;
+    if x <= 0. || x >= 180. {
+        return Err(format!("{} is not in the range (0, 180)", s));
+    }
+    Ok(x)
+}
+
+fn parse_kelvin(s: &str) -> Result<f64, String> {
+    let x = parse_f64(s)?;
+    if !(1667. ..=25000.).contains(&x) {
+        return Err(format!("{} is not in the range [1667, 25000]", s));
+    }
+    Ok(x)
+}
+
+fn parse_threads(s: &str) -> Result<usize, String> {
+    let n = s.parse::<usize>().map_err(|e| e.to_string())?;
+    if n == 0 {
+        return Err("at least one thread is required".to_owned());
+    }
+    Ok(n)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::scene::builtin_scene;
+
+    fn parse(args: &str) -> Result<Args, clap::Error> {
+        Args::try_parse_from(["raytracer"].into_iter().chain(args.split_whitespace()))
+    }
+
+    // the options override the presets of the scene, and leave the rest alone
+    #[test]
+    fn options_override_the_scene() {
+        let args =
+            parse("-s cornell_box -w 300 --spp 8 --lookfrom 1,-2,3 --vfov 60 --seed 5").unwrap();
+        assert_eq!((args.scene.as_str(), args.seed), ("cornell_box", Some(5)));
+        let mut config = builtin_scene(&args.scene).unwrap();
+        let lookat = config.lookat;
+        args.apply(&mut config);
+        assert_eq!((config.width, config.height), (300, None));
+        assert_eq!(config.samples_per_pixel, 8);
+        assert_eq!(config.lookfrom.to_array(), [1., -2., 3.]);
+        assert_eq!(config.lookat.to_array(), lookat.to_array());
+        assert_eq!(config.vfov, 60.);
+
+        let args = parse("-s cornell_box --width 300 --height 100").unwrap();
+        args.apply(&mut config);
+        assert_eq!((config.width, config.height), (300, Some(100)));
+        assert_eq!(config.aspect_ratio, 3.);
+    }
+
+    #[test]
+    fn bad_values_are_refused() {
+        for args in [
+            "-s no_such_scene",
+            "--lookfrom 1,2",
+            "--vfov 180",
+            "--spp 0",
+            "--crop 4,0,2,2",
+        ] {
+            assert!(parse(args).is_err(), "{}", args);
+        }
+    }
+
+    // a time budget renders passes of a few samples until the next would not end in time,
+    // unless `--spp` is reached first
+    #[test]
+    fn time_budget_sets_the_passes() {
+        let args = parse("--time-budget 2m").unwrap();
+        assert_eq!(args.time_budget, Some(Duration::from_secs(120)));
+        assert_eq!(args.target_spp(100), u32::MAX);
+        assert_eq!(args.pass_spp(100), TIME_BUDGET_PASS_SPP);
+        let seconds = Duration::from_secs;
+        assert!(!args.out_of_time(seconds(100), seconds(10)));
+        assert!(args.out_of_time(seconds(100), seconds(30)));
+
+        // `--spp` has been applied to the samples per pixel of the scene
+        let args = parse("--time-budget 1.5h --spp 64").unwrap();
+        assert_eq!(args.time_budget, Some(Duration::from_secs(5400)));
+        assert_eq!(args.target_spp(64), 64);
+        assert!(!parse("-s cornell_box")
+            .unwrap()
+            .out_of_time(seconds(1 << 20), seconds(1)));
+        assert!(parse("--time-budget 0").is_err());
+    }
+}
@@ -1,4 +1,10 @@
-use crate::{hittable::*, material::Material, utility::*};
+use crate::{
+    hittable::*,
+    material::Material,
+    sampler::Sampler,
+    scene::desc::{MaterialRef, ObjectDesc},
+    utility::*,
+};
 
 #[derive(Clone, Default)]
 pub struct XYRect<M: Material> {
@@ -25,6 +31,7 @@ impl<M: Material> XYRect<M> {
 
 impl<M: Material> Hittable for XYRect<M> {
     fn hit(&self, r: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord> {
+        count(Counter::PrimitiveTests);
         let t = (self.k - r.origin_borrow().z) / r.direction_borrow().z;
         if t < t_min || t > t_max {
             return None;
@@ -42,6 +49,7 @@ impl<M: Material> Hittable for XYRect<M> {
             v: (y - self.y0) / (self.y1 - self.y0),
             front_face: false,
             mat_ptr: &self.mp,
+            id: 0,
         };
         let outward_normal = Vec3::new(0., 0., 1.);
         rec.set_face_normal(r, &outward_normal);
@@ -68,14 +76,35 @@ impl<M: Material> Hittable for XYRect<M> {
         distance_squared / (cosine * area)
     }
 
-    fn random(&self, origin: &Vec3) -> Vec3 {
-        let random_point = Point3::new(
-            randrange(self.x0, self.x1),
-            randrange(self.y0, self.y1),
-            self.k,
-        );
+    fn random(&self, origin: &Vec3, sampler: &mut dyn Sampler) -> Vec3 {
+        let (s, t) = sampler.get_2d();
+        let random_point =
+            Point3::new(lerp(self.x0, self.x1, s), lerp(self.y0, self.y1, t), self.k);
         random_point - *origin
     }
+
+    fn sample_surface(&self, sampler: &mut dyn Sampler) -> Option<(Point3, Vec3)> {
+        let (s, t) = sampler.get_2d();
+        Some((
+            Point3::new(lerp(self.x0, self.x1, s), lerp(self.y0, self.y1, t), self.k),
+            Vec3::new(0., 0., 1.),
+        ))
+    }
+
+    fn area(&self) -> f64 {
+        (self.x1 - self.x0) * (self.y1 - self.y0)
+    }
+
+    fn describe(&self) -> ObjectDesc {
+        ObjectDesc::XyRect {
+            x0: self.x0,
+            x1: self.x1,
+            y0: self.y0,
+            y1: self.y1,
+            k: self.k,
+            material: MaterialRef::Inline(Box::new(self.mp.describe())),
+        }
+    }
 }
 
 #[derive(Clone, Default)]
@@ -103,6 +132,7 @@ impl<M: Material> XZRect<M> {
 
 impl<M: Material> Hittable for XZRect<M> {
     fn hit(&self, r: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord> {
+        count(Counter::PrimitiveTests);
         let t = (self.k - r.origin_borrow().y) / r.direction_borrow().y;
         if t < t_min || t > t_max {
             return None;
@@ -120,6 +150,7 @@ impl<M: Material> Hittable for XZRect<M> {
             v: (z - self.z0) / (self.z1 - self.z0),
             front_face: false,
             mat_ptr: &self.mp,
+            id: 0,
         };
         let outward_normal = Vec3::new(0., 1., 0.);
         rec.set_face_normal(r, &outward_normal);
@@ -146,14 +177,35 @@ impl<M: Material> Hittable for XZRect<M> {
         distance_squared / (cosine * area)
     }
 
-    fn random(&self, origin: &Vec3) -> Vec3 {
-        let random_point = Point3::new(
-            randrange(self.x0, self.x1),
-            self.k,
-            randrange(self.z0, self.z1),
-        );
+    fn random(&self, origin: &Vec3, sampler: &mut dyn Sampler) -> Vec3 {
+        let (s, t) = sampler.get_2d();
+        let random_point =
+            Point3::new(lerp(self.x0, self.x1, s), self.k, lerp(self.z0, self.z1, t));
         random_point - *origin
     }
+
+    fn sample_surface(&self, sampler: &mut dyn Sampler) -> Option<(Point3, Vec3)> {
+        let (s, t) = sampler.get_2d();
+        Some((
+            Point3::new(lerp(self.x0, self.x1, s), self.k, lerp(self.z0, self.z1, t)),
+            Vec3::new(0., 1., 0.),
+        ))
+    }
+
+    fn area(&self) -> f64 {
+        (self.x1 - self.x0) * (self.z1 - self.z0)
+    }
+
+    fn describe(&self) -> ObjectDesc {
+        ObjectDesc::XzRect {
+            x0: self.x0,
+            x1: self.x1,
+            z0: self.z0,
+            z1: self.z1,
+            k: self.k,
+            material: MaterialRef::Inline(Box::new(self.mp.describe())),
+        }
+    }
 }
 
 #[derive(Clone, Default)]
@@ -181,6 +233,7 @@ impl<M: Material> YZRect<M> {
 
 impl<M: Material> Hittable for YZRect<M> {
     fn hit(&self, r: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord> {
+        count(Counter::PrimitiveTests);
         let t = (self.k - r.origin_borrow().x) / r.direction_borrow().x;
         if t < t_min || t > t_max {
             return None;
@@ -198,6 +251,7 @@ impl<M: Material> Hittable for YZRect<M> {
             v: (z - self.z0) / (self.z1 - self.z0),
             front_face: false,
             mat_ptr: &self.mp,
+            id: 0,
         };
         let outward_normal = Vec3::new(1., 0., 0.);
         rec.set_face_normal(r, &outward_normal);
@@ -224,12 +278,33 @@ impl<M: Material> Hittable for YZRect<M> {
         distance_squared / (cosine * area)
     }
 
-    fn random(&self, origin: &Vec3) -> Vec3 {
-        let random_point = Point3::new(
-            self.k,
-            randrange(self.y0, self.y1),
-            randrange(self.z0, self.z1),
-        );
+    fn random(&self, origin: &Vec3, sampler: &mut dyn Sampler) -> Vec3 {
+        let (s, t) = sampler.get_2d();
+        let random_point =
+            Point3::new(self.k, lerp(self.y0, self.y1, s), lerp(self.z0, self.z1, t));
         random_point - *origin
     }
+
+    fn sample_surface(&self, sampler: &mut dyn Sampler) -> Option<(Point3, Vec3)> {
+        let (s, t) = sampler.get_2d();
+        Some((
+            Point3::new(self.k, lerp(self.y0, self.y1, s), lerp(self.z0, self.z1, t)),
+            Vec3::new(1., 0., 0.),
+        ))
+    }
+
+    fn area(&self) -> f64 {
+        (self.y1 - self.y0) * (self.z1 - self.z0)
+    }
+
+    fn describe(&self) -> ObjectDesc {
+        ObjectDesc::YzRect {
+            y0: self.y0,
+            y1: self.y1,
+            z0: self.z0,
+            z1: self.z1,
+            k: self.k,
+            material: MaterialRef::Inline(Box::new(self.mp.describe())),
+        }
+    }
 }
@@ -30,6 +30,7 @@ impl AABB {
     }
 
     pub fn hit(&self, r: &Ray, t_min: f64, t_max: f64) -> bool {
+        count(Counter::AabbTests);
         let mut t_min = t_min;
         let mut t_max = t_max;
         for a in 0..3 {
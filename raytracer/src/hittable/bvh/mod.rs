@@ -1,6 +1,6 @@
 pub mod aabb;
 
-use crate::{hittable::*, utility::*};
+use crate::{hittable::*, scene::desc::ObjectDesc, utility::*};
 use std::cmp::Ordering;
 
 #[derive(Default)]
@@ -8,12 +8,22 @@ pub struct BVHNode {
     left: Option<Box<dyn Hittable>>,
     right: Option<Box<dyn Hittable>>,
     box_: AABB,
+    media: bool, //if any leaf has one, so the rest of the tree is skipped when looking for them
 }
 
 impl BVHNode {
-    pub fn new(mut list: HittableList, time0: f64, time1: f64) -> Self {
+    pub fn new(list: HittableList, time0: f64, time1: f64) -> Self {
+        let start = std::time::Instant::now();
         let len = list.objects.len();
-        Self::build(&mut list.objects, 0, len, time0, time1)
+        let mut objects = list
+            .objects
+            .into_iter()
+            .enumerate()
+            .map(|(index, object)| Box::new(Leaf { index, object }) as Box<dyn Hittable>)
+            .collect();
+        let node = Self::build(&mut objects, 0, len, time0, time1);
+        add(Counter::BvhBuildNanos, start.elapsed().as_nanos() as u64);
+        node
     }
 
     pub fn construct(
@@ -50,7 +60,16 @@ impl BVHNode {
             box_right
         };
 
-        BVHNode { left, right, box_ }
+        let media = left
+            .iter()
+            .chain(right.iter())
+            .any(|child| child.has_media());
+        BVHNode {
+            left,
+            right,
+            box_,
+            media,
+        }
     }
 
     pub fn build(
@@ -122,6 +141,7 @@ impl BVHNode {
 
 impl Hittable for BVHNode {
     fn hit(&self, r: &Ray, t_min: f64, mut t_max: f64) -> Option<HitRecord> {
+        count(Counter::BvhVisits);
         if !self.box_.hit(r, t_min, t_max) {
             return None;
         }
@@ -147,4 +167,59 @@ impl Hittable for BVHNode {
         *output_box = self.box_;
         true
     }
+
+    fn media<'a>(&'a self, r: &Ray, t_min: f64, t_max: f64, segments: &mut Vec<MediumSegment<'a>>) {
+        if !self.media || !self.box_.hit(r, t_min, t_max) {
+            return;
+        }
+        for child in self.left.iter().chain(self.right.iter()) {
+            child.media(r, t_min, t_max, segments);
+        }
+    }
+
+    fn has_media(&self) -> bool {
+        self.media
+    }
+
+    fn describe(&self) -> ObjectDesc {
+        // the leaves in tree order; nested nodes are flattened
+        let mut objects = Vec::new();
+        for child in self.left.iter().chain(self.right.iter()) {
+            match child.describe() {
+                ObjectDesc::Bvh { objects: leaves } => objects.extend(leaves),
+                desc => objects.push(desc),
+            }
+        }
+        ObjectDesc::Bvh { objects }
+    }
+}
+
+// an object of the tree, which names its hits by its index in the list the tree was built from,
+// so they do not depend on the shape of the tree
+struct Leaf {
+    index: usize,
+    object: Box<dyn Hittable>,
+}
+
+impl Hittable for Leaf {
+    fn hit(&self, r: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord<'_>> {
+        let rec = self.object.hit(r, t_min, t_max)?;
+        Some(rec.within(self.index))
+    }
+
+    fn bounding_box(&self, time0: f64, time1: f64, output_box: &mut AABB) -> bool {
+        self.object.bounding_box(time0, time1, output_box)
+    }
+
+    fn media<'a>(&'a self, r: &Ray, t_min: f64, t_max: f64, segments: &mut Vec<MediumSegment<'a>>) {
+        self.object.media(r, t_min, t_max, segments)
+    }
+
+    fn has_media(&self) -> bool {
+        self.object.has_media()
+    }
+
+    fn describe(&self) -> ObjectDesc {
+        self.object.describe()
+    }
 }
@@ -1,15 +1,125 @@
 use crate::{
     hittable::bvh::aabb::AABB,
     hittable::{HitRecord, Hittable},
-    material::{Isotropic, Material},
+    material::{Isotropic, Material, PhaseFunction, Volumetric},
+    sampler::Sampler,
+    scene::desc::ObjectDesc,
     texture::{SolidColor, Texture},
     utility::*,
 };
 
+// crossings of a boundary looked for along a ray, enough for any shape a medium is given
+const MAX_CROSSINGS: usize = 16;
+
+// a participating medium: light travelling through it collides with it at a rate of `density`
+// per unit length, and the phase function scatters a share (its albedo) of what collides; the
+// rest is absorbed
+pub trait Medium: Send + Sync {
+    fn density(&self, p: &Point3) -> f64;
+    // bound of the density: the rate of the tentative collisions of delta and ratio tracking
+    fn majorant(&self) -> f64;
+    // scatters the light at a collision; it gets a hit record with only the point and a normal
+    // facing the ray
+    fn phase(&self) -> &dyn Material;
+
+    // where, from t0 to t1 along `r`, the light first collides with the medium. delta tracking
+    // (Woodcock): tentative collisions come at the rate of the majorant, and each is real with
+    // probability density / majorant
+    fn sample_collision(
+        &self,
+        r: &Ray,
+        t0: f64,
+        t1: f64,
+        sampler: &mut dyn Sampler,
+    ) -> Option<f64> {
+        let rate = self.majorant() * r.direction_borrow().length(); //per unit of t
+        if rate <= 0. {
+            return None;
+        }
+        let mut t = t0;
+        loop {
+            t -= (1. - sampler.get_1d()).ln() / rate;
+            if t >= t1 {
+                return None;
+            }
+            let density = self.density(&r.at(t));
+            debug_assert!(density <= self.majorant(), "density above the majorant");
+            if sampler.get_1d() * self.majorant() < density {
+                return Some(t);
+            }
+        }
+    }
+
+    // share of the light that crosses from t0 to t1 without colliding. ratio tracking: the same
+    // tentative collisions, each weighing by its probability of not being real
+    fn transmittance(&self, r: &Ray, t0: f64, t1: f64, sampler: &mut dyn Sampler) -> f64 {
+        let rate = self.majorant() * r.direction_borrow().length();
+        if rate <= 0. {
+            return 1.;
+        }
+        let mut transmittance = 1.;
+        let mut t = t0;
+        loop {
+            t -= (1. - sampler.get_1d()).ln() / rate;
+            if t >= t1 || transmittance <= 0. {
+                return transmittance;
+            }
+            let density = self.density(&r.at(t));
+            debug_assert!(density <= self.majorant(), "density above the majorant");
+            transmittance *= 1. - density / self.majorant();
+        }
+    }
+}
+
+// the part [t0, t1] of a ray inside `medium`. transforms keep t, so the media are tracked along
+// the ray of the scene, and their densities are looked up there like textures
+#[derive(Clone, Copy)]
+pub struct MediumSegment<'a> {
+    pub t0: f64,
+    pub t1: f64,
+    pub medium: &'a dyn Medium,
+}
+
+// the segments of `r` inside a closed boundary. the whole line is followed from behind the
+// origin, so the crossings alternate between entering and leaving whatever side the normals of
+// the boundary face, and a ray that starts inside is inside from its origin
+fn boundary_media<'a>(
+    boundary: &dyn Hittable,
+    medium: &'a dyn Medium,
+    r: &Ray,
+    t_min: f64,
+    t_max: f64,
+    segments: &mut Vec<MediumSegment<'a>>,
+) {
+    let mut t = -INFINITY;
+    let mut entered = None;
+    for _ in 0..MAX_CROSSINGS {
+        let rec = match boundary.hit(r, t, INFINITY) {
+            Some(rec) => rec,
+            None => break,
+        };
+        match entered.take() {
+            None => entered = Some(rec.t),
+            Some(t0) => {
+                let (t0, t1) = (f64::max(t0, t_min), rec.t.min(t_max));
+                if t0 < t1 {
+                    segments.push(MediumSegment { t0, t1, medium });
+                }
+            }
+        }
+        if rec.t >= t_max {
+            break;
+        }
+        t = rec.t + 0.0001;
+    }
+}
+
+// a medium of uniform density filling a closed boundary
 #[derive(Clone, Default)]
 pub struct ConstantMedium<H: Hittable, M: Material> {
     pub boundary: H,
     pub phase_function: M,
+    pub density: f64,
     pub neg_inv_density: f64,
 }
 
@@ -17,6 +127,7 @@ impl<H: Hittable, T: Texture> ConstantMedium<H, Isotropic<T>> {
     pub fn new(b: H, d: f64, a: T) -> Self {
         Self {
             boundary: b,
+            density: d,
             neg_inv_density: -1. / d,
             phase_function: Isotropic::new(a),
         }
@@ -27,50 +138,201 @@ impl<H: Hittable> ConstantMedium<H, Isotropic<SolidColor>> {
     pub fn new_from_color(b: H, d: f64, c: &Color) -> Self {
         Self {
             boundary: b,
+            density: d,
             neg_inv_density: -1. / d,
             phase_function: Isotropic::new_from_color(c),
         }
     }
 }
 
-impl<H: Hittable, T: Texture> Hittable for ConstantMedium<H, Isotropic<T>> {
-    fn hit(&self, r: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord> {
-        let op1 = self.boundary.hit(r, -INFINITY, INFINITY);
-        op1.as_ref()?;
-        let mut rec1 = op1.unwrap();
+impl<H: Hittable, T: Texture, P: PhaseFunction> ConstantMedium<H, Volumetric<T, P>> {
+    pub fn with_phase(b: H, d: f64, a: T, phase: P) -> Self {
+        Self {
+            boundary: b,
+            density: d,
+            neg_inv_density: -1. / d,
+            phase_function: Volumetric::with_phase(a, phase),
+        }
+    }
+}
+
+// the density is its own majorant, so both trackings take a single step, in closed form
+impl<H: Hittable, T: Texture, P: PhaseFunction> Medium for ConstantMedium<H, Volumetric<T, P>> {
+    fn density(&self, _p: &Point3) -> f64 {
+        self.density
+    }
 
-        let op2 = self.boundary.hit(r, rec1.t + 0.0001, INFINITY);
-        op2.as_ref()?;
-        let mut rec2 = op2.unwrap();
+    fn majorant(&self) -> f64 {
+        self.density
+    }
 
-        rec1.t = rec1.t.max(t_min);
-        rec2.t = rec2.t.min(t_max);
+    fn phase(&self) -> &dyn Material {
+        &self.phase_function
+    }
 
-        if rec1.t >= rec2.t {
-            return None;
+    fn sample_collision(
+        &self,
+        r: &Ray,
+        t0: f64,
+        t1: f64,
+        sampler: &mut dyn Sampler,
+    ) -> Option<f64> {
+        let hit_distance = self.neg_inv_density * (1. - sampler.get_1d()).ln();
+        let t = t0 + hit_distance / r.direction_borrow().length();
+        if t < t1 {
+            Some(t)
+        } else {
+            None
         }
-        rec1.t = rec1.t.max(0.);
+    }
 
-        let ray_length = r.direction_borrow().length();
-        let distance_inside_boundary = (rec2.t - rec1.t) * ray_length;
-        let hit_distance = self.neg_inv_density * random().ln();
+    fn transmittance(&self, r: &Ray, t0: f64, t1: f64, _sampler: &mut dyn Sampler) -> f64 {
+        (-self.density * (t1 - t0) * r.direction_borrow().length()).exp()
+    }
+}
 
-        if hit_distance > distance_inside_boundary {
-            return None;
+impl<H: Hittable, T: Texture, P: PhaseFunction> Hittable for ConstantMedium<H, Volumetric<T, P>> {
+    fn hit(&self, _r: &Ray, _t_min: f64, _t_max: f64) -> Option<HitRecord<'_>> {
+        None
+    }
+
+    fn bounding_box(&self, time0: f64, time1: f64, output_box: &mut AABB) -> bool {
+        self.boundary.bounding_box(time0, time1, output_box)
+    }
+
+    fn media<'a>(&'a self, r: &Ray, t_min: f64, t_max: f64, segments: &mut Vec<MediumSegment<'a>>) {
+        boundary_media(&self.boundary, self, r, t_min, t_max, segments);
+    }
+
+    fn has_media(&self) -> bool {
+        true
+    }
+
+    fn describe(&self) -> ObjectDesc {
+        ObjectDesc::ConstantMedium {
+            boundary: Box::new(self.boundary.describe()),
+            density: self.density,
+            albedo: self.phase_function.albedo.describe(),
+            phase: self.phase_function.phase.describe(),
         }
+    }
+}
+
+// a medium whose density varies in space: `density` times the mean of the channels of a texture,
+// such as a voxel grid or turbulence. the texture bounds the majorant of the tracking
+#[derive(Clone)]
+pub struct HeterogeneousMedium<H: Hittable, D: Texture, M: Material> {
+    pub boundary: H,
+    pub density_field: D,
+    pub density: f64, //where the field is 1
+    pub phase_function: M,
+}
 
-        let mut rec = HitRecord::default();
-        rec.t = rec1.t + hit_distance / ray_length;
-        rec.p = r.at(rec.t);
+impl<H: Hittable, D: Texture, T: Texture> HeterogeneousMedium<H, D, Isotropic<T>> {
+    pub fn new(b: H, d: f64, field: D, a: T) -> Self {
+        Self::with_phase(b, d, field, a, Default::default())
+    }
+}
+
+impl<H: Hittable, D: Texture, T: Texture, P: PhaseFunction>
+    HeterogeneousMedium<H, D, Volumetric<T, P>>
+{
+    pub fn with_phase(b: H, d: f64, field: D, a: T, phase: P) -> Self {
+        Self {
+            boundary: b,
+            density_field: field,
+            density: d,
+            phase_function: Volumetric::with_phase(a, phase),
+        }
+    }
+}
 
-        rec.normal = Vec3::new(1., 0., 0.); // arbitrary
-        rec.front_face = true; // also arbitrary
-        rec.mat_ptr = &self.phase_function;
+impl<H: Hittable, D: Texture, T: Texture, P: PhaseFunction> Medium
+    for HeterogeneousMedium<H, D, Volumetric<T, P>>
+{
+    fn density(&self, p: &Point3) -> f64 {
+        let field = self.density_field.value(0., 0., p);
+        self.density * (field.x + field.y + field.z) / 3.
+    }
 
-        Some(rec)
+    fn majorant(&self) -> f64 {
+        self.density * self.density_field.max_value()
+    }
+
+    fn phase(&self) -> &dyn Material {
+        &self.phase_function
+    }
+}
+
+impl<H: Hittable, D: Texture, T: Texture, P: PhaseFunction> Hittable
+    for HeterogeneousMedium<H, D, Volumetric<T, P>>
+{
+    fn hit(&self, _r: &Ray, _t_min: f64, _t_max: f64) -> Option<HitRecord<'_>> {
+        None
     }
 
     fn bounding_box(&self, time0: f64, time1: f64, output_box: &mut AABB) -> bool {
         self.boundary.bounding_box(time0, time1, output_box)
     }
+
+    fn media<'a>(&'a self, r: &Ray, t_min: f64, t_max: f64, segments: &mut Vec<MediumSegment<'a>>) {
+        boundary_media(&self.boundary, self, r, t_min, t_max, segments);
+    }
+
+    fn has_media(&self) -> bool {
+        true
+    }
+
+    fn describe(&self) -> ObjectDesc {
+        ObjectDesc::HeterogeneousMedium {
+            boundary: Box::new(self.boundary.describe()),
+            density: self.density,
+            density_field: self.density_field.describe(),
+            albedo: self.phase_function.albedo.describe(),
+            phase: self.phase_function.phase.describe(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{hittable::sphere::Sphere, material::Lambertian, sampler::IndependentSampler};
+
+    fn medium() -> impl Medium {
+        let gray = Color::new(0.5, 0.5, 0.5);
+        HeterogeneousMedium::new(
+            Sphere::new(&Point3::default(), 1., Lambertian::new_from_color(&gray)),
+            2.,
+            SolidColor::new(&Color::new(0.25, 0.5, 0.75)),
+            SolidColor::new(&gray),
+        )
+    }
+
+    // both trackings converge to the closed form of a uniform density, and follow the sampler
+    #[test]
+    fn tracking_matches_beer_lambert() {
+        let medium = medium();
+        let r = Ray::new(&Point3::new(0., 0., -1.), &Vec3::new(0., 0., 2.), 0.);
+        let expected = (-medium.density(&Point3::default()) * 2.).exp();
+        let n = 20000;
+        let mut sampler = IndependentSampler::new(7);
+        let (mut transmitted, mut escaped) = (0., 0);
+        for index in 0..n {
+            sampler.start_sample(0, index);
+            transmitted += medium.transmittance(&r, 0., 1., &mut sampler);
+            escaped += medium.sample_collision(&r, 0., 1., &mut sampler).is_none() as u32;
+        }
+        assert!((transmitted / n as f64 - expected).abs() < 0.01);
+        assert!((escaped as f64 / n as f64 - expected).abs() < 0.01);
+
+        let mut a = IndependentSampler::new(3);
+        let mut b = IndependentSampler::new(3);
+        a.start_sample(5, 1);
+        b.start_sample(5, 1);
+        assert_eq!(
+            medium.sample_collision(&r, 0., 1., &mut a),
+            medium.sample_collision(&r, 0., 1., &mut b)
+        );
+    }
 }
@@ -5,8 +5,9 @@ pub mod rect_box;
 pub mod sphere;
 pub mod triangle;
 
-use crate::{material::*, utility::*};
+use crate::{material::*, sampler::Sampler, scene::desc::ObjectDesc, utility::*};
 pub use bvh::aabb::*;
+pub use medium::{Medium, MediumSegment};
 
 #[derive(Clone)]
 pub struct HitRecord<'a> {
@@ -17,6 +18,8 @@ pub struct HitRecord<'a> {
     pub v: f64,           //surface coordinates
     pub front_face: bool, //if ray hit to the front face
     pub mat_ptr: &'a dyn Material,
+    // the primitive hit: its indices in the lists and trees it is in, hashed on the way up
+    pub id: u64,
 }
 
 impl<'a> HitRecord<'a> {
@@ -29,9 +32,16 @@ impl<'a> HitRecord<'a> {
             v: 0.,
             front_face: false,
             mat_ptr,
+            id: 0,
         }
     }
 
+    // the hit is on the `index`-th object of a list or tree
+    pub fn within(mut self, index: usize) -> Self {
+        self.id = splitmix64(self.id ^ index as u64);
+        self
+    }
+
     pub fn set_face_normal(&mut self, r: &Ray, outward_normal: &Vec3) {
         self.front_face = dot(&r.dir, outward_normal) < 0.;
         self.normal = if self.front_face {
@@ -54,12 +64,68 @@ pub trait Hittable: Send + Sync {
     fn pdf_value(&self, _o: &Point3, _v: &Vec3) -> f64 {
         0.25 / PI
     }
-    fn random(&self, _o: &Vec3) -> Vec3 {
-        Vec3::random().unit()
+    fn random(&self, _o: &Vec3, sampler: &mut dyn Sampler) -> Vec3 {
+        let (x, y) = sampler.get_2d();
+        Vec3::new(x, y, sampler.get_1d()).unit()
     }
     fn empty(&self) -> bool {
         false
     }
+    // a point of the surface, uniform by area, with its outward normal: where the light paths of
+    // the bidirectional integrator start; None for objects that cannot be sampled that way
+    fn sample_surface(&self, _sampler: &mut dyn Sampler) -> Option<(Point3, Vec3)> {
+        None
+    }
+    fn area(&self) -> f64 {
+        0.
+    }
+    // the parts of `r` within [t_min, t_max] inside a medium; media are not hit, rays cross them
+    fn media<'a>(
+        &'a self,
+        _r: &Ray,
+        _t_min: f64,
+        _t_max: f64,
+        _segments: &mut Vec<MediumSegment<'a>>,
+    ) {
+    }
+    fn has_media(&self) -> bool {
+        false
+    }
+    // description in the scene file format, see scene::desc
+    fn describe(&self) -> ObjectDesc;
+}
+
+impl<H: Hittable + ?Sized> Hittable for Box<H> {
+    fn hit(&self, r: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord> {
+        self.as_ref().hit(r, t_min, t_max)
+    }
+    fn bounding_box(&self, time0: f64, time1: f64, output_box: &mut AABB) -> bool {
+        self.as_ref().bounding_box(time0, time1, output_box)
+    }
+    fn pdf_value(&self, o: &Point3, v: &Vec3) -> f64 {
+        self.as_ref().pdf_value(o, v)
+    }
+    fn random(&self, o: &Vec3, sampler: &mut dyn Sampler) -> Vec3 {
+        self.as_ref().random(o, sampler)
+    }
+    fn empty(&self) -> bool {
+        self.as_ref().empty()
+    }
+    fn sample_surface(&self, sampler: &mut dyn Sampler) -> Option<(Point3, Vec3)> {
+        self.as_ref().sample_surface(sampler)
+    }
+    fn area(&self) -> f64 {
+        self.as_ref().area()
+    }
+    fn media<'a>(&'a self, r: &Ray, t_min: f64, t_max: f64, segments: &mut Vec<MediumSegment<'a>>) {
+        self.as_ref().media(r, t_min, t_max, segments)
+    }
+    fn has_media(&self) -> bool {
+        self.as_ref().has_media()
+    }
+    fn describe(&self) -> ObjectDesc {
+        self.as_ref().describe()
+    }
 }
 
 pub struct HittableList {
@@ -82,23 +148,44 @@ impl HittableList {
     pub fn size(&self) -> usize {
         self.objects.len()
     }
-}
 
-impl Hittable for HittableList {
-    fn hit(&self, r: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord> {
+    // the closest hit with the index of the object hit
+    pub fn hit_object(&self, r: &Ray, t_min: f64, t_max: f64) -> Option<(usize, HitRecord<'_>)> {
         let mut rec = None;
         let mut closest_so_far = t_max;
 
-        for object in self.objects.iter() {
+        for (index, object) in self.objects.iter().enumerate() {
             if let Some(temp_rec) = object.hit(r, t_min, closest_so_far) {
                 closest_so_far = temp_rec.t;
-                rec = Some(temp_rec);
+                rec = Some((index, temp_rec.within(index)));
             }
         }
 
         rec
     }
 
+    // the media along `r` with the index of the object each belongs to
+    pub fn media_objects(
+        &self,
+        r: &Ray,
+        t_min: f64,
+        t_max: f64,
+    ) -> Vec<(usize, MediumSegment<'_>)> {
+        let mut media = Vec::new();
+        let mut segments = Vec::new();
+        for (index, object) in self.objects.iter().enumerate() {
+            object.media(r, t_min, t_max, &mut segments);
+            media.extend(segments.drain(..).map(|segment| (index, segment)));
+        }
+        media
+    }
+}
+
+impl Hittable for HittableList {
+    fn hit(&self, r: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord> {
+        self.hit_object(r, t_min, t_max).map(|(_, rec)| rec)
+    }
+
     fn bounding_box(&self, time0: f64, time1: f64, output_box: &mut AABB) -> bool {
         if self.objects.is_empty() {
             return false;
@@ -129,9 +216,47 @@ impl Hittable for HittableList {
         sum / self.size() as f64
     }
 
-    fn random(&self, o: &Point3) -> Vec3 {
+    fn random(&self, o: &Point3, sampler: &mut dyn Sampler) -> Vec3 {
         let int_size = self.size();
-        self.objects[randint(0, int_size as i32) as usize].random(o)
+        let i = ((sampler.get_1d() * int_size as f64) as usize).min(int_size - 1);
+        self.objects[i].random(o, sampler)
+    }
+
+    // an object picked by its share of the area
+    fn sample_surface(&self, sampler: &mut dyn Sampler) -> Option<(Point3, Vec3)> {
+        let mut pick = sampler.get_1d() * self.area();
+        for object in &self.objects {
+            let area = object.area();
+            if pick < area {
+                return object.sample_surface(sampler);
+            }
+            pick -= area;
+        }
+        None
+    }
+
+    fn area(&self) -> f64 {
+        self.objects.iter().map(|object| object.area()).sum()
+    }
+
+    fn media<'a>(&'a self, r: &Ray, t_min: f64, t_max: f64, segments: &mut Vec<MediumSegment<'a>>) {
+        for object in &self.objects {
+            object.media(r, t_min, t_max, segments);
+        }
+    }
+
+    fn has_media(&self) -> bool {
+        self.objects.iter().any(|object| object.has_media())
+    }
+
+    fn describe(&self) -> ObjectDesc {
+        ObjectDesc::List {
+            objects: self
+                .objects
+                .iter()
+                .map(|object| object.describe())
+                .collect(),
+        }
     }
 }
 
@@ -177,8 +302,33 @@ impl<H: Hittable> Hittable for Translate<H> {
         self.ptr.pdf_value(&(*o - self.offset), v)
     }
 
-    fn random(&self, o: &Vec3) -> Vec3 {
-        self.ptr.random(&(*o - self.offset))
+    fn random(&self, o: &Vec3, sampler: &mut dyn Sampler) -> Vec3 {
+        self.ptr.random(&(*o - self.offset), sampler)
+    }
+
+    fn sample_surface(&self, sampler: &mut dyn Sampler) -> Option<(Point3, Vec3)> {
+        let (p, normal) = self.ptr.sample_surface(sampler)?;
+        Some((p + self.offset, normal))
+    }
+
+    fn area(&self) -> f64 {
+        self.ptr.area()
+    }
+
+    fn media<'a>(&'a self, r: &Ray, t_min: f64, t_max: f64, segments: &mut Vec<MediumSegment<'a>>) {
+        let moved_r = Ray::new(&(r.origin() - self.offset), r.direction_borrow(), r.time());
+        self.ptr.media(&moved_r, t_min, t_max, segments)
+    }
+
+    fn has_media(&self) -> bool {
+        self.ptr.has_media()
+    }
+
+    fn describe(&self) -> ObjectDesc {
+        ObjectDesc::Translate {
+            offset: self.offset.to_array(),
+            object: Box::new(self.ptr.describe()),
+        }
     }
 }
 
@@ -194,6 +344,7 @@ impl<H: Hittable> Translate<H> {
 #[derive(Clone, Default)]
 pub struct RotateY<H: Hittable> {
     pub ptr: H,
+    pub angle: f64, //degrees
     pub sin_theta: f64,
     pub cos_theta: f64,
     pub hasbox: bool,
@@ -245,11 +396,45 @@ impl<H: Hittable> Hittable for RotateY<H> {
         self.ptr.pdf_value(&rotated_o, &rotated_v)
     }
 
-    fn random(&self, o: &Vec3) -> Vec3 {
+    fn random(&self, o: &Vec3, sampler: &mut dyn Sampler) -> Vec3 {
         let rotated_o = rotate_vec_y(o, self.sin_theta, self.cos_theta);
-        let rotated_rand = self.ptr.random(&rotated_o);
+        let rotated_rand = self.ptr.random(&rotated_o, sampler);
         rotate_vec_y(&rotated_rand, -self.sin_theta, self.cos_theta)
     }
+
+    fn sample_surface(&self, sampler: &mut dyn Sampler) -> Option<(Point3, Vec3)> {
+        let (p, normal) = self.ptr.sample_surface(sampler)?;
+        Some((
+            rotate_vec_y(&p, -self.sin_theta, self.cos_theta),
+            rotate_vec_y(&normal, -self.sin_theta, self.cos_theta),
+        ))
+    }
+
+    fn area(&self) -> f64 {
+        self.ptr.area()
+    }
+
+    fn media<'a>(&'a self, r: &Ray, t_min: f64, t_max: f64, segments: &mut Vec<MediumSegment<'a>>) {
+        let origin = rotate_vec_y(r.origin_borrow(), self.sin_theta, self.cos_theta);
+        let direction = rotate_vec_y(r.direction_borrow(), self.sin_theta, self.cos_theta);
+        self.ptr.media(
+            &Ray::new(&origin, &direction, r.time()),
+            t_min,
+            t_max,
+            segments,
+        )
+    }
+
+    fn has_media(&self) -> bool {
+        self.ptr.has_media()
+    }
+
+    fn describe(&self) -> ObjectDesc {
+        ObjectDesc::RotateY {
+            angle: self.angle,
+            object: Box::new(self.ptr.describe()),
+        }
+    }
 }
 
 impl<H: Hittable> RotateY<H> {
@@ -283,6 +468,7 @@ impl<H: Hittable> RotateY<H> {
         bbox = AABB::new(&min, &max);
         Self {
             ptr: p,
+            angle,
             sin_theta,
             cos_theta,
             hasbox,
@@ -294,6 +480,7 @@ impl<H: Hittable> RotateY<H> {
 #[derive(Clone, Default)]
 pub struct RotateX<H: Hittable> {
     pub ptr: H,
+    pub angle: f64, //degrees
     pub sin_theta: f64,
     pub cos_theta: f64,
     pub hasbox: bool,
@@ -328,11 +515,45 @@ impl<H: Hittable> Hittable for RotateX<H> {
         self.ptr.pdf_value(&rotated_o, &rotated_v)
     }
 
-    fn random(&self, o: &Vec3) -> Vec3 {
+    fn random(&self, o: &Vec3, sampler: &mut dyn Sampler) -> Vec3 {
         let rotated_o = rotate_vec_x(o, self.sin_theta, self.cos_theta);
-        let rotated_rand = self.ptr.random(&rotated_o);
+        let rotated_rand = self.ptr.random(&rotated_o, sampler);
         rotate_vec_x(&rotated_rand, -self.sin_theta, self.cos_theta)
     }
+
+    fn sample_surface(&self, sampler: &mut dyn Sampler) -> Option<(Point3, Vec3)> {
+        let (p, normal) = self.ptr.sample_surface(sampler)?;
+        Some((
+            rotate_vec_x(&p, -self.sin_theta, self.cos_theta),
+            rotate_vec_x(&normal, -self.sin_theta, self.cos_theta),
+        ))
+    }
+
+    fn area(&self) -> f64 {
+        self.ptr.area()
+    }
+
+    fn media<'a>(&'a self, r: &Ray, t_min: f64, t_max: f64, segments: &mut Vec<MediumSegment<'a>>) {
+        let origin = rotate_vec_x(r.origin_borrow(), self.sin_theta, self.cos_theta);
+        let direction = rotate_vec_x(r.direction_borrow(), self.sin_theta, self.cos_theta);
+        self.ptr.media(
+            &Ray::new(&origin, &direction, r.time()),
+            t_min,
+            t_max,
+            segments,
+        )
+    }
+
+    fn has_media(&self) -> bool {
+        self.ptr.has_media()
+    }
+
+    fn describe(&self) -> ObjectDesc {
+        ObjectDesc::RotateX {
+            angle: self.angle,
+            object: Box::new(self.ptr.describe()),
+        }
+    }
 }
 
 impl<H: Hittable> RotateX<H> {
@@ -365,6 +586,7 @@ impl<H: Hittable> RotateX<H> {
         bbox = AABB::new(&min, &max);
         Self {
             ptr: p,
+            angle,
             sin_theta,
             cos_theta,
             hasbox,
@@ -376,6 +598,7 @@ impl<H: Hittable> RotateX<H> {
 #[derive(Clone, Default)]
 pub struct RotateZ<H: Hittable> {
     pub ptr: H,
+    pub angle: f64, //degrees
     pub sin_theta: f64,
     pub cos_theta: f64,
     pub hasbox: bool,
@@ -411,11 +634,45 @@ impl<H: Hittable> Hittable for RotateZ<H> {
         self.ptr.pdf_value(&rotated_o, &rotated_v)
     }
 
-    fn random(&self, o: &Vec3) -> Vec3 {
+    fn random(&self, o: &Vec3, sampler: &mut dyn Sampler) -> Vec3 {
         let rotated_o = rotate_vec_z(o, self.sin_theta, self.cos_theta);
-        let rotated_rand = self.ptr.random(&rotated_o);
+        let rotated_rand = self.ptr.random(&rotated_o, sampler);
         rotate_vec_z(&rotated_rand, -self.sin_theta, self.cos_theta)
     }
+
+    fn sample_surface(&self, sampler: &mut dyn Sampler) -> Option<(Point3, Vec3)> {
+        let (p, normal) = self.ptr.sample_surface(sampler)?;
+        Some((
+            rotate_vec_z(&p, -self.sin_theta, self.cos_theta),
+            rotate_vec_z(&normal, -self.sin_theta, self.cos_theta),
+        ))
+    }
+
+    fn area(&self) -> f64 {
+        self.ptr.area()
+    }
+
+    fn media<'a>(&'a self, r: &Ray, t_min: f64, t_max: f64, segments: &mut Vec<MediumSegment<'a>>) {
+        let origin = rotate_vec_z(r.origin_borrow(), self.sin_theta, self.cos_theta);
+        let direction = rotate_vec_z(r.direction_borrow(), self.sin_theta, self.cos_theta);
+        self.ptr.media(
+            &Ray::new(&origin, &direction, r.time()),
+            t_min,
+            t_max,
+            segments,
+        )
+    }
+
+    fn has_media(&self) -> bool {
+        self.ptr.has_media()
+    }
+
+    fn describe(&self) -> ObjectDesc {
+        ObjectDesc::RotateZ {
+            angle: self.angle,
+            object: Box::new(self.ptr.describe()),
+        }
+    }
 }
 
 impl<H: Hittable> RotateZ<H> {
@@ -448,6 +705,7 @@ impl<H: Hittable> RotateZ<H> {
         bbox = AABB::new(&min, &max);
         Self {
             ptr: p,
+            angle,
             sin_theta,
             cos_theta,
             hasbox,
@@ -468,7 +726,7 @@ impl<H: Hittable> FlipFace<H> {
 }
 
 impl<H: Hittable> Hittable for FlipFace<H> {
-    fn hit(&self, r: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord> {
+    fn hit(&self, r: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord<'_>> {
         let op = self.ptr.hit(r, t_min, t_max);
         op.as_ref()?;
         let mut rec = op.unwrap();
@@ -484,8 +742,30 @@ impl<H: Hittable> Hittable for FlipFace<H> {
         self.ptr.pdf_value(o, v)
     }
 
-    fn random(&self, o: &Vec3) -> Vec3 {
-        self.ptr.random(o)
+    fn random(&self, o: &Vec3, sampler: &mut dyn Sampler) -> Vec3 {
+        self.ptr.random(o, sampler)
+    }
+
+    fn sample_surface(&self, sampler: &mut dyn Sampler) -> Option<(Point3, Vec3)> {
+        self.ptr.sample_surface(sampler)
+    }
+
+    fn area(&self) -> f64 {
+        self.ptr.area()
+    }
+
+    fn media<'a>(&'a self, r: &Ray, t_min: f64, t_max: f64, segments: &mut Vec<MediumSegment<'a>>) {
+        self.ptr.media(r, t_min, t_max, segments)
+    }
+
+    fn has_media(&self) -> bool {
+        self.ptr.has_media()
+    }
+
+    fn describe(&self) -> ObjectDesc {
+        ObjectDesc::FlipFace {
+            object: Box::new(self.ptr.describe()),
+        }
     }
 }
 
@@ -1,6 +1,8 @@
 use crate::{
     hittable::{aarect::*, *},
     material::Material,
+    sampler::Sampler,
+    scene::desc::{MaterialDesc, MaterialRef, ObjectDesc},
     utility::*,
 };
 
@@ -8,6 +10,7 @@ pub struct RectBox {
     pub box_min: Point3,
     pub box_max: Point3,
     pub sides: HittableList,
+    pub material: MaterialDesc,
 }
 
 impl RectBox {
@@ -54,6 +57,7 @@ impl RectBox {
             p1.x,
             material.clone(),
         )));
+        let desc = material.describe();
         sides.add(Box::new(YZRect::new(
             p0.y, p1.y, p0.z, p1.z, p0.x, material,
         )));
@@ -62,6 +66,7 @@ impl RectBox {
             box_min: *p0,
             box_max: *p1,
             sides,
+            material: desc,
         }
     }
 }
@@ -71,8 +76,24 @@ impl Hittable for RectBox {
         self.sides.hit(r, t_min, t_max)
     }
 
+    fn sample_surface(&self, sampler: &mut dyn Sampler) -> Option<(Point3, Vec3)> {
+        self.sides.sample_surface(sampler)
+    }
+
+    fn area(&self) -> f64 {
+        self.sides.area()
+    }
+
     fn bounding_box(&self, _time0: f64, _time1: f64, output_box: &mut AABB) -> bool {
         *output_box = AABB::new(&self.box_min, &self.box_max);
         true
     }
+
+    fn describe(&self) -> ObjectDesc {
+        ObjectDesc::RectBox {
+            p0: self.box_min.to_array(),
+            p1: self.box_max.to_array(),
+            material: MaterialRef::Inline(Box::new(self.material.clone())),
+        }
+    }
 }
@@ -1,4 +1,11 @@
-use crate::{hittable::*, material::Material, pdf::onb::ONB, utility::*};
+use crate::{
+    hittable::*,
+    material::Material,
+    pdf::onb::ONB,
+    sampler::Sampler,
+    scene::desc::{MaterialRef, ObjectDesc},
+    utility::*,
+};
 
 pub fn get_sphere_uv(p: &Point3, u: &mut f64, v: &mut f64) {
     // p: a given point on the sphere of radius one, centered at the origin.
@@ -32,6 +39,7 @@ impl<M: Material> Sphere<M> {
 
 impl<M: Material> Hittable for Sphere<M> {
     fn hit(&self, r: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord> {
+        count(Counter::PrimitiveTests);
         let oc = r.origin() - self.center;
         let a = r.direction_borrow().length_squared();
         let half_b = dot(r.direction_borrow(), &oc);
@@ -55,6 +63,7 @@ impl<M: Material> Hittable for Sphere<M> {
             v: 0.,
             front_face: false,
             mat_ptr: &self.mat_ptr,
+            id: 0,
         };
         let outward_normal = (rec.p - self.center) / self.radius;
         rec.set_face_normal(r, &outward_normal);
@@ -78,11 +87,32 @@ impl<M: Material> Hittable for Sphere<M> {
         1. / solid_angle
     }
 
-    fn random(&self, o: &Vec3) -> Vec3 {
+    fn random(&self, o: &Vec3, sampler: &mut dyn Sampler) -> Vec3 {
         let direction = self.center - *o;
         let distance_squared = direction.length_squared();
         let uvw = ONB::build_from_w(&direction);
-        uvw.local_vec(&Vec3::random_to_sphere(self.radius, distance_squared))
+        uvw.local_vec(&Vec3::random_to_sphere(
+            self.radius,
+            distance_squared,
+            sampler,
+        ))
+    }
+
+    fn sample_surface(&self, sampler: &mut dyn Sampler) -> Option<(Point3, Vec3)> {
+        let normal = Vec3::random_unit_vector(sampler);
+        Some((self.center + self.radius * normal, normal))
+    }
+
+    fn area(&self) -> f64 {
+        2. * TAU * self.radius * self.radius
+    }
+
+    fn describe(&self) -> ObjectDesc {
+        ObjectDesc::Sphere {
+            center: self.center.to_array(),
+            radius: self.radius,
+            material: MaterialRef::Inline(Box::new(self.mat_ptr.describe())),
+        }
     }
 }
 
@@ -116,6 +146,7 @@ impl<M: Material> MovingSphere<M> {
 
 impl<M: Material> Hittable for MovingSphere<M> {
     fn hit(&self, r: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord> {
+        count(Counter::PrimitiveTests);
         let oc = r.origin() - self.center(r.time());
         let a = r.direction_borrow().length_squared();
         let half_b = dot(r.direction_borrow(), &oc);
@@ -139,6 +170,7 @@ impl<M: Material> Hittable for MovingSphere<M> {
             v: 0.,
             front_face: false,
             mat_ptr: &self.mat_ptr,
+            id: 0,
         };
         let outward_normal = (rec.p - self.center(r.time())) / self.radius;
         rec.set_face_normal(r, &outward_normal);
@@ -151,4 +183,15 @@ impl<M: Material> Hittable for MovingSphere<M> {
         *output_box = surrounding_box(&box0, &box1);
         true
     }
+
+    fn describe(&self) -> ObjectDesc {
+        ObjectDesc::MovingSphere {
+            center0: self.center0.to_array(),
+            center1: self.center1.to_array(),
+            radius: self.radius,
+            time0: self.time0,
+            time1: self.time1,
+            material: MaterialRef::Inline(Box::new(self.mat_ptr.describe())),
+        }
+    }
 }
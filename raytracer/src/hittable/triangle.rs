@@ -1,5 +1,10 @@
 #![allow(dead_code)]
-use crate::{hittable::*, texture::*};
+use crate::{
+    hittable::*,
+    sampler::Sampler,
+    scene::desc::{MaterialRef, ObjectDesc},
+    texture::*,
+};
 use ndarray::array;
 
 #[derive(Clone)]
@@ -66,9 +71,17 @@ impl<M: Material> Triangle<M> {
             tangent: Vec3::from_array(tangent),
         }
     }
+
+    // ab and ac back from pb and pc, which are their dual basis in the plane
+    fn edges(&self) -> (Vec3, Vec3) {
+        let ab = cross(&self.pb, &self.n);
+        let ac = cross(&self.n, &self.pc);
+        (ab / dot(&self.pc, &ab), ac / dot(&self.pb, &ac))
+    }
 }
 impl<M: Material> Hittable for Triangle<M> {
     fn hit(&self, r: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord> {
+        count(Counter::PrimitiveTests);
         let t = dot(&(self.a - r.origin()), &self.n) / dot(r.direction_borrow(), &self.n);
         if t < t_min || t_max < t {
             return None;
@@ -109,4 +122,83 @@ impl<M: Material> Hittable for Triangle<M> {
         *output_box = self.bbox;
         true
     }
+    fn sample_surface(&self, sampler: &mut dyn Sampler) -> Option<(Point3, Vec3)> {
+        // uniform barycentric coordinates, the square folded onto the triangle
+        let (mut u, mut v) = sampler.get_2d();
+        if u + v > 1. {
+            (u, v) = (1. - u, 1. - v);
+        }
+        let (ab, ac) = self.edges();
+        Some((self.a + u * ab + v * ac, self.n))
+    }
+    fn area(&self) -> f64 {
+        let (ab, ac) = self.edges();
+        0.5 * cross(&ab, &ac).length()
+    }
+    fn describe(&self) -> ObjectDesc {
+        let (ab, ac) = self.edges();
+        let (uvb, uvc) = (self.uva + self.uvab, self.uva + self.uvac);
+        ObjectDesc::Triangle {
+            vertices: [
+                self.a.to_array(),
+                (self.a + ab).to_array(),
+                (self.a + ac).to_array(),
+            ],
+            uvs: [[self.uva.x, self.uva.y], [uvb.x, uvb.y], [uvc.x, uvc.y]],
+            normals: Some([
+                self.na.to_array(),
+                (self.na + self.nab).to_array(),
+                (self.na + self.nac).to_array(),
+            ]),
+            material: MaterialRef::Inline(Box::new(self.mat.describe())),
+            normal_map: self.nmap.as_ref().map(|nmap| nmap.file_name().to_owned()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::material::Lambertian;
+
+    #[test]
+    fn describe_gives_back_the_vertices() {
+        let (a, b, c) = (
+            Point3::new(1., 2., 3.),
+            Point3::new(-4., 0.5, 2.),
+            Point3::new(0., 7., -1.),
+        );
+        let normals = (
+            Vec3::new(0., 1., 0.),
+            Vec3::new(1., 0., 0.),
+            Vec3::new(0., 0., 1.),
+        );
+        let triangle = Triangle::new(
+            (a, b, c),
+            Lambertian::new_from_color(&Color::new(0.5, 0.5, 0.5)),
+            (0., 0.),
+            (1., 0.25),
+            (0.5, 1.),
+            normals,
+            None,
+        );
+        let close = |x: [f64; 3], y: [f64; 3]| (0..3).all(|i| (x[i] - y[i]).abs() < 1e-9);
+        match triangle.describe() {
+            ObjectDesc::Triangle {
+                vertices,
+                uvs,
+                normals: Some(n),
+                ..
+            } => {
+                assert!(close(vertices[0], a.to_array()));
+                assert!(close(vertices[1], b.to_array()));
+                assert!(close(vertices[2], c.to_array()));
+                assert_eq!(uvs, [[0., 0.], [1., 0.25], [0.5, 1.]]);
+                assert!(close(n[1], normals.1.to_array()));
+                assert!(close(n[2], normals.2.to_array()));
+            }
+            _ => panic!("a triangle describes itself as a triangle"),
+        }
+        assert!((triangle.area() - 0.5 * cross(&(b - a), &(c - a)).length()).abs() < 1e-9);
+    }
 }
@@ -0,0 +1,652 @@
+use crate::{
+    camera::Camera,
+    hittable::*,
+    integrator::{
+        emission_pdf, emitted_towards, interact, transmittance, FaultKind, LightPoint,
+        MisHeuristic, PathSample,
+    },
+    material::ScatterRecord,
+    sampler::Sampler,
+    texture::Texture,
+    utility::*,
+};
+
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+enum VertexKind {
+    Camera,
+    Light, //the first vertex of a light subpath
+    Surface,
+    Medium, //a collision in a medium
+}
+
+// a vertex of a camera or light subpath, with the densities, per unit area, of sampling it from
+// the end of the path it belongs to (fwd) and from the other end (rev), for the MIS weights
+#[derive(Clone)]
+struct Vertex<'a> {
+    kind: VertexKind,
+    p: Point3,
+    normal: Vec3, //facing the ray that reached a surface or medium, outward on a light
+    rec: Option<HitRecord<'a>>,
+    ray: Ray, //that reached the vertex
+    object: Option<usize>,
+    beta: Color, //throughput of the subpath up to the vertex
+    attenuation: Color,
+    delta: bool,              //scattered specularly, so it cannot be connected to
+    emission: (Color, Color), //towards the side of the normal and the other
+    light_pdf: f64,           //of the lights sampling this point, by area
+    pdf_fwd: f64,
+    pdf_rev: f64,
+}
+
+impl<'a> Vertex<'a> {
+    fn new(kind: VertexKind, p: Point3, normal: Vec3, ray: Ray, beta: Color) -> Self {
+        Self {
+            kind,
+            p,
+            normal,
+            rec: None,
+            ray,
+            object: None,
+            beta,
+            attenuation: Color::default(),
+            delta: false,
+            emission: (Color::default(), Color::default()),
+            light_pdf: 0.,
+            pdf_fwd: 0.,
+            pdf_rev: 0.,
+        }
+    }
+
+    fn hit(&self) -> Option<(usize, &HitRecord<'a>)> {
+        Some((self.object?, self.rec.as_ref()?))
+    }
+
+    fn emitted(&self, dir: &Vec3) -> Color {
+        emitted_towards(&self.normal, &self.emission, dir)
+    }
+
+    fn emission_pdf(&self, dir: &Vec3) -> f64 {
+        emission_pdf(&self.normal, &self.emission, dir)
+    }
+
+    // f·cos of the light leaving towards `dir`: the emission of a light, the scattering of the
+    // light that reached a surface
+    fn f(&self, dir: &Vec3) -> Color {
+        match (self.kind, &self.rec) {
+            (VertexKind::Light, _) => self.emitted(dir) * dot(&dir.unit(), &self.normal).abs(),
+            (VertexKind::Surface | VertexKind::Medium, Some(rec)) if !self.delta => {
+                let scattered = Ray::new(&self.p, dir, self.ray.time());
+                self.attenuation * rec.mat_ptr.scattering_pdf(&self.ray, rec, &scattered)
+            }
+            _ => Color::default(),
+        }
+    }
+
+    // density of leaving towards `dir`, per solid angle
+    fn pdf_dir(&self, dir: &Vec3) -> f64 {
+        self.pdf_dir_after(&self.ray, dir)
+    }
+
+    // the same for the light arriving along `incoming` instead, as it does through a connection
+    // of the path reversed; phase functions depend on it
+    fn pdf_dir_after(&self, incoming: &Ray, dir: &Vec3) -> f64 {
+        match (self.kind, &self.rec) {
+            (VertexKind::Light, _) => self.emission_pdf(dir),
+            (VertexKind::Surface | VertexKind::Medium, Some(rec)) if !self.delta => {
+                let scattered = Ray::new(&self.p, dir, incoming.time());
+                rec.mat_ptr.scattering_pdf(incoming, rec, &scattered)
+            }
+            _ => 0.,
+        }
+    }
+
+    // a density per solid angle at this vertex to one per unit area at `next`
+    fn to_area(&self, pdf: f64, next: &Vertex) -> f64 {
+        let w = next.p - self.p;
+        let distance_squared = w.length_squared();
+        // a point of the camera or a medium has no surface to project onto; the densities of
+        // sampling the distance to a collision are left out of every strategy alike, so the
+        // weights still add up to one
+        if matches!(next.kind, VertexKind::Camera | VertexKind::Medium) {
+            pdf / distance_squared
+        } else {
+            pdf * dot(&next.normal, &w).abs() / (distance_squared * distance_squared.sqrt())
+        }
+    }
+}
+
+fn black(color: &Color) -> bool {
+    color.x == 0. && color.y == 0. && color.z == 0.
+}
+
+// bidirectional path tracer (Veach 1997): a subpath from the camera and one from a point of
+// `lights` are connected at every pair of their vertices, and the paths so made are weighted by
+// multiple importance sampling over all the ways they could have been sampled.
+// connecting the light subpath to the camera splats its light on whatever pixel it lands, so
+// caustics and lights seen through small openings converge
+pub struct Bdpt<'a> {
+    pub world: &'a HittableList,
+    pub lights: &'a HittableList,
+    pub background: &'a dyn Texture,
+    pub camera: &'a Camera,
+    pub width: u32,
+    pub height: u32,
+    pub max_depth: i32,
+    pub rr_depth: i32, //bounces of a subpath before Russian roulette
+    pub heuristic: MisHeuristic,
+}
+
+impl<'a> Bdpt<'a> {
+    // the radiance of the camera ray `r`; what the light subpath brings to other pixels is pushed
+    // to `splats` with where it lands on the film, in pixels from its top left corner, to be
+    // divided by the number of samples of the image
+    pub fn sample(
+        &self,
+        r: &Ray,
+        (u, v): (f64, f64),
+        sampler: &mut dyn Sampler,
+        splats: &mut Vec<((f64, f64), PathSample<'a>)>,
+    ) -> PathSample<'a> {
+        let mut sample = PathSample::default();
+        count(Counter::Paths);
+        let camera = self.camera_path(r, (u, v), sampler, &mut sample);
+        let light = self.light_path(r.time(), sampler);
+        let lens = self.camera.sample_lens(sampler);
+        let max_vertices = self.max_depth as usize;
+        for t in 1..=camera.len() {
+            for s in 0..=light.len() {
+                // vertices of the path, the camera left out
+                let vertices = s + t - 1;
+                if vertices == 0 || vertices > max_vertices {
+                    continue;
+                }
+                if t == 1 {
+                    self.splat(&light[..s], &lens, r.time(), &mut sample, splats, sampler);
+                } else {
+                    let contribution = self.connect(&camera[..t], &light[..s], r.time(), sampler);
+                    sample.add(contribution, vertices <= 2, camera[t - 1].hit());
+                }
+            }
+        }
+        sample
+    }
+
+    fn camera_path(
+        &self,
+        r: &Ray,
+        (u, v): (f64, f64),
+        sampler: &mut dyn Sampler,
+        sample: &mut PathSample<'a>,
+    ) -> Vec<Vertex<'a>> {
+        let white = Color::new(1., 1., 1.);
+        let camera = Vertex::new(
+            VertexKind::Camera,
+            r.origin(),
+            self.camera.forward(),
+            *r,
+            white,
+        );
+        let mut path = vec![camera];
+        let pdf = self.camera_pdf(r.direction_borrow());
+        // nothing samples the background but the camera paths
+        if let Some((ray, beta)) = self.walk(*r, white, pdf, &mut path, sampler) {
+            let background = if path.len() == 1 {
+                self.background.value(u, v, ray.origin_borrow())
+            } else {
+                let dir = ray.direction_borrow().unit();
+                self.background
+                    .value(0.5 * (dir.x + 1.), 0.5 * (dir.y + 1.), ray.origin_borrow())
+            };
+            sample.add(beta * background, path.len() <= 2, None);
+        }
+        path
+    }
+
+    fn light_path(&self, time: f64, sampler: &mut dyn Sampler) -> Vec<Vertex<'a>> {
+        let mut path = Vec::new();
+        let light = match LightPoint::sample(self.lights, time, sampler) {
+            Some(light) => light,
+            None => return path,
+        };
+        let mut vertex = Vertex::new(
+            VertexKind::Light,
+            light.p,
+            light.normal,
+            light.probe,
+            Color::grayscale(1. / light.pdf),
+        );
+        vertex.emission = light.emission;
+        vertex.light_pdf = light.pdf;
+        vertex.pdf_fwd = light.pdf;
+        let dir = light.sample_direction(sampler);
+        let pdf = vertex.pdf_dir(&dir);
+        let beta = vertex.beta * vertex.f(&dir) / pdf;
+        path.push(vertex);
+        self.walk(
+            Ray::new(&light.p, &dir, time),
+            beta,
+            pdf,
+            &mut path,
+            sampler,
+        );
+        path
+    }
+
+    // extends the subpath from its last vertex along `ray`, sampled with density `pdf` per solid
+    // angle; the ray and throughput of a path that left the scene
+    fn walk(
+        &self,
+        mut ray: Ray,
+        mut beta: Color,
+        mut pdf: f64,
+        path: &mut Vec<Vertex<'a>>,
+        sampler: &mut dyn Sampler,
+    ) -> Option<(Ray, Color)> {
+        let from_camera = path[0].kind == VertexKind::Camera;
+        // a camera subpath also has the camera, a light subpath its first vertex is on a light
+        let max_vertices = self.max_depth as usize + from_camera as usize;
+        while path.len() < max_vertices {
+            count(Counter::Rays);
+            let (object, rec, medium) = match interact(self.world, &ray, 0.001, sampler) {
+                Some(interaction) => (interaction.object, interaction.rec, interaction.medium),
+                None => return Some((ray, beta)),
+            };
+            count(Counter::PathVertices);
+            let kind = if medium {
+                VertexKind::Medium
+            } else {
+                VertexKind::Surface
+            };
+            let mut vertex = Vertex::new(kind, rec.p, rec.normal, ray, beta);
+            vertex.object = Some(object);
+            vertex.pdf_fwd = path.last().unwrap().to_area(pdf, &vertex);
+            if from_camera && !medium {
+                let mut back = rec.clone();
+                back.front_face = !back.front_face;
+                let emitted =
+                    |rec: &HitRecord| rec.mat_ptr.emitted(&ray, rec, rec.u, rec.v, &rec.p);
+                vertex.emission = (emitted(&rec), emitted(&back));
+                if !black(&vertex.emission.0) || !black(&vertex.emission.1) {
+                    vertex.light_pdf = self.light_pdf(&ray, rec.t);
+                }
+            }
+
+            let mut srec = ScatterRecord::default();
+            let scattered = rec.mat_ptr.scatter(&ray, &rec, &mut srec, sampler);
+            let (next, pdf_rev) = match (scattered, srec.pdf_ptr) {
+                (false, _) => {
+                    vertex.rec = Some(rec);
+                    path.push(vertex);
+                    return None;
+                }
+                (true, None) => {
+                    vertex.delta = true;
+                    beta *= srec.attenuation;
+                    pdf = 0.;
+                    (srec.scattered, 0.)
+                }
+                (true, Some(pdf_ptr)) => {
+                    let next = Ray::new(&rec.p, &pdf_ptr.generate(sampler).unit(), ray.time());
+                    pdf = pdf_ptr.value(next.direction_borrow());
+                    // the path reversed arrives against `next` and leaves against `ray`
+                    let reverse = Ray::new(&rec.p, &-next.direction(), ray.time());
+                    let back = Ray::new(&rec.p, &-ray.direction().unit(), ray.time());
+                    let pdf_rev = rec.mat_ptr.scattering_pdf(&reverse, &rec, &back);
+                    beta *= srec.attenuation * rec.mat_ptr.scattering_pdf(&ray, &rec, &next) / pdf;
+                    (next, pdf_rev)
+                }
+            };
+            vertex.attenuation = srec.attenuation;
+            vertex.rec = Some(rec);
+            let last = path.len() - 1;
+            path[last].pdf_rev = vertex.to_area(pdf_rev, &path[last]);
+            path.push(vertex);
+            if pdf <= 0. && !path[last + 1].delta {
+                return None;
+            }
+
+            // Russian roulette, as in the unidirectional tracer
+            if path.len() as i32 - 1 >= self.rr_depth {
+                let survival = beta.x.max(beta.y).max(beta.z);
+                if survival < 1. {
+                    if sampler.get_1d() >= survival {
+                        return None;
+                    }
+                    beta /= survival;
+                }
+            }
+            ray = next;
+        }
+        None
+    }
+
+    // the last vertex of the light subpath seen through the lens
+    fn splat(
+        &self,
+        light: &[Vertex<'a>],
+        lens: &Point3,
+        time: f64,
+        sample: &mut PathSample<'a>,
+        splats: &mut Vec<((f64, f64), PathSample<'a>)>,
+        sampler: &mut dyn Sampler,
+    ) {
+        let qs = &light[light.len() - 1];
+        if qs.delta {
+            return;
+        }
+        let (s, t) = match self.camera.raster(&qs.p, lens) {
+            Some(st) => st,
+            None => return,
+        };
+        // the inverse of the pixel to (s, t) mapping of the camera rays
+        let (x, j) = (s * (self.width - 1) as f64, t * (self.height - 1) as f64);
+        if x < 0. || j < 0. || x >= self.width as f64 || j >= self.height as f64 {
+            return;
+        }
+
+        let w = *lens - qs.p;
+        let distance_squared = w.length_squared();
+        // the film density turns the flux into the radiance of a pixel, which spans
+        // 1 / ((width - 1) * (height - 1)) of (s, t)
+        let pixels = ((self.width - 1) * (self.height - 1)) as f64;
+        let contribution =
+            qs.beta * qs.f(&w) * (self.camera.film_density(&-w) * pixels / distance_squared);
+        if black(&contribution) {
+            return;
+        }
+        let contribution = contribution * self.transmittance(&qs.p, lens, time, sampler);
+        if black(&contribution) {
+            return;
+        }
+        let camera = Vertex::new(
+            VertexKind::Camera,
+            *lens,
+            self.camera.forward(),
+            Ray::new(lens, &-w, time),
+            Color::new(1., 1., 1.),
+        );
+        let contribution = contribution * self.mis_weight(&[camera], light);
+        sample.check(&contribution, qs.hit());
+        if FaultKind::of(&contribution).is_none() {
+            // split like the camera paths, for the radiance clamp
+            let mut splat = PathSample::default();
+            splat.add(contribution, light.len() <= 2, qs.hit());
+            // v grows upwards, the rows of the film downwards
+            splats.push(((x, self.height as f64 - j), splat));
+        }
+    }
+
+    // the path of the camera subpath ending at its last vertex and the light subpath ending at
+    // its last vertex, or the camera subpath alone when it ends on a light
+    fn connect(
+        &self,
+        camera: &[Vertex],
+        light: &[Vertex],
+        time: f64,
+        sampler: &mut dyn Sampler,
+    ) -> Color {
+        let pt = &camera[camera.len() - 1];
+        let contribution = match light.last() {
+            None => pt.beta * pt.emission.0,
+            Some(qs) => {
+                if pt.delta || qs.delta {
+                    return Color::default();
+                }
+                let w = qs.p - pt.p;
+                let contribution = pt.beta * pt.f(&w) * qs.f(&-w) * qs.beta / w.length_squared();
+                if black(&contribution) {
+                    return contribution;
+                }
+                contribution * self.transmittance(&pt.p, &qs.p, time, sampler)
+            }
+        };
+        if black(&contribution) {
+            return contribution;
+        }
+        contribution * self.mis_weight(camera, light)
+    }
+
+    // the sampling densities of the path from every other split between the subpaths, against
+    // this one's
+    fn mis_weight(&self, camera: &[Vertex], light: &[Vertex]) -> f64 {
+        let (s, t) = (light.len(), camera.len());
+        // (fwd, rev, delta) of each vertex, those next to the connection seen from the other side
+        let densities = |v: &Vertex| (v.pdf_fwd, v.pdf_rev, v.delta);
+        let mut cam: Vec<_> = camera.iter().map(densities).collect();
+        let mut lig: Vec<_> = light.iter().map(densities).collect();
+        let pt = &camera[t - 1];
+        match light.last() {
+            Some(qs) => {
+                cam[t - 1].1 = self.pdf(None, qs, pt);
+                if t > 1 {
+                    cam[t - 2].1 = self.pdf(Some(qs), pt, &camera[t - 2]);
+                }
+                lig[s - 1].1 = self.pdf(None, pt, qs);
+                lig[s - 1].2 = false;
+                if s > 1 {
+                    lig[s - 2].1 = self.pdf(Some(pt), qs, &light[s - 2]);
+                }
+            }
+            // a light the lights cannot sample only the camera subpaths find
+            None if pt.light_pdf == 0. => return 1.,
+            None => {
+                cam[t - 1].1 = pt.light_pdf;
+                let prev = &camera[t - 2];
+                cam[t - 2].1 = pt.to_area(pt.emission_pdf(&(prev.p - pt.p)), prev);
+            }
+        }
+        cam[t - 1].2 = false;
+
+        // delta vertices have no density: they leave the ratios alone
+        let remap = |pdf: f64| if pdf != 0. { pdf } else { 1. };
+        let weight = |ratio: f64| match self.heuristic {
+            MisHeuristic::Power => ratio * ratio,
+            MisHeuristic::Balance => ratio,
+        };
+        let mut sum = 0.;
+        let mut ratio = 1.;
+        for i in (1..t).rev() {
+            ratio *= remap(cam[i].1) / remap(cam[i].0);
+            if !cam[i].2 && !cam[i - 1].2 {
+                sum += weight(ratio);
+            }
+        }
+        ratio = 1.;
+        for i in (0..s).rev() {
+            ratio *= remap(lig[i].1) / remap(lig[i].0);
+            if !lig[i].2 && !(i > 0 && lig[i - 1].2) {
+                sum += weight(ratio);
+            }
+        }
+        1. / (1. + sum)
+    }
+
+    // density of sampling `to` from `from`, per unit area, for a path that reached `from` from
+    // `prev`, or the way it was traced
+    fn pdf(&self, prev: Option<&Vertex>, from: &Vertex, to: &Vertex) -> f64 {
+        let dir = to.p - from.p;
+        let pdf = match (from.kind, prev) {
+            (VertexKind::Camera, _) => self.camera_pdf(&dir),
+            (_, Some(prev)) => {
+                let incoming = Ray::new(&prev.p, &(from.p - prev.p), from.ray.time());
+                from.pdf_dir_after(&incoming, &dir)
+            }
+            (_, None) => from.pdf_dir(&dir),
+        };
+        from.to_area(pdf, to)
+    }
+
+    // density of the camera rays, per solid angle: (s, t) is uniform over the pixels
+    fn camera_pdf(&self, dir: &Vec3) -> f64 {
+        let (width, height) = (self.width as f64, self.height as f64);
+        let film_area = width * height / ((width - 1.) * (height - 1.));
+        self.camera.film_density(dir) / film_area
+    }
+
+    // density of the lights sampling the point at `t` along `r`, per unit area
+    fn light_pdf(&self, r: &Ray, t: f64) -> f64 {
+        let n = self.lights.size() as f64;
+        let mut pdf = 0.;
+        for light in &self.lights.objects {
+            let area = light.area();
+            if area <= 0. {
+                continue;
+            }
+            if let Some(rec) = light.hit(r, 0.001, INFINITY) {
+                if (rec.t - t).abs() <= 1e-6 * t.max(1.) {
+                    pdf += 1. / (n * area);
+                }
+            }
+        }
+        pdf
+    }
+
+    // of the light between two points: 0 if a surface is in the way
+    fn transmittance(&self, a: &Point3, b: &Point3, time: f64, sampler: &mut dyn Sampler) -> f64 {
+        count(Counter::Rays);
+        count(Counter::ShadowRays);
+        let w = *b - *a;
+        let distance = w.length();
+        let shadow = Ray::new(a, &(w / distance), time);
+        if self.world.hit(&shadow, 0.001, distance - 0.001).is_some() {
+            return 0.;
+        }
+        transmittance(self.world, &shadow, 0.001, distance - 0.001, sampler)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        hittable::aarect::XYRect,
+        integrator::{tests::lit_floor, PathTracer},
+        material::DiffuseLight,
+        render::tonemap::luminance,
+        sampler::IndependentSampler,
+        texture::SolidColor,
+    };
+
+    const SIZE: u32 = 16;
+
+    fn camera(lookfrom: Point3, lookat: Point3) -> Camera {
+        Camera::new(&lookfrom, &lookat, &Vec3::new(0., 1., 0.), 60., 1., 0., 1.)
+    }
+
+    // the light of an image, summed over its pixels, from `spp` camera rays per pixel numbered
+    // like those of a render
+    fn image_light(
+        camera: &Camera,
+        spp: u64,
+        mut sample: impl FnMut(&Ray, (f64, f64), &mut IndependentSampler) -> Color,
+    ) -> f64 {
+        let mut sampler = IndependentSampler::new(5);
+        let mut sum = 0.;
+        for index in 0..(SIZE * SIZE) as u64 {
+            let (i, j) = (index % SIZE as u64, index / SIZE as u64);
+            for number in 0..spp {
+                sampler.start_sample(index, number);
+                let (du, dv) = sampler.get_2d();
+                let u = (i as f64 + du) / (SIZE - 1) as f64;
+                let v = (j as f64 + dv) / (SIZE - 1) as f64;
+                let r = camera.get_ray(u, v, 0., 1., &mut sampler);
+                sum += luminance(&sample(&r, (u, v), &mut sampler));
+            }
+        }
+        sum / spp as f64
+    }
+
+    // the paths connected every way and weighted, the light paths splatted on the film included,
+    // bring the image the light of the paths from the camera alone
+    #[test]
+    fn agrees_with_the_path_tracer() {
+        let (world, lights) = lit_floor(0.5, Some(0.5));
+        let black = SolidColor::new(&Color::default());
+        let camera = camera(Point3::new(0., 1., -3.), Point3::new(0., 0., 0.));
+        let tracer = PathTracer {
+            world: &world,
+            lights: &lights,
+            background: &black,
+            max_depth: 8,
+            rr_depth: 8,
+            heuristic: MisHeuristic::Power,
+        };
+        let bdpt = Bdpt {
+            world: &world,
+            lights: &lights,
+            background: &black,
+            camera: &camera,
+            width: SIZE,
+            height: SIZE,
+            max_depth: 8,
+            rr_depth: 8,
+            heuristic: MisHeuristic::Power,
+        };
+        let expected = image_light(&camera, 64, |r, uv, sampler| {
+            tracer.ray_color(r, uv, sampler).color()
+        });
+        let estimate = image_light(&camera, 64, |r, uv, sampler| {
+            let mut splats = Vec::new();
+            let color = bdpt.sample(r, uv, sampler, &mut splats).color();
+            // a splat is averaged over all the samples of the image, not those of a pixel
+            let pixels = (SIZE * SIZE) as f64;
+            splats
+                .iter()
+                .fold(color, |sum, (_, splat)| sum + splat.color() / pixels)
+        });
+        assert!(expected > 0.);
+        assert!(
+            (estimate - expected).abs() < 0.03 * expected,
+            "{} {}",
+            estimate,
+            expected
+        );
+    }
+
+    // the light paths reach the film where the camera rays that see their last vertex start
+    #[test]
+    fn splats_land_where_the_camera_sees() {
+        let light = FlipFace::new(XYRect::new(
+            -0.5,
+            1.,
+            -0.3,
+            0.4,
+            0.,
+            DiffuseLight::new_from_color(&Color::new(4., 4., 4.)),
+        ));
+        let mut world = HittableList::default();
+        world.add(Box::new(light.clone()));
+        let mut lights = HittableList::default();
+        lights.add(Box::new(light));
+        let black = SolidColor::new(&Color::default());
+        let camera = camera(Point3::new(0.3, 0.2, -3.), Point3::new(0., 0., 0.));
+        let bdpt = Bdpt {
+            world: &world,
+            lights: &lights,
+            background: &black,
+            camera: &camera,
+            width: SIZE,
+            height: SIZE,
+            max_depth: 4,
+            rr_depth: 4,
+            heuristic: MisHeuristic::Power,
+        };
+        let mut sampler = IndependentSampler::new(1);
+        let r = camera.get_ray(0.5, 0.5, 0., 1., &mut sampler);
+        let mut splats = Vec::new();
+        for index in 0..200 {
+            sampler.start_sample(0, index);
+            bdpt.sample(&r, (0.5, 0.5), &mut sampler, &mut splats);
+        }
+        assert!(splats.len() > 100);
+        let lens = camera.sample_lens(&mut sampler);
+        for ((x, y), _) in splats {
+            // the inverse of the film position of the camera rays, for the pinhole
+            let (u, v) = (x / (SIZE - 1) as f64, (SIZE as f64 - y) / (SIZE - 1) as f64);
+            let ray = camera.get_ray(u, v, 0., 1., &mut sampler);
+            let rec = world.hit(&ray, 0.001, INFINITY).unwrap();
+            let (s, t) = camera.raster(&rec.p, &lens).unwrap();
+            assert!((s - u).abs() < 1e-9 && (t - v).abs() < 1e-9);
+        }
+    }
+}
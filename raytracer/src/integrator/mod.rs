@@ -0,0 +1,561 @@
+use crate::{
+    hittable::*,
+    material::{Material, ScatterRecord},
+    pdf::*,
+    render::{aov::object_id, tonemap::luminance},
+    sampler::Sampler,
+    texture::Texture,
+    utility::*,
+};
+use clap::ValueEnum;
+use std::hash::{Hash, Hasher};
+
+pub mod bdpt;
+pub mod photon;
+pub mod volume;
+
+pub use bdpt::*;
+pub use photon::*;
+pub use volume::*;
+
+#[derive(Copy, Clone, PartialEq, Eq, Debug, ValueEnum)]
+pub enum IntegratorKind {
+    Path,   //unidirectional, with next-event estimation
+    Bdpt,   //bidirectional
+    Photon, //photon mapping, with a fixed radius
+    Sppm,   //progressive photon mapping, the radius shrinking with every sample
+}
+
+// weighting of two sampling strategies of the same path in multiple importance sampling
+#[derive(Copy, Clone, PartialEq, Eq, Debug, ValueEnum)]
+pub enum MisHeuristic {
+    Power, //exponent 2
+    Balance,
+}
+
+impl MisHeuristic {
+    // weight of the strategy with density `pdf` against the one with density `other`
+    pub fn weight(self, pdf: f64, other: f64) -> f64 {
+        let (a, b) = match self {
+            Self::Power => (pdf * pdf, other * other),
+            Self::Balance => (pdf, other),
+        };
+        if a + b > 0. {
+            a / (a + b)
+        } else {
+            0.
+        }
+    }
+}
+
+#[derive(Copy, Clone, PartialEq, Eq, Hash, Debug)]
+pub enum FaultKind {
+    NaN,
+    Infinite,
+    Negative,
+}
+
+impl FaultKind {
+    pub fn of(color: &Color) -> Option<Self> {
+        let channels = color.to_array();
+        if channels.iter().any(|c| c.is_nan()) {
+            Some(Self::NaN)
+        } else if channels.iter().any(|c| c.is_infinite()) {
+            Some(Self::Infinite)
+        } else if channels.iter().any(|&c| c < 0.) {
+            Some(Self::Negative)
+        } else {
+            None
+        }
+    }
+}
+
+// the vertex where a path first went wrong: index of the top-level object, id of the primitive hit
+// in it, as in the object id AOV, and its material, None for the background. they are only
+// described when the faults are reported
+#[derive(Clone, Copy)]
+pub struct Fault<'a> {
+    pub kind: FaultKind,
+    pub object: Option<usize>,
+    pub primitive: u32,
+    pub material: Option<&'a dyn Material>,
+}
+
+impl<'a> Fault<'a> {
+    fn new(kind: FaultKind, hit: Option<(usize, &HitRecord<'a>)>) -> Self {
+        Self {
+            kind,
+            object: hit.map(|(object, _)| object),
+            primitive: hit.map_or(0, |(_, rec)| object_id(rec.id)),
+            material: hit.map(|(_, rec)| rec.mat_ptr),
+        }
+    }
+
+    // the material by its address, which is all there is to tell two apart
+    fn key(&self) -> (FaultKind, Option<usize>, u32, Option<*const ()>) {
+        let material = self.material.map(|m| m as *const dyn Material as *const ());
+        (self.kind, self.object, self.primitive, material)
+    }
+}
+
+impl PartialEq for Fault<'_> {
+    fn eq(&self, other: &Self) -> bool {
+        self.key() == other.key()
+    }
+}
+
+impl Eq for Fault<'_> {}
+
+impl Hash for Fault<'_> {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.key().hash(state);
+    }
+}
+
+// radiance of a camera path, split at its first vertex
+#[derive(Clone, Default)]
+pub struct PathSample<'a> {
+    pub direct: Color, //emitted by what the camera sees, or by a light after one reflection
+    pub indirect: Color,
+    pub fault: Option<Fault<'a>>,
+}
+
+impl<'a> PathSample<'a> {
+    pub fn color(&self) -> Color {
+        self.direct + self.indirect
+    }
+
+    // `hit` produced the contribution
+    fn add(&mut self, contribution: Color, direct: bool, hit: Option<(usize, &HitRecord<'a>)>) {
+        self.check(&contribution, hit);
+        if direct {
+            self.direct += contribution;
+        } else {
+            self.indirect += contribution;
+        }
+    }
+
+    fn check(&mut self, color: &Color, hit: Option<(usize, &HitRecord<'a>)>) {
+        if self.fault.is_none() {
+            self.fault = FaultKind::of(color).map(|kind| Fault::new(kind, hit));
+        }
+    }
+}
+
+#[derive(Copy, Clone, PartialEq, Eq, Debug, ValueEnum)]
+pub enum ClampMode {
+    Indirect, //leave what the camera sees directly and direct lighting alone
+    All,
+}
+
+// caps the radiance of every sample, trading a little energy for no fireflies
+#[derive(Copy, Clone, Debug)]
+pub struct RadianceClamp {
+    pub max: f64,
+    pub mode: ClampMode,
+}
+
+impl RadianceClamp {
+    pub fn apply(&self, sample: &PathSample) -> Color {
+        let clamp = |color: Color| {
+            let peak = color.x.max(color.y).max(color.z);
+            if peak > self.max {
+                color * (self.max / peak) //keeps the hue
+            } else {
+                color
+            }
+        };
+        match self.mode {
+            ClampMode::Indirect => sample.direct + clamp(sample.indirect),
+            ClampMode::All => clamp(sample.direct) + clamp(sample.indirect),
+        }
+    }
+}
+
+// unidirectional path tracer: at every vertex with a pdf, surface or collision in a medium, the
+// emission reaching it is estimated twice, by sampling a point of `lights` (next-event
+// estimation, through the media on the way) and by the direction the path continues in, and the
+// two are combined by multiple importance sampling.
+// the path continues in a mixture of the light and material densities, so it is still guided
+// towards the objects in `lights`, like a glass sphere, that do not emit
+#[derive(Clone, Copy)]
+pub struct PathTracer<'a> {
+    pub world: &'a HittableList,
+    pub lights: &'a HittableList,
+    pub background: &'a dyn Texture,
+    pub max_depth: i32,
+    pub rr_depth: i32, //bounces before Russian roulette
+    pub heuristic: MisHeuristic,
+}
+
+impl<'a> PathTracer<'a> {
+    pub fn ray_color(
+        &self,
+        r: &Ray,
+        (u, v): (f64, f64),
+        sampler: &mut dyn Sampler,
+    ) -> PathSample<'a> {
+        let mut sample = PathSample::default();
+        count(Counter::Paths);
+        let mut throughput = Color::new(1., 1., 1.);
+        let mut ray = *r;
+        // the density the ray was sampled with at a vertex with a pdf, None after the camera
+        // and specular vertices, where lights are not sampled
+        let mut scatter_pdf: Option<f64> = None;
+        for bounce in 0..self.max_depth {
+            // vertices the path may still have, counting this one
+            let depth = self.max_depth - bounce;
+            // light reaching the camera from the first vertex is direct
+            let direct = bounce <= 1;
+            count(Counter::Rays);
+            let (object, rec) = match interact(self.world, &ray, 0.001, sampler) {
+                Some(interaction) => (interaction.object, interaction.rec),
+                None => {
+                    let background = throughput * self.background(&ray, depth, (u, v));
+                    sample.add(background, direct, None);
+                    break;
+                }
+            };
+            let hit = Some((object, &rec));
+            count(Counter::PathVertices);
+            let mut emitted = rec.mat_ptr.emitted(&ray, &rec, rec.u, rec.v, &rec.p);
+            if let Some(pdf) = scatter_pdf {
+                if !self.lights.is_empty() {
+                    let light_pdf = self
+                        .lights
+                        .pdf_value(ray.origin_borrow(), ray.direction_borrow());
+                    emitted *= self.heuristic.weight(pdf, light_pdf);
+                }
+            }
+            sample.add(throughput * emitted, direct, hit);
+
+            let mut srec = ScatterRecord::default();
+            if !rec.mat_ptr.scatter(&ray, &rec, &mut srec, sampler) {
+                break;
+            }
+            match srec.pdf_ptr {
+                None => {
+                    throughput *= srec.attenuation;
+                    ray = srec.scattered;
+                    scatter_pdf = None;
+                }
+                Some(pdf_ptr) if self.lights.is_empty() => {
+                    let scattered = Ray::new(&rec.p, &pdf_ptr.generate(sampler).unit(), ray.time());
+                    let pdf_val = pdf_ptr.value(scattered.direction_borrow());
+                    throughput *= srec.attenuation
+                        * rec.mat_ptr.scattering_pdf(&ray, &rec, &scattered)
+                        / pdf_val;
+                    ray = scattered;
+                    scatter_pdf = Some(pdf_val);
+                }
+                Some(pdf_ptr) => {
+                    let light_ptr = HittablePDF::new(self.lights, &rec.p);
+                    let mixed_pdf = MixturePDF::new(&light_ptr, pdf_ptr.as_ref());
+
+                    // next-event estimation, for paths that are still allowed one more vertex
+                    if depth > 1 {
+                        let shadow =
+                            Ray::new(&rec.p, &light_ptr.generate(sampler).unit(), ray.time());
+                        let light_pdf = light_ptr.value(shadow.direction_borrow());
+                        if light_pdf > 0. {
+                            count(Counter::Rays);
+                            count(Counter::ShadowRays);
+                            if let Some((light, light_rec)) =
+                                self.world.hit_object(&shadow, 0.001, INFINITY)
+                            {
+                                let le = light_rec.mat_ptr.emitted(
+                                    &shadow,
+                                    &light_rec,
+                                    light_rec.u,
+                                    light_rec.v,
+                                    &light_rec.p,
+                                ) * transmittance(
+                                    self.world,
+                                    &shadow,
+                                    0.001,
+                                    light_rec.t,
+                                    sampler,
+                                );
+                                let weight = self
+                                    .heuristic
+                                    .weight(light_pdf, mixed_pdf.value(shadow.direction_borrow()));
+                                let contribution = throughput
+                                    * srec.attenuation
+                                    * rec.mat_ptr.scattering_pdf(&ray, &rec, &shadow)
+                                    * (weight / light_pdf);
+                                // a bad density is the fault of this vertex, bad emission the light's
+                                sample.check(&contribution, hit);
+                                sample.add(
+                                    contribution * le,
+                                    bounce == 0,
+                                    Some((light, &light_rec)),
+                                );
+                            }
+                        }
+                    }
+
+                    let scattered =
+                        Ray::new(&rec.p, &mixed_pdf.generate(sampler).unit(), ray.time());
+                    let pdf_val = mixed_pdf.value(scattered.direction_borrow());
+                    throughput *= srec.attenuation
+                        * rec.mat_ptr.scattering_pdf(&ray, &rec, &scattered)
+                        / pdf_val;
+                    ray = scattered;
+                    scatter_pdf = Some(pdf_val);
+                }
+            }
+
+            sample.check(&throughput, hit);
+
+            // Russian roulette: a path that can only carry little light survives with a
+            // probability as small as its throughput, and carries more to stay unbiased
+            if bounce + 1 >= self.rr_depth {
+                let survival = throughput.x.max(throughput.y).max(throughput.z);
+                if survival < 1. {
+                    if sampler.get_1d() >= survival {
+                        break;
+                    }
+                    throughput /= survival;
+                }
+            }
+        }
+        sample
+    }
+
+    pub fn background(&self, r: &Ray, depth: i32, (u, v): (f64, f64)) -> Color {
+        if depth == self.max_depth {
+            self.background.value(u, v, r.origin_borrow())
+        } else {
+            let dir = r.direction_borrow().unit();
+            self.background
+                .value(0.5 * (dir.x + 1.), 0.5 * (dir.y + 1.), r.origin_borrow())
+        }
+    }
+}
+
+// a point picked on one of `lights`, uniformly and then by area, where a light subpath or a
+// photon starts
+pub struct LightPoint {
+    pub p: Point3,
+    pub normal: Vec3, //outward
+    pub probe: Ray,   //the ray the emission was looked up with
+    pub emission: (Color, Color),
+    pub pdf: f64, //by area, the pick of the light included
+}
+
+impl LightPoint {
+    // None if the light picked has no area or emits nothing there
+    pub fn sample(lights: &HittableList, time: f64, sampler: &mut dyn Sampler) -> Option<Self> {
+        if lights.is_empty() {
+            return None;
+        }
+        let n = lights.size();
+        let light = &lights.objects[((sampler.get_1d() * n as f64) as usize).min(n - 1)];
+        let area = light.area();
+        let (p, normal) = light.sample_surface(sampler)?;
+        if area <= 0. {
+            return None;
+        }
+        // which side emits: the light hit from just outside, and the same hit from inside
+        let probe = Ray::new(&(p + normal), &-normal, time);
+        let front = light.hit(&probe, 0.5, 1.5)?;
+        let mut back = front.clone();
+        back.front_face = !back.front_face;
+        let emitted = |rec: &HitRecord| rec.mat_ptr.emitted(&probe, rec, rec.u, rec.v, &rec.p);
+        let emission = (emitted(&front), emitted(&back));
+        if luminance(&emission.0) + luminance(&emission.1) <= 0. {
+            return None;
+        }
+        Some(Self {
+            p,
+            normal,
+            probe,
+            emission,
+            pdf: 1. / (n as f64 * area),
+        })
+    }
+
+    pub fn emitted(&self, dir: &Vec3) -> Color {
+        emitted_towards(&self.normal, &self.emission, dir)
+    }
+
+    pub fn emission_pdf(&self, dir: &Vec3) -> f64 {
+        emission_pdf(&self.normal, &self.emission, dir)
+    }
+
+    pub fn sample_direction(&self, sampler: &mut dyn Sampler) -> Vec3 {
+        let (front, back) = (luminance(&self.emission.0), luminance(&self.emission.1));
+        let side = if sampler.get_1d() * (front + back) < front {
+            self.normal
+        } else {
+            -self.normal
+        };
+        CosPDF::new(&side).generate(sampler).unit()
+    }
+}
+
+// the emission towards `dir` of a surface emitting `emission` towards the side of `normal` and
+// the other
+pub fn emitted_towards(normal: &Vec3, emission: &(Color, Color), dir: &Vec3) -> Color {
+    if dot(dir, normal) > 0. {
+        emission.0
+    } else {
+        emission.1
+    }
+}
+
+// density per solid angle of emitting towards `dir`: a side picked by its share of the emission,
+// cosine distributed
+pub fn emission_pdf(normal: &Vec3, emission: &(Color, Color), dir: &Vec3) -> f64 {
+    let total = luminance(&emission.0) + luminance(&emission.1);
+    if total <= 0. {
+        return 0.;
+    }
+    let cos = dot(&dir.unit(), normal);
+    luminance(&emitted_towards(normal, emission, dir)) / total * cos.abs() / PI
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        hittable::aarect::XZRect,
+        material::{DiffuseLight, Lambertian},
+        sampler::IndependentSampler,
+        texture::SolidColor,
+    };
+
+    // the mean of `n` samples of the light a camera ray brings back
+    fn mean(tracer: &PathTracer, r: &Ray, n: u64) -> (f64, f64) {
+        let mut sampler = IndependentSampler::new(11);
+        let (mut sum, mut sum_sq) = (0., 0.);
+        for index in 0..n {
+            sampler.start_sample(0, index);
+            let l = luminance(&tracer.ray_color(r, (0.5, 0.5), &mut sampler).color());
+            sum += l;
+            sum_sq += l * l;
+        }
+        let mean = sum / n as f64;
+        (mean, sum_sq / n as f64 - mean * mean)
+    }
+
+    // a gray floor under a small light facing it, and a wide ceiling above both if given its albedo
+    pub(super) fn lit_floor(floor: f64, ceiling: Option<f64>) -> (HittableList, HittableList) {
+        let light = FlipFace::new(XZRect::new(
+            -1.,
+            1.,
+            -1.,
+            1.,
+            2.,
+            DiffuseLight::new_from_color(&Color::new(4., 4., 4.)),
+        ));
+        let gray = |albedo| Lambertian::new_from_color(&Color::grayscale(albedo));
+        let mut world = HittableList::default();
+        world.add(Box::new(XZRect::new(
+            -100.,
+            100.,
+            -100.,
+            100.,
+            0.,
+            gray(floor),
+        )));
+        if let Some(ceiling) = ceiling {
+            world.add(Box::new(XZRect::new(
+                -100.,
+                100.,
+                -100.,
+                100.,
+                3.,
+                gray(ceiling),
+            )));
+        }
+        world.add(Box::new(light.clone()));
+        let mut lights = HittableList::default();
+        lights.add(Box::new(light));
+        (world, lights)
+    }
+
+    // sampling the lights and weighing the strategies changes the noise of a path, not its mean
+    #[test]
+    fn light_sampling_keeps_the_mean() {
+        let (world, lights) = lit_floor(0.5, None);
+        let black = SolidColor::new(&Color::default());
+        let no_lights = HittableList::default();
+        let tracer = |lights, heuristic| PathTracer {
+            world: &world,
+            lights,
+            background: &black,
+            max_depth: 2,
+            rr_depth: 2,
+            heuristic,
+        };
+        let r = Ray::new(&Point3::new(0., 1., -3.), &Vec3::new(0., -1., 3.), 0.);
+
+        let (expected, bsdf_variance) = mean(&tracer(&no_lights, MisHeuristic::Power), &r, 40000);
+        assert!(expected > 0.);
+        for heuristic in [MisHeuristic::Power, MisHeuristic::Balance] {
+            let (estimate, variance) = mean(&tracer(&lights, heuristic), &r, 40000);
+            assert!(
+                (estimate - expected).abs() < 0.05 * expected,
+                "{:?}",
+                heuristic
+            );
+            assert!(variance < 0.5 * bsdf_variance, "{:?}", heuristic);
+        }
+        let weights = MisHeuristic::Power.weight(0.3, 2.) + MisHeuristic::Power.weight(2., 0.3);
+        assert!((weights - 1.).abs() < 1e-12);
+    }
+
+    // a fault names the primitive and material of the hit, and the same source is one entry
+    #[test]
+    fn faults_come_from_the_hit_primitive() {
+        let (red, blue) = (
+            Lambertian::new_from_color(&Color::new(1., 0., 0.)),
+            Lambertian::new_from_color(&Color::new(0., 0., 1.)),
+        );
+        let (a, b) = (
+            HitRecord::new(&red).within(3),
+            HitRecord::new(&blue).within(4),
+        );
+        let nan = Color::new(f64::NAN, 0., 0.);
+        fn fault<'a>(rec: &HitRecord<'a>) -> Fault<'a> {
+            let mut sample = PathSample::default();
+            sample.add(Color::new(1., 1., 1.), true, Some((2, rec)));
+            sample.add(Color::new(f64::NAN, 0., 0.), false, Some((2, rec)));
+            sample.fault.unwrap()
+        }
+        let fault_a = fault(&a);
+        assert_eq!(fault_a.kind, FaultKind::NaN);
+        assert_eq!(
+            (fault_a.object, fault_a.primitive),
+            (Some(2), object_id(a.id))
+        );
+        assert!(fault_a == fault(&a) && fault_a != fault(&b));
+        let mut background = PathSample::default();
+        background.add(nan, true, None);
+        assert_eq!(background.fault.unwrap().object, None);
+    }
+
+    // Russian roulette ends paths early without changing their mean, and the depth bounds what
+    // they can bring back
+    #[test]
+    fn roulette_keeps_the_mean() {
+        let (world, lights) = lit_floor(0.9, Some(0.9));
+        let black = SolidColor::new(&Color::default());
+        let tracer = |max_depth, rr_depth| PathTracer {
+            world: &world,
+            lights: &lights,
+            background: &black,
+            max_depth,
+            rr_depth,
+            heuristic: MisHeuristic::Power,
+        };
+        let r = Ray::new(&Point3::new(0., 1., -3.), &Vec3::new(0., -1., 3.), 0.);
+        let (expected, _) = mean(&tracer(12, 12), &r, 40000);
+        let (estimate, _) = mean(&tracer(12, 1), &r, 40000);
+        assert!((estimate - expected).abs() < 0.03 * expected);
+        let (shallow, _) = mean(&tracer(2, 2), &r, 40000);
+        assert!(shallow < 0.95 * expected);
+        assert_eq!(mean(&tracer(1, 1), &r, 100).0, 0.);
+    }
+}
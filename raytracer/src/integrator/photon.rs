@@ -0,0 +1,489 @@
+use crate::{
+    hittable::*,
+    integrator::{interact, transmittance, LightPoint, PathSample, PathTracer},
+    material::ScatterRecord,
+    pdf::{HittablePDF, PDF},
+    sampler::{IndependentSampler, Sampler},
+    utility::*,
+};
+use std::{collections::BinaryHeap, thread};
+
+// each iteration of progressive photon mapping keeps this share of the photons of the last
+// (Knaus and Zwicker 2011)
+const SPPM_ALPHA: f64 = 2. / 3.;
+// the default radius reaches this many photons around a typical photon
+const RADIUS_NEIGHBOURS: usize = 50;
+const RADIUS_PHOTONS: usize = 1000; //photons the typical distance is measured around
+
+#[derive(Copy, Clone, Debug)]
+pub struct Photon {
+    pub p: Point3,
+    pub dir: Vec3, //of travel, unit
+    pub power: Color,
+}
+
+// photons left at the surfaces with a pdf, in a kd-tree: a slice is split at its median along its
+// longest axis, which is kept at the median's index, and its halves are split the same way
+pub struct PhotonMap {
+    pub photons: Vec<Photon>,
+    axes: Vec<u8>,
+}
+
+impl PhotonMap {
+    pub fn new(mut photons: Vec<Photon>) -> Self {
+        let mut axes = vec![0; photons.len()];
+        build(&mut photons, &mut axes);
+        Self { photons, axes }
+    }
+
+    // the `iteration`-th map of a render: `total` photons from `lights`, traced by `threads`
+    // threads, with the counts of the rays they cast
+    pub fn trace(
+        tracer: &PathTracer,
+        total: usize,
+        (time0, time1): (f64, f64),
+        seed: u64,
+        iteration: u64,
+        threads: usize,
+    ) -> (Self, Counts) {
+        let seed = splitmix64(seed ^ 0x70686f746f6e); //apart from the camera samples
+        let threads = threads.clamp(1, total.max(1));
+        let chunk = (total + threads - 1) / threads;
+        let mut photons = Vec::new();
+        let mut counts = Counts::default();
+        thread::scope(|scope| {
+            let handles: Vec<_> = (0..threads)
+                .map(|thread| {
+                    scope.spawn(move || {
+                        let mut sampler = IndependentSampler::new(seed);
+                        let mut photons = Vec::new();
+                        for index in thread * chunk..((thread + 1) * chunk).min(total) {
+                            sampler.start_sample(index as u64, iteration);
+                            let time = time0 + sampler.get_1d() * (time1 - time0);
+                            trace_photon(tracer, total, time, &mut sampler, &mut photons);
+                        }
+                        (photons, take_counts())
+                    })
+                })
+                .collect();
+            // in the order of the photons, whatever thread finishes first
+            for handle in handles {
+                let (chunk, chunk_counts) = handle.join().unwrap();
+                photons.extend(chunk);
+                counts += chunk_counts;
+            }
+        });
+        (Self::new(photons), counts)
+    }
+
+    pub fn for_each_within(&self, p: &Point3, radius: f64, mut f: impl FnMut(&Photon)) {
+        within(&self.photons, &self.axes, p, radius * radius, &mut f);
+    }
+
+    // distance from `p` to its `k`-th nearest photon, infinite if there are fewer
+    pub fn nearest_distance(&self, p: &Point3, k: usize) -> f64 {
+        // squared distances as bits, which order like the non-negative floats they are
+        let mut nearest = BinaryHeap::with_capacity(k + 1);
+        nearest_k(&self.photons, &self.axes, p, k, &mut nearest);
+        if nearest.len() < k || k == 0 {
+            INFINITY
+        } else {
+            f64::from_bits(*nearest.peek().unwrap()).sqrt()
+        }
+    }
+
+    // the radius that reaches RADIUS_NEIGHBOURS photons around the median photon
+    pub fn typical_radius(&self) -> f64 {
+        let n = self.photons.len();
+        if n <= RADIUS_NEIGHBOURS {
+            return INFINITY;
+        }
+        let step = (n / RADIUS_PHOTONS).max(1);
+        let mut distances: Vec<f64> = (0..n)
+            .step_by(step)
+            .map(|i| self.nearest_distance(&self.photons[i].p, RADIUS_NEIGHBOURS + 1)) //itself
+            .collect();
+        let mid = distances.len() / 2;
+        *distances.select_nth_unstable_by(mid, f64::total_cmp).1
+    }
+}
+
+// photon mapping (Jensen 1996): a camera path follows the specular bounces to the first vertex
+// with a pdf, which gets the light coming straight from the lights by sampling them, and the
+// light that bounced on the way from the photons within `radius`, by density estimation.
+// the estimate is blurred by the radius; progressive photon mapping shrinks it with every map,
+// so the average of the estimates converges
+pub struct PhotonMapper<'a, 'p> {
+    pub tracer: PathTracer<'a>,
+    pub photons: &'p PhotonMap,
+    pub radius: f64,
+}
+
+impl<'a> PhotonMapper<'a, '_> {
+    pub fn ray_color(
+        &self,
+        r: &Ray,
+        (u, v): (f64, f64),
+        sampler: &mut dyn Sampler,
+    ) -> PathSample<'a> {
+        let tracer = &self.tracer;
+        let mut sample = PathSample::default();
+        count(Counter::Paths);
+        let mut beta = Color::new(1., 1., 1.);
+        let mut ray = *r;
+        // the density the ray was sampled with in a medium, where lights are sampled too
+        let mut scatter_pdf: Option<f64> = None;
+        for bounce in 0..tracer.max_depth {
+            let depth = tracer.max_depth - bounce;
+            // light reaching the camera from the first vertex is direct
+            let direct = bounce <= 1;
+            count(Counter::Rays);
+            let (object, rec, medium) = match interact(tracer.world, &ray, 0.001, sampler) {
+                Some(interaction) => (interaction.object, interaction.rec, interaction.medium),
+                None => {
+                    let background = beta * tracer.background(&ray, depth, (u, v));
+                    sample.add(background, direct, None);
+                    break;
+                }
+            };
+            let hit = Some((object, &rec));
+            count(Counter::PathVertices);
+            let mut emitted = rec.mat_ptr.emitted(&ray, &rec, rec.u, rec.v, &rec.p);
+            if let Some(pdf) = scatter_pdf {
+                let light_pdf = tracer
+                    .lights
+                    .pdf_value(ray.origin_borrow(), ray.direction_borrow());
+                emitted *= tracer.heuristic.weight(pdf, light_pdf);
+            }
+            sample.add(beta * emitted, direct, hit);
+
+            let mut srec = ScatterRecord::default();
+            if !rec.mat_ptr.scatter(&ray, &rec, &mut srec, sampler) {
+                break;
+            }
+            let pdf_ptr = match srec.pdf_ptr {
+                Some(pdf_ptr) => pdf_ptr,
+                None => {
+                    beta *= srec.attenuation;
+                    ray = srec.scattered;
+                    scatter_pdf = None;
+                    sample.check(&beta, hit);
+                    continue;
+                }
+            };
+            if depth == 1 {
+                break;
+            }
+            beta *= srec.attenuation;
+
+            // in a medium the photons on the surfaces say nothing: the path goes on, by the
+            // phase function, and the lights are sampled on the way
+            if medium {
+                self.sample_light(
+                    &ray,
+                    &rec,
+                    beta,
+                    Some(pdf_ptr.as_ref()),
+                    bounce == 0,
+                    &mut sample,
+                    sampler,
+                );
+                let scattered = Ray::new(&rec.p, &pdf_ptr.generate(sampler).unit(), ray.time());
+                let pdf_val = pdf_ptr.value(scattered.direction_borrow());
+                beta *= rec.mat_ptr.scattering_pdf(&ray, &rec, &scattered) / pdf_val;
+                ray = scattered;
+                scatter_pdf = Some(pdf_val);
+                sample.check(&beta, hit);
+                continue;
+            }
+            self.sample_light(&ray, &rec, beta, None, bounce == 0, &mut sample, sampler);
+
+            // f·Φ of the photons over the area they were gathered from; a photon that reached
+            // the other side of the surface does not light this one
+            let mut flux = Color::default();
+            self.photons.for_each_within(&rec.p, self.radius, |photon| {
+                let incoming = Ray::new(&rec.p, &-photon.dir, ray.time());
+                let cos = dot(&rec.normal, &-photon.dir);
+                if cos > 0. {
+                    flux += photon.power * rec.mat_ptr.scattering_pdf(&ray, &rec, &incoming) / cos;
+                }
+            });
+            let area = PI * self.radius * self.radius;
+            sample.add(beta * flux / area, false, hit);
+            break;
+        }
+        sample
+    }
+
+    // the light reaching the vertex `rec` straight from a point sampled on the lights; weighed
+    // against sampling its direction with `scatter_pdf`, if the path goes on that way
+    fn sample_light(
+        &self,
+        ray: &Ray,
+        rec: &HitRecord<'a>,
+        beta: Color,
+        scatter_pdf: Option<&dyn PDF>,
+        direct: bool,
+        sample: &mut PathSample<'a>,
+        sampler: &mut dyn Sampler,
+    ) {
+        let tracer = &self.tracer;
+        if tracer.lights.is_empty() {
+            return;
+        }
+        let light_ptr = HittablePDF::new(tracer.lights, &rec.p);
+        let shadow = Ray::new(&rec.p, &light_ptr.generate(sampler).unit(), ray.time());
+        let light_pdf = light_ptr.value(shadow.direction_borrow());
+        if light_pdf <= 0. {
+            return;
+        }
+        count(Counter::Rays);
+        count(Counter::ShadowRays);
+        if let Some((light, light_rec)) = tracer.world.hit_object(&shadow, 0.001, INFINITY) {
+            let le = light_rec.mat_ptr.emitted(
+                &shadow,
+                &light_rec,
+                light_rec.u,
+                light_rec.v,
+                &light_rec.p,
+            ) * transmittance(tracer.world, &shadow, 0.001, light_rec.t, sampler);
+            let weight = scatter_pdf.map_or(1., |pdf| {
+                let pdf = pdf.value(shadow.direction_borrow());
+                tracer.heuristic.weight(light_pdf, pdf)
+            });
+            let contribution =
+                beta * rec.mat_ptr.scattering_pdf(ray, rec, &shadow) * (weight / light_pdf);
+            sample.check(&contribution, None);
+            sample.add(contribution * le, direct, Some((light, &light_rec)));
+        }
+    }
+}
+
+// radius of the `iteration`-th (from 0) estimate of progressive photon mapping, from the first one
+pub fn sppm_radius(radius: f64, iteration: u32) -> f64 {
+    let shrink: f64 = (1..=iteration)
+        .map(|i| (i as f64 + SPPM_ALPHA) / (i as f64 + 1.))
+        .product();
+    radius * shrink.sqrt()
+}
+
+// a photon is left at every surface with a pdf but the first one, whose light the camera side
+// gets from sampling the lights; in media the photons only scatter
+fn trace_photon(
+    tracer: &PathTracer,
+    total: usize,
+    time: f64,
+    sampler: &mut dyn Sampler,
+    photons: &mut Vec<Photon>,
+) {
+    let light = match LightPoint::sample(tracer.lights, time, sampler) {
+        Some(light) => light,
+        None => return,
+    };
+    let dir = light.sample_direction(sampler);
+    let cos = dot(&dir, &light.normal).abs();
+    let power = light.emitted(&dir) * (cos / (light.pdf * light.emission_pdf(&dir) * total as f64));
+    let mut beta = Color::new(1., 1., 1.);
+    let mut ray = Ray::new(&light.p, &dir, time);
+    for bounce in 0..tracer.max_depth {
+        count(Counter::Rays);
+        let (rec, medium) = match interact(tracer.world, &ray, 0.001, sampler) {
+            Some(interaction) => (interaction.rec, interaction.medium),
+            None => break,
+        };
+        let mut srec = ScatterRecord::default();
+        if !rec.mat_ptr.scatter(&ray, &rec, &mut srec, sampler) {
+            break;
+        }
+        match srec.pdf_ptr {
+            None => {
+                beta *= srec.attenuation;
+                ray = srec.scattered;
+            }
+            Some(pdf_ptr) => {
+                if bounce > 0 && !medium {
+                    photons.push(Photon {
+                        p: rec.p,
+                        dir: ray.direction_borrow().unit(),
+                        power: power * beta,
+                    });
+                }
+                let scattered = Ray::new(&rec.p, &pdf_ptr.generate(sampler).unit(), time);
+                let pdf_val = pdf_ptr.value(scattered.direction_borrow());
+                beta *=
+                    srec.attenuation * rec.mat_ptr.scattering_pdf(&ray, &rec, &scattered) / pdf_val;
+                ray = scattered;
+            }
+        }
+        // Russian roulette, like the camera paths
+        if bounce + 1 >= tracer.rr_depth {
+            let survival = beta.x.max(beta.y).max(beta.z);
+            if survival < 1. {
+                if sampler.get_1d() >= survival {
+                    break;
+                }
+                beta /= survival;
+            }
+        }
+    }
+}
+
+fn build(photons: &mut [Photon], axes: &mut [u8]) {
+    if photons.len() <= 1 {
+        return;
+    }
+    let (mut min, mut max) = (Vec3::grayscale(INFINITY), Vec3::grayscale(-INFINITY));
+    for photon in photons.iter() {
+        for c in 0..3 {
+            min[c] = min[c].min(photon.p[c]);
+            max[c] = max[c].max(photon.p[c]);
+        }
+    }
+    let extent = max - min;
+    let axis = if extent.x >= extent.y && extent.x >= extent.z {
+        0
+    } else if extent.y >= extent.z {
+        1
+    } else {
+        2
+    };
+    let mid = photons.len() / 2;
+    photons.select_nth_unstable_by(mid, |a, b| a.p[axis].total_cmp(&b.p[axis]));
+    axes[mid] = axis as u8;
+    let (left, right) = photons.split_at_mut(mid);
+    let (left_axes, right_axes) = axes.split_at_mut(mid);
+    build(left, left_axes);
+    build(&mut right[1..], &mut right_axes[1..]);
+}
+
+fn within(
+    photons: &[Photon],
+    axes: &[u8],
+    p: &Point3,
+    radius_squared: f64,
+    f: &mut impl FnMut(&Photon),
+) {
+    if photons.is_empty() {
+        return;
+    }
+    let mid = photons.len() / 2;
+    let photon = &photons[mid];
+    if (photon.p - *p).length_squared() <= radius_squared {
+        f(photon);
+    }
+    let axis = axes[mid] as usize;
+    let d = p[axis] - photon.p[axis];
+    let (left, right) = (0..mid, mid + 1..photons.len());
+    let (near, far) = if d <= 0. {
+        (left, right)
+    } else {
+        (right, left)
+    };
+    within(&photons[near.clone()], &axes[near], p, radius_squared, f);
+    if d * d <= radius_squared {
+        within(&photons[far.clone()], &axes[far], p, radius_squared, f);
+    }
+}
+
+// keeps the squared distances of the `k` photons nearest to `p` in `nearest`, farthest on top
+fn nearest_k(photons: &[Photon], axes: &[u8], p: &Point3, k: usize, nearest: &mut BinaryHeap<u64>) {
+    if photons.is_empty() || k == 0 {
+        return;
+    }
+    let mid = photons.len() / 2;
+    let photon = &photons[mid];
+    let distance_squared = (photon.p - *p).length_squared();
+    if nearest.len() < k {
+        nearest.push(distance_squared.to_bits());
+    } else if distance_squared < f64::from_bits(*nearest.peek().unwrap()) {
+        nearest.pop();
+        nearest.push(distance_squared.to_bits());
+    }
+    let axis = axes[mid] as usize;
+    let d = p[axis] - photon.p[axis];
+    let (left, right) = (0..mid, mid + 1..photons.len());
+    let (near, far) = if d <= 0. {
+        (left, right)
+    } else {
+        (right, left)
+    };
+    nearest_k(&photons[near.clone()], &axes[near], p, k, nearest);
+    if nearest.len() < k || d * d < f64::from_bits(*nearest.peek().unwrap()) {
+        nearest_k(&photons[far.clone()], &axes[far], p, k, nearest);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        integrator::{tests::lit_floor, MisHeuristic},
+        texture::SolidColor,
+    };
+
+    // the kd-tree finds what looking at every photon finds
+    #[test]
+    fn map_finds_the_nearby_photons() {
+        let mut rng = Rng::new(2);
+        let photons: Vec<Photon> = (0..2000)
+            .map(|_| Photon {
+                p: Point3::new(rng.random(), rng.random(), rng.random() * 0.1),
+                dir: Vec3::new(0., 0., -1.),
+                power: Color::new(1., 1., 1.),
+            })
+            .collect();
+        let map = PhotonMap::new(photons.clone());
+        for _ in 0..50 {
+            let p = Point3::new(rng.random(), rng.random(), 0.05);
+            let mut distances: Vec<f64> = photons.iter().map(|q| (q.p - p).length()).collect();
+            distances.sort_by(f64::total_cmp);
+            let mut found = 0;
+            map.for_each_within(&p, 0.05, |_| found += 1);
+            assert_eq!(found, distances.iter().filter(|&&d| d < 0.05).count());
+            assert!((map.nearest_distance(&p, 10) - distances[9]).abs() < 1e-12);
+        }
+        assert_eq!(map.nearest_distance(&Point3::default(), 2001), INFINITY);
+    }
+
+    // the light bounced between a floor and a ceiling, gathered from photons, is what paths bring
+    // back; the radius of progressive photon mapping shrinks so that the bias goes away
+    #[test]
+    fn photons_carry_the_bounced_light() {
+        let (world, lights) = lit_floor(0.9, Some(0.9));
+        let black = SolidColor::new(&Color::default());
+        let tracer = PathTracer {
+            world: &world,
+            lights: &lights,
+            background: &black,
+            max_depth: 12,
+            rr_depth: 12,
+            heuristic: MisHeuristic::Power,
+        };
+        let r = Ray::new(&Point3::new(0., 1., -3.), &Vec3::new(0., -1., 3.), 0.);
+        let mean = |f: &mut dyn FnMut(&mut IndependentSampler) -> Color| {
+            let mut sampler = IndependentSampler::new(4);
+            let n = 20000;
+            let mut sum = Color::default();
+            for index in 0..n {
+                sampler.start_sample(0, index);
+                sum += f(&mut sampler);
+            }
+            (sum / n as f64).y
+        };
+        let expected = mean(&mut |sampler| tracer.ray_color(&r, (0.5, 0.5), sampler).color());
+
+        let (photons, _) = PhotonMap::trace(&tracer, 100000, (0., 1.), 5, 0, 2);
+        let mapper = PhotonMapper {
+            tracer,
+            photons: &photons,
+            radius: 0.1,
+        };
+        let estimate = mean(&mut |sampler| mapper.ray_color(&r, (0.5, 0.5), sampler).color());
+        assert!((estimate - expected).abs() < 0.05 * expected);
+
+        let radii: Vec<f64> = (0..4).map(|i| sppm_radius(1., i)).collect();
+        assert_eq!(radii[0], 1.);
+        assert!(radii.windows(2).all(|w| w[1] < w[0]));
+        assert!((radii[1] * radii[1] - (1. + SPPM_ALPHA) / 2.).abs() < 1e-12);
+    }
+}
@@ -0,0 +1,68 @@
+use crate::{hittable::*, sampler::Sampler, utility::*};
+
+// where the light travelling along a ray first interacts with the scene: a surface, or a
+// collision in a medium, whose record has the phase function of the medium as its material and
+// a normal facing the ray
+pub struct Interaction<'a> {
+    pub object: usize, //index of the top-level object
+    pub rec: HitRecord<'a>,
+    pub medium: bool,
+}
+
+// the first interaction along `r` past t_min: the surface it hits, unless the light collides
+// first in one of the media on the way. the media are independent, so the first collision is the
+// earliest of those each samples
+pub fn interact<'a>(
+    world: &'a HittableList,
+    r: &Ray,
+    t_min: f64,
+    sampler: &mut dyn Sampler,
+) -> Option<Interaction<'a>> {
+    let hit = world.hit_object(r, t_min, INFINITY);
+    let t_max = hit.as_ref().map_or(INFINITY, |(_, rec)| rec.t);
+    let mut collision: Option<(f64, usize, &dyn Medium)> = None;
+    for (object, segment) in world.media_objects(r, t_min, t_max) {
+        let t1 = collision.map_or(segment.t1, |(t, _, _)| t.min(segment.t1));
+        if let Some(t) = segment.medium.sample_collision(r, segment.t0, t1, sampler) {
+            collision = Some((t, object, segment.medium));
+        }
+    }
+    match collision {
+        Some((t, object, medium)) => {
+            let mut rec = HitRecord::new(medium.phase());
+            rec.t = t;
+            rec.p = r.at(t);
+            rec.normal = -r.direction_borrow().unit();
+            rec.front_face = true;
+            Some(Interaction {
+                object,
+                rec,
+                medium: true,
+            })
+        }
+        None => hit.map(|(object, rec)| Interaction {
+            object,
+            rec,
+            medium: false,
+        }),
+    }
+}
+
+// share of the light that crosses the media along `r` from t_min to t_max
+pub fn transmittance(
+    world: &HittableList,
+    r: &Ray,
+    t_min: f64,
+    t_max: f64,
+    sampler: &mut dyn Sampler,
+) -> f64 {
+    world
+        .media_objects(r, t_min, t_max)
+        .iter()
+        .map(|(_, segment)| {
+            segment
+                .medium
+                .transmittance(r, segment.t0, segment.t1, sampler)
+        })
+        .product()
+}
@@ -1,126 +1,119 @@
 #![allow(unused, clippy::all)]
 use console::style;
-use image::{ImageBuffer, RgbImage};
-use indicatif::{MultiProgress, ProgressBar, ProgressStyle};
-use rand::prelude::SliceRandom;
+use indicatif::{ProgressBar, ProgressStyle};
 use std::{
-    fs::File,
+    path::{Path, PathBuf},
     process::exit,
     sync::mpsc,
-    thread::{self, JoinHandle},
+    thread,
+    time::Instant,
 };
 
 use crate::{
-    camera::*, hittable::*, material::ScatterRecord, my_scene::*, pdf::*, scene::*, texture::*,
-    utility::*,
+    camera::*, cli::Args, hittable::*, integrator::*, material::ScatterRecord, pdf::*, render::*,
+    sampler::*, scene::*, texture::*, utility::*,
 };
+use clap::Parser;
 
 pub mod camera;
+pub mod cli;
 pub mod hittable;
+pub mod integrator;
 pub mod material;
 pub mod obj_loader;
 pub mod pdf;
+pub mod render;
+pub mod sampler;
 pub mod scene;
 pub mod texture;
 pub mod utility;
 
-const MAX_DEPTH: i32 = 20;
-const THREAD_NUM: usize = 14;
-const BATCH_SIZE: u32 = 4; // optimize progress bar
-
 fn main() {
-    let path = std::path::Path::new("output/test/test5.jpg");
+    let args = Args::parse();
+    args.validate();
+    let start = Instant::now();
+    let mut timings = Timings::default();
+
+    // a checkpoint to resume, which has the seed of the render when none is given
+    let resumed = args
+        .checkpoint
+        .as_deref()
+        .filter(|c| args.resume && c.exists())
+        .map(|checkpoint| {
+            let film = Film::load(checkpoint).unwrap_or_else(|e| {
+                eprintln!("{}: {}", style("Cannot load the checkpoint").red(), e);
+                exit(1);
+            });
+            (checkpoint, film)
+        });
+
+    // every sample derives its generator from this seed; the scene is built on this thread,
+    // so seed it before any random layout is generated
+    let seed = args
+        .seed
+        .or(resumed.as_ref().map(|(_, film)| film.seed))
+        .unwrap_or_else(|| Rng::from_entropy().next_u64());
+    utility::seed(seed);
+
+    // World & Camera
+    let mut config = load_scene(&args.scene).unwrap_or_else(|e| {
+        eprintln!("{}", style(e).red());
+        exit(1);
+    });
+    timings.scene = start.elapsed().as_secs_f64();
+    timings.bvh = take_counts().bvh_build_nanos as f64 * 1e-9;
+    args.apply(&mut config);
+    let scene_hash = fnv1a(&serde_json::to_vec(&config.describe()).unwrap());
+    if let Some(export) = &args.export {
+        save_scene_file(&config, export).unwrap_or_else(|e| {
+            eprintln!("{}", style(e).red());
+            exit(1);
+        });
+        println!("Scene exported to {}", style(export.display()).green());
+        return;
+    }
+
+    let path = args.output.as_path();
     let prefix = path.parent().unwrap();
     std::fs::create_dir_all(prefix).expect("Cannot create all parent directories");
 
     // Image
-    let mut aspect_ratio: f64 = 1.;
-    let width: u32;
-    let samples_per_pixel: i32;
     let time0 = 0.;
     let time1 = 1.;
-    let quality: u8 = 100;
-
-    // World & Camera
-    let lookfrom;
-    let lookat;
-    let vfov;
-    let mut aperture = 0.;
-    let mut background: Box<dyn Texture> = Box::new(SolidColor::new(&Color::default()));
-
-    let world;
-    let mut lights = HittableList::default();
-    match 1 {
-        1 => {
-            (world, lights) = scifi1();
-            aspect_ratio = 16. / 9.;
-            width = 3200;
-            samples_per_pixel = 2000;
-            lookfrom = Point3::new(0., 0., 10.);
-            lookat = Point3::default();
-            // background = Box::new(SolidColor::new(&Color::new(0.70, 0.80, 1.00)));
-            background = Box::new(ImageTexture::new("image/milky_way.png"));
-            aperture = 0.1;
-            vfov = 40.;
-        }
-        // 2 => {
-        //     world = test1();
-        //     aspect_ratio = 16. / 9.;
-        //     width = 600 / 2;
-        //     samples_per_pixel = 100 * 2;
-        //     lookfrom = Point3::new(13., 2., 3.);
-        //     lookat = Point3::default();
-        //     background = Box::new(SolidColor::new(&Color::new(0.70, 0.80, 1.00)));
-        //     // background = Box::new(ImageTexture::new("image/stars.png"));
-        //     aperture = 0.1;
-        //     vfov = 20.;
-        //     // lookfrom = Point3::new(0., 0., 1000.);
-        //     // lookat = Point3::default();
-        //     // background = Box::new(SolidColor::new(&Color::new(1.00, 1.00, 1.00)));
-        //     // // background = Box::new(ImageTexture::new("image/milky_way.png"));
-        //     // vfov = 45.;
-        // }
-        _ => {
-            match 1 {
-                1 => {
-                    world = random_scene();
-                    // aspect_ratio = 16. / 9.;
-                    // width = 400;
-                    // samples_per_pixel = 100;
-                    aspect_ratio = 3. / 2.;
-                    width = 1200;
-                    samples_per_pixel = 500;
-                    lookfrom = Point3::new(13., 2., 3.);
-                    lookat = Point3::default();
-                    background = Box::new(SolidColor::new(&Color::new(0.70, 0.80, 1.00)));
-                    aperture = 0.1;
-                    vfov = 20.;
-                }
-                2 => {
-                    (world, lights) = cornell_box();
-                    width = 600;
-                    samples_per_pixel = 200;
-                    lookfrom = Point3::new(278., 278., -800.);
-                    lookat = Point3::new(278., 278., 0.);
-                    vfov = 40.;
-                }
-                _ => {
-                    (world, lights) = final_scene();
-                    width = 800;
-                    samples_per_pixel = 100;
-                    lookfrom = Point3::new(478., 278., -600.);
-                    lookat = Point3::new(278., 278., 0.);
-                    vfov = 40.;
-                }
-            }
-        }
-    }
 
-    let height: u32 = (width as f64 / aspect_ratio) as u32;
-    let mut img: RgbImage = ImageBuffer::new(width, height);
+    let SceneConfig {
+        world,
+        lights,
+        background,
+        aspect_ratio,
+        width,
+        height,
+        samples_per_pixel,
+        lookfrom,
+        lookat,
+        vup,
+        vfov,
+        aperture,
+        focus_dist,
+    } = config;
+    let height = height.unwrap_or_else(|| ((width as f64 / aspect_ratio) as u32).max(2));
+    let max_depth = args.max_depth;
+    let thread_num = args.threads();
+    // the filter spreads samples over the neighbours of their pixel, so the pixels around the crop
+    // are rendered too, for the crop to match the whole image
+    let filter = args.filter();
+    let crop = args.crop(width, height);
+    let full = Tile {
+        index: 0,
+        x0: 0,
+        y0: 0,
+        x1: width,
+        y1: height,
+    };
+    let output_crop = crop.filter(|_| !args.full_frame);
+    let region = crop.map_or(full, |crop| crop.expand(filter.padding(), width, height));
+    let crop = crop.unwrap_or(full);
 
-    let vup = Vec3::new(0., 1., 0.);
-    let dist_to_focus = 10.;
     let cam = Camera::new(
         &lookfrom,
         &lookat,
@@ -128,172 +121,943 @@ fn main() {
         vfov,
         aspect_ratio,
         aperture,
-        dist_to_focus,
+        focus_dist,
     );
-    // Progress Bar
-    let multi_progress = MultiProgress::new();
-
-    // Render
-
-    let mut threads: Vec<JoinHandle<()>> = Vec::new();
-    let mut task_list: Vec<Vec<(u32, u32)>> = vec![Vec::new(); THREAD_NUM];
-    let mut receiver_list = Vec::new();
-    let mut k = 0;
-    for j in 0..height {
-        for i in 0..width {
-            task_list[k].push((i, j));
-            k = (k + 1) % THREAD_NUM;
-        }
-    }
-    for task in task_list.iter_mut() {
-        task.shuffle(&mut rand::thread_rng());
+    let ctx = RenderContext {
+        cam,
+        world,
+        lights,
+        background,
+        width,
+        height,
+        max_depth,
+        rr_depth: args.rr_depth,
+        integrator: args.integrator,
+        photons: args.photons as usize,
+        photon_radius: args.photon_radius,
+        heuristic: args.mis,
+        clamp: args.radiance_clamp(),
+        sampler: args.sampler,
+        samples_per_pixel,
+        filter,
+        crop,
+        region,
+        time0,
+        time1,
+    };
+    let photon_mapping = matches!(
+        ctx.integrator,
+        IntegratorKind::Photon | IntegratorKind::Sppm
+    );
+    if photon_mapping && ctx.lights.is_empty() {
+        println!(
+            "{}: only what the camera sees through specular bounces is lit",
+            style("The scene has no lights to emit photons from").yellow()
+        );
     }
 
-    let world = Arc::new(world);
-    let lights = Arc::new(lights);
-    let background = Arc::from(background);
-
-    for task in task_list {
-        let (tx, rx) = mpsc::channel();
-        receiver_list.push(rx);
-        let world_ = world.clone();
-        let lights_ = lights.clone();
-        // let background_ = background.clone();
-        let background_: Arc<dyn Texture> = Arc::clone(&background);
-        let progress_bar = multi_progress.add(ProgressBar::new(
-            (width * height / THREAD_NUM as u32 / BATCH_SIZE) as u64,
-        ));
+    // Progress Bar
+    // samples per pixel to reach, or u32::MAX when the time budget decides
+    let target_spp = args.target_spp(samples_per_pixel);
+    let progress_bar = if args.time_budget.is_some() {
+        let progress_bar = ProgressBar::new_spinner();
+        progress_bar.set_style(
+            ProgressStyle::default_spinner()
+                .template(" [{elapsed_precise}] {spinner} {pos} samples, {msg}"),
+        );
+        progress_bar
+    } else {
+        let progress_bar = ProgressBar::new(region.area() as u64 * samples_per_pixel as u64);
         progress_bar.set_style(
             ProgressStyle::default_bar()
-                .template(" [{elapsed_precise}] [{wide_bar:.cyan/blue}] [{pos}/{len}] ({eta})")
+                .template(" [{elapsed_precise}] [{wide_bar:.cyan/blue}] {percent}% {msg} ({eta})")
                 .progress_chars("#>-"),
         );
-        let handle = thread::spawn(move || {
-            let mut result = Vec::new();
-            let mut progress_count = 0;
-            for (i, j) in task {
-                let mut pixel_color = Color::default();
-                for _s in 0..samples_per_pixel {
-                    let u = ((i as f64) + random()) / ((width - 1) as f64);
-                    let v = ((j as f64) + random()) / ((height - 1) as f64);
-                    let ray = cam.get_ray(u, v, time0, time1);
-                    let mut color = ray_color(
-                        &ray,
-                        background_.as_ref(),
-                        world_.as_ref(),
-                        lights_.as_ref(),
-                        MAX_DEPTH,
-                        (u, v),
-                    );
-                    for _i in 0..3 {
-                        if color[_i].is_nan() {
-                            color[_i] = 0.;
-                        }
-                    }
-                    // TODO eliminate NaN, not just catch it
-                    pixel_color += color;
-                    // TODO pdf for generic material
-                }
-                pixel_color /= samples_per_pixel as f64;
-                for _i in 0..3 {
-                    pixel_color[_i] = clamp(pixel_color[_i].sqrt(), 0., 0.99);
-                }
-                pixel_color *= 256.;
-                result.push((i, j, pixel_color));
-                progress_count += 1;
-                if progress_count % BATCH_SIZE == 0 {
-                    progress_bar.inc(1);
+        progress_bar
+    };
+
+    // AOVs, from the camera rays of the first samples, before the image so every pass has them;
+    // the denoiser is guided by them
+    let aovs = (!args.aov.is_empty() || args.denoise.is_some()).then(|| {
+        progress_bar.set_message("AOVs");
+        let aovs_start = Instant::now();
+        let aovs = render_aovs(&ctx, seed, thread_num, args.tile_size);
+        timings.aovs = aovs_start.elapsed().as_secs_f64();
+        aovs
+    });
+    let output_start = Instant::now();
+    if let Some(aovs) = &aovs {
+        let cropped = output_crop.map(|crop| aovs.crop(&crop));
+        let aovs = cropped.as_ref().unwrap_or(aovs);
+        let format = args.output_format();
+        if format != OutputFormat::Exr {
+            // high-dynamic-range formats other than EXR get pictures of the layers as PNG
+            let extension = match format {
+                OutputFormat::Jpeg => "jpg",
+                OutputFormat::Ppm => "ppm",
+                _ => "png",
+            };
+            for &aov in &args.aov {
+                let output = aov_path(path, aov.name(), extension);
+                if let Err(e) = aovs.to_rgb8(aov).save(&output) {
+                    eprintln!("{}: {}", style("Failed to output the AOV").red(), e);
                 }
             }
-            tx.send(result).unwrap();
-            progress_bar.finish();
-        });
-        threads.push(handle);
-    }
-    multi_progress.join_and_clear().unwrap();
-
-    for receiver in receiver_list {
-        let result = receiver.recv().unwrap();
-        for (i, j, pixel_color) in result {
-            let (r, g, b) = pixel_color.to_tuple();
-            let pixel = img.get_pixel_mut(i, height - 1 - j);
-            *pixel = image::Rgb([r as u8, g as u8, b as u8]);
         }
     }
-    for thread in threads {
-        thread.join().unwrap();
+
+    timings.output += output_start.elapsed().as_secs_f64();
+
+    // Render
+    // in passes of `pass_spp` samples per pixel, writing the image after each pass
+    let pass_spp = args.pass_spp(samples_per_pixel);
+    let settings = checkpoint_settings(&args, &ctx, scene_hash, seed, pass_spp);
+    let mut film = Film::new(width, height);
+    let mut image = Framebuffer::new(width, height);
+    if let Some((checkpoint, resumed)) = resumed {
+        if resumed.settings != settings {
+            eprintln!(
+                "{}:\n{}\ninstead of\n{}",
+                style("The checkpoint is of a render with other settings").red(),
+                resumed.settings,
+                settings
+            );
+            exit(1);
+        }
+        film = resumed;
+        film.resolve_region(&crop, &mut image);
+        println!(
+            "Resuming from \"{}\" at {} samples per pixel",
+            style(checkpoint.display()).yellow(),
+            film.min_samples()
+        );
     }
+    film.seed = seed;
+    film.settings = settings;
+    // `--spp` is the average with adaptive sampling
+    let budget = region.area() as u64 * target_spp as u64;
+    progress_bar.inc(film.total_samples().min(budget));
+    progress_bar.reset_eta();
+    let adaptive = args.adaptive_sampling(samples_per_pixel);
+    let next_plan = |film: &Film| match &adaptive {
+        Some(adaptive) => adaptive.plan(film, &region, pass_spp, budget),
+        None => {
+            let mut plan = vec![0; film.samples.len()];
+            for (x, y) in region.pixels() {
+                let index = film.index(x, y);
+                plan[index] = pass_spp.min(target_spp.saturating_sub(film.samples[index]));
+            }
+            plan
+        }
+    };
+    // samples keep their own seeds, so a resumed or progressive render is the single-pass one
+    let first_pass = film.passes;
+    let mut diagnostics = Diagnostics::default();
+    let mut counts = Counts::default();
+    let mut plan = next_plan(&film);
+    while plan.iter().any(|&n| n > 0) {
+        let pass = film.passes;
+        progress_bar.set_message(format!("pass {}", pass + 1));
+        let pass_start = Instant::now();
+        match ctx.integrator {
+            IntegratorKind::Photon | IntegratorKind::Sppm => render_photon_pass(
+                &ctx,
+                &mut film,
+                &mut image,
+                &mut diagnostics,
+                &mut counts,
+                &plan,
+                seed,
+                thread_num,
+                args.tile_size,
+                &progress_bar,
+            ),
+            _ => render_pass(
+                &ctx,
+                &mut film,
+                &mut image,
+                &mut diagnostics,
+                &mut counts,
+                &plan,
+                seed,
+                thread_num,
+                args.tile_size,
+                &progress_bar,
+                None,
+            ),
+        }
+        film.passes += 1;
+        timings.render += pass_start.elapsed().as_secs_f64();
+        let output_start = Instant::now();
 
+        if let Some(checkpoint) = &args.checkpoint {
+            if let Err(e) = film.save(checkpoint) {
+                progress_bar.println(format!(
+                    "{}: {}",
+                    style("Failed to save the checkpoint").red(),
+                    e
+                ));
+            }
+        }
+        plan = next_plan(&film);
+        // with a time budget, a pass that would not end in time is not started
+        if args.out_of_time(start.elapsed(), pass_start.elapsed()) {
+            plan = vec![0; plan.len()];
+        }
+        let last = !plan.iter().any(|&n| n > 0);
+        let output = if args.snapshots && !last {
+            snapshot_path(path, pass + 1)
+        } else {
+            path.to_path_buf()
+        };
+        let written = write_output(
+            &film,
+            &image,
+            aovs.as_ref(),
+            &args,
+            output_crop.as_ref(),
+            &output,
+            last,
+        );
+        timings.output += output_start.elapsed().as_secs_f64();
+        if let Err(e) = written {
+            progress_bar.println(format!("{}: {}", style("Failed to output image").red(), e));
+        } else if !last {
+            progress_bar.println(format!(
+                "Pass {} written to \"{}\"",
+                pass + 1,
+                style(output.display()).yellow()
+            ));
+        }
+    }
+    // nothing left to render, but the image may not have been written before the interruption
+    let output_start = Instant::now();
+    if film.passes == first_pass {
+        if let Err(e) = write_output(
+            &film,
+            &image,
+            aovs.as_ref(),
+            &args,
+            output_crop.as_ref(),
+            path,
+            true,
+        ) {
+            eprintln!("{}: {}", style("Failed to output image").red(), e);
+        }
+    }
+    if let Some(heatmap) = &args.spp_heatmap {
+        let mut image = spp_heatmap(&film);
+        if let Some(crop) = &output_crop {
+            image =
+                image::imageops::crop_imm(&image, crop.x0, crop.y0, crop.width(), crop.height())
+                    .to_image();
+        }
+        if let Err(e) = image.save(heatmap) {
+            eprintln!("{}: {}", style("Failed to output the heatmap").red(), e);
+        }
+    }
+    timings.output += output_start.elapsed().as_secs_f64();
+    timings.total = start.elapsed().as_secs_f64();
+    progress_bar.finish_and_clear();
+    if args.time_budget.is_some() {
+        println!(
+            "Rendered {} samples per pixel in {:.1} s",
+            style(format!(
+                "{:.1}",
+                film.total_samples() as f64 / region.area() as f64
+            ))
+            .green(),
+            start.elapsed().as_secs_f64()
+        );
+    }
+    if diagnostics.total() > 0 {
+        if args.diagnostics {
+            diagnostics.report(&ctx.world, width);
+        } else {
+            println!(
+                "{} samples were NaN, infinite or negative, see --diagnostics",
+                style(diagnostics.total()).red()
+            );
+        }
+    } else if args.diagnostics {
+        println!("No invalid samples");
+    }
+    if args.stats || args.stats_json.is_some() {
+        let stats = RenderStats::new(
+            args.scene.clone(),
+            (width, height),
+            samples_per_pixel,
+            thread_num,
+            timings,
+            counts,
+        );
+        if args.stats {
+            stats.print();
+        }
+        if let Some(json) = &args.stats_json {
+            if let Err(e) = stats.save(json) {
+                eprintln!("{}: {}", style("Failed to output the statistics").red(), e);
+            }
+        }
+    }
     println!(
         "Output image as \"{}\"",
         style(path.to_str().unwrap()).yellow()
     );
-    let output_image = image::DynamicImage::ImageRgb8(img);
-    let mut output_file = File::create(path).unwrap();
-    match output_image.write_to(&mut output_file, image::ImageOutputFormat::Jpeg(quality)) {
-        Ok(_) => {}
-        Err(_) => println!("{}", style("Failed to output image").red()),
-    }
 
     exit(0);
 }
 
-fn ray_color(
-    r: &Ray,
-    background: &dyn Texture,
-    world: &HittableList,
-    lights: &HittableList,
-    depth: i32,
-    (u, v): (f64, f64),
-) -> Color {
-    if depth <= 0 {
-        return Color::default();
+struct RenderContext {
+    cam: Camera,
+    world: HittableList,
+    lights: HittableList,
+    background: Box<dyn Texture>,
+    width: u32,
+    height: u32,
+    max_depth: i32,
+    rr_depth: i32,
+    integrator: IntegratorKind,
+    photons: usize,
+    photon_radius: Option<f64>,
+    heuristic: MisHeuristic,
+    clamp: Option<RadianceClamp>,
+    sampler: SamplerKind,
+    samples_per_pixel: u32,
+    filter: Filter,
+    crop: Tile,   //pixels of the image, the rest left black
+    region: Tile, //pixels to render: the crop and the pixels whose samples the filter spreads to it
+    time0: f64,
+    time1: f64,
+}
+
+// what the samples of a render depend on, which a checkpoint must have been rendered with
+fn checkpoint_settings(
+    args: &Args,
+    ctx: &RenderContext,
+    scene_hash: u64,
+    seed: u64,
+    pass_spp: u32,
+) -> String {
+    let crop = &ctx.crop;
+    [
+        format!("scene {:016x}", scene_hash),
+        format!("seed {}", seed),
+        format!("resolution {}x{}", ctx.width, ctx.height),
+        format!("crop {} {} {} {}", crop.x0, crop.y0, crop.x1, crop.y1),
+        format!(
+            "spp {}, {} per pass, adaptive {:?}",
+            args.target_spp(ctx.samples_per_pixel),
+            pass_spp,
+            args.adaptive_sampling(ctx.samples_per_pixel)
+        ),
+        format!("sampler {:?}", ctx.sampler),
+        format!("filter {:?}", ctx.filter),
+        format!(
+            "integrator {:?}, depth {} ({} before roulette), {:?} heuristic, clamp {:?}",
+            ctx.integrator, ctx.max_depth, ctx.rr_depth, ctx.heuristic, ctx.clamp
+        ),
+        format!("photons {}, radius {:?}", ctx.photons, ctx.photon_radius),
+    ]
+    .join("\n")
+}
+
+// add `plan[i]` samples to pixel i of the film
+fn render_pass<'a>(
+    ctx: &'a RenderContext,
+    film: &mut Film,
+    image: &mut Framebuffer, //the film resolved, kept up to date as tiles are added
+    diagnostics: &mut Diagnostics<'a>,
+    counts: &mut Counts,
+    plan: &[u32],
+    seed: u64,
+    thread_num: usize,
+    tile_size: u32,
+    progress_bar: &ProgressBar,
+    photons: Option<(&PhotonMap, f64)>, //with the radius to gather from
+) {
+    let (width, height) = (ctx.width, ctx.height);
+    let queue = TileQueue::new(width, height, tile_size, &ctx.region);
+    // samples the pixels had before the pass, to number the new ones
+    let offsets = film.samples.clone();
+    let (tx, rx) = mpsc::channel();
+    thread::scope(|scope| {
+        for _ in 0..thread_num.min(queue.len()) {
+            let tx = tx.clone();
+            let (queue, offsets) = (&queue, &offsets);
+            scope.spawn(move || {
+                let mut sampler = ctx.sampler.create(seed, ctx.samples_per_pixel);
+                let tracer = PathTracer {
+                    world: &ctx.world,
+                    lights: &ctx.lights,
+                    background: ctx.background.as_ref(),
+                    max_depth: ctx.max_depth,
+                    rr_depth: ctx.rr_depth,
+                    heuristic: ctx.heuristic,
+                };
+                let bdpt = Bdpt {
+                    world: &ctx.world,
+                    lights: &ctx.lights,
+                    background: ctx.background.as_ref(),
+                    camera: &ctx.cam,
+                    width,
+                    height,
+                    max_depth: ctx.max_depth,
+                    rr_depth: ctx.rr_depth,
+                    heuristic: ctx.heuristic,
+                };
+                let photon_mapper = photons.map(|(photons, radius)| PhotonMapper {
+                    tracer,
+                    photons,
+                    radius,
+                });
+                // of the current sample
+                let mut light_samples = Vec::new();
+                while let Some(tile) = queue.pop() {
+                    let mut result = Vec::with_capacity(tile.area() as usize);
+                    let mut splats = Splats::new(&tile, &ctx.filter, width, height);
+                    // where the light subpaths of the bidirectional integrator reach the camera
+                    let mut light = LightSplats::new(width, height, &ctx.crop);
+                    let mut faults = Diagnostics::default();
+                    for (i, y) in tile.pixels() {
+                        let j = height - 1 - y;
+                        let index = (y * width + i) as usize;
+                        let spp = plan[index];
+                        let mut pixel = PixelSamples {
+                            count: spp,
+                            ..Default::default()
+                        };
+                        for s in 0..spp {
+                            let sample = (offsets[index] + s) as u64;
+                            sampler.start_sample(index as u64, sample);
+                            let (du, dv) = sampler.get_2d();
+                            let u = ((i as f64) + du) / ((width - 1) as f64);
+                            let v = ((j as f64) + dv) / ((height - 1) as f64);
+                            let ray = ctx
+                                .cam
+                                .get_ray(u, v, ctx.time0, ctx.time1, sampler.as_mut());
+                            count(Counter::PrimaryRays);
+                            let path = match ctx.integrator {
+                                IntegratorKind::Path => {
+                                    tracer.ray_color(&ray, (u, v), sampler.as_mut())
+                                }
+                                IntegratorKind::Bdpt => {
+                                    bdpt.sample(&ray, (u, v), sampler.as_mut(), &mut light_samples)
+                                }
+                                IntegratorKind::Photon | IntegratorKind::Sppm => photon_mapper
+                                    .as_ref()
+                                    .unwrap()
+                                    .ray_color(&ray, (u, v), sampler.as_mut()),
+                            };
+                            let mut color = match &ctx.clamp {
+                                Some(clamp) => clamp.apply(&path),
+                                None => path.color(),
+                            };
+                            // invalid samples count as black, or lose their negative channels
+                            if let Some(fault) = path.fault {
+                                faults.add(index, fault);
+                                match FaultKind::of(&color) {
+                                    Some(FaultKind::Negative) => {
+                                        for c in 0..3 {
+                                            color[c] = color[c].max(0.);
+                                        }
+                                    }
+                                    Some(_) => color = Color::default(),
+                                    None => (),
+                                }
+                            }
+                            // the light paths are clamped and filtered like the camera paths
+                            for ((x, y), splat) in light_samples.drain(..) {
+                                let color = match &ctx.clamp {
+                                    Some(clamp) => clamp.apply(&splat),
+                                    None => splat.color(),
+                                };
+                                light.add(&ctx.filter, x, y, &color);
+                            }
+                            pixel.sum += color;
+                            // v grows upwards, the rows of the film downwards
+                            splats.add(&ctx.filter, i as f64 + du, (y + 1) as f64 - dv, &color);
+                            pixel.sum_sq += luminance(&color).powi(2);
+                            // TODO pdf for generic material
+                        }
+                        result.push(pixel);
+                    }
+                    tx.send((tile, result, splats, light, faults, take_counts()))
+                        .unwrap();
+                }
+            });
+        }
+        drop(tx);
+
+        // splats overlap the neighbouring tiles, so tiles are added to the film in a fixed order,
+        // to keep the sums independent of the threads: each as soon as those before it are
+        let mut done: Vec<_> = (0..queue.len()).map(|_| None).collect();
+        let mut next = 0;
+        for (tile, result, splats, light, faults, tile_counts) in rx {
+            diagnostics.merge(faults);
+            *counts += tile_counts;
+            progress_bar.inc(
+                result
+                    .iter()
+                    .map(|pixel: &PixelSamples| pixel.count as u64)
+                    .sum(),
+            );
+            done[tile.index] = Some((tile, result, splats, light));
+            while let Some((tile, result, splats, light)) =
+                done.get_mut(next).and_then(Option::take)
+            {
+                film.add_tile(&tile, &result, &splats);
+                film.add_light(&light);
+                film.resolve_region(&splats.region().intersection(&ctx.crop), image);
+                next += 1;
+            }
+        }
+    });
+}
+
+// photon mapping traces a photon map for every sample number, which the samples of that number
+// of all the pixels gather from, so its passes are rendered one sample number at a time
+fn render_photon_pass<'a>(
+    ctx: &'a RenderContext,
+    film: &mut Film,
+    image: &mut Framebuffer,
+    diagnostics: &mut Diagnostics<'a>,
+    counts: &mut Counts,
+    plan: &[u32],
+    seed: u64,
+    thread_num: usize,
+    tile_size: u32,
+    progress_bar: &ProgressBar,
+) {
+    let tracer = PathTracer {
+        world: &ctx.world,
+        lights: &ctx.lights,
+        background: ctx.background.as_ref(),
+        max_depth: ctx.max_depth,
+        rr_depth: ctx.rr_depth,
+        heuristic: ctx.heuristic,
+    };
+    let offsets = film.samples.clone();
+    let planned = || offsets.iter().zip(plan).filter(|&(_, &n)| n > 0);
+    let first = planned().map(|(&offset, _)| offset).min().unwrap_or(0);
+    let last = planned().map(|(&offset, &n)| offset + n).max().unwrap_or(0);
+    for number in first..last {
+        let numbered: Vec<u32> = offsets
+            .iter()
+            .zip(plan)
+            .map(|(&offset, &n)| (offset <= number && number < offset + n) as u32)
+            .collect();
+        let times = (ctx.time0, ctx.time1);
+        let (photons, photon_counts) =
+            PhotonMap::trace(&tracer, ctx.photons, times, seed, number as u64, thread_num);
+        *counts += photon_counts;
+        let radius = ctx
+            .photon_radius
+            .unwrap_or_else(|| photons.typical_radius());
+        let radius = match ctx.integrator {
+            IntegratorKind::Sppm => sppm_radius(radius, number),
+            _ => radius,
+        };
+        render_pass(
+            ctx,
+            film,
+            image,
+            diagnostics,
+            counts,
+            &numbered,
+            seed,
+            thread_num,
+            tile_size,
+            progress_bar,
+            Some((&photons, radius)),
+        );
     }
-    if let Some(rec) = world.hit(r, 0.001, INFINITY) {
-        let emitted = rec.mat_ptr.emitted(r, &rec, rec.u, rec.v, &rec.p);
-        let mut srec = ScatterRecord::default();
-        if rec.mat_ptr.scatter(r, &rec, &mut srec) {
-            if let Some(pdf_ptr) = srec.pdf_ptr {
-                if lights.is_empty() {
-                    let scattered = Ray::new(&rec.p, &pdf_ptr.generate().unit(), r.time());
-                    let pdf_val = pdf_ptr.value(scattered.direction_borrow());
-
-                    emitted
-                        + srec.attenuation
-                            * rec.mat_ptr.scattering_pdf(r, &rec, &scattered)
-                            * ray_color(&scattered, background, world, lights, depth - 1, (u, v))
-                            / pdf_val
-                } else {
-                    let light_ptr = HittablePDF::new(lights, &rec.p);
-                    let mixed_pdf = MixturePDF::new(&light_ptr, pdf_ptr.as_ref());
-                    let scattered = Ray::new(&rec.p, &mixed_pdf.generate().unit(), r.time());
-                    let pdf_val = mixed_pdf.value(scattered.direction_borrow());
-
-                    emitted
-                        + srec.attenuation
-                            * rec.mat_ptr.scattering_pdf(r, &rec, &scattered)
-                            * ray_color(&scattered, background, world, lights, depth - 1, (u, v))
-                            / pdf_val
+}
+
+// the AOVs have no fixed sample count, so they get the first samples of each pixel, at most
+const AOV_SPP: u32 = 64;
+
+fn render_aovs(ctx: &RenderContext, seed: u64, thread_num: usize, tile_size: u32) -> AovBuffer {
+    let (width, height) = (ctx.width, ctx.height);
+    let spp = ctx.samples_per_pixel.min(AOV_SPP);
+    let queue = TileQueue::new(width, height, tile_size, &ctx.region);
+    let mut aovs = AovBuffer::new(width, height);
+    let (tx, rx) = mpsc::channel();
+    thread::scope(|scope| {
+        for _ in 0..thread_num.min(queue.len()) {
+            let tx = tx.clone();
+            let queue = &queue;
+            scope.spawn(move || {
+                let mut sampler = ctx.sampler.create(seed, ctx.samples_per_pixel);
+                let mut materials = MaterialIds::default();
+                let tracer = PathTracer {
+                    world: &ctx.world,
+                    lights: &ctx.lights,
+                    background: ctx.background.as_ref(),
+                    max_depth: ctx.max_depth,
+                    rr_depth: ctx.rr_depth,
+                    heuristic: ctx.heuristic,
+                };
+                while let Some(tile) = queue.pop() {
+                    let mut result = Vec::with_capacity(tile.area() as usize);
+                    for (i, y) in tile.pixels() {
+                        let j = height - 1 - y;
+                        let index = (y * width + i) as usize;
+                        let mut pixel = AovPixel::default();
+                        let spp = if ctx.region.contains(i, y) { spp } else { 0 };
+                        // the same camera rays as the samples of the image
+                        for sample in 0..spp as u64 {
+                            sampler.start_sample(index as u64, sample);
+                            let (du, dv) = sampler.get_2d();
+                            let u = ((i as f64) + du) / ((width - 1) as f64);
+                            let v = ((j as f64) + dv) / ((height - 1) as f64);
+                            let ray = ctx
+                                .cam
+                                .get_ray(u, v, ctx.time0, ctx.time1, sampler.as_mut());
+                            pixel.add(&tracer, &ray, (u, v), &mut materials);
+                        }
+                        result.push(pixel);
+                    }
+                    tx.send((tile, result)).unwrap();
                 }
+            });
+        }
+        drop(tx);
+        for (tile, result) in rx {
+            aovs.set_tile(&tile, &result);
+        }
+    });
+    aovs
+}
+
+// the image, or its crop, and the denoised one next to it once the render is done
+fn write_output(
+    film: &Film,
+    image: &Framebuffer,
+    aovs: Option<&AovBuffer>,
+    args: &Args,
+    crop: Option<&Tile>,
+    path: &Path,
+    last: bool,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let mut framebuffer = image.clone();
+    film.add_light_paths(&mut framebuffer);
+    let mut variance = film.mean_variance();
+    let cropped;
+    let mut aovs = aovs;
+    if let Some(crop) = crop {
+        framebuffer = framebuffer.crop(crop);
+        variance = crop.crop(&variance, film.width);
+        cropped = aovs.map(|aovs| aovs.crop(crop));
+        aovs = cropped.as_ref();
+    }
+    write_layers(&framebuffer, aovs, args, path)?;
+    if let (Some(denoiser), Some(aovs), true) = (args.denoise, aovs, last) {
+        let denoised = denoise(denoiser, &framebuffer, &variance, aovs, args.threads());
+        write_layers(&denoised, Some(aovs), args, &denoised_path(path))?;
+    }
+    Ok(())
+}
+
+// the image, with the AOVs as layers when it is an EXR
+fn write_layers(
+    framebuffer: &Framebuffer,
+    aovs: Option<&AovBuffer>,
+    args: &Args,
+    path: &Path,
+) -> Result<(), Box<dyn std::error::Error>> {
+    match aovs {
+        Some(aovs) if args.output_format() == OutputFormat::Exr && !args.aov.is_empty() => {
+            let layers = args.aov.iter().flat_map(|&aov| aovs.channels(aov));
+            write_exr_layers(framebuffer, layers.collect(), path)
+        }
+        _ => write_image(
+            framebuffer,
+            path,
+            args.output_format(),
+            &args.color_pipeline(),
+        ),
+    }
+}
+
+// "dir/name.png" -> "dir/name.depth.png"
+fn aov_path(path: &Path, name: &str, extension: &str) -> PathBuf {
+    let stem = path.file_stem().unwrap().to_string_lossy();
+    path.with_file_name(format!("{}.{}.{}", stem, name, extension))
+}
+
+// "dir/name.png" -> "dir/name.denoised.png"
+fn denoised_path(path: &Path) -> PathBuf {
+    let stem = path.file_stem().unwrap().to_string_lossy();
+    let name = match path.extension() {
+        Some(ext) => format!("{}.denoised.{}", stem, ext.to_string_lossy()),
+        None => format!("{}.denoised", stem),
+    };
+    path.with_file_name(name)
+}
+
+// "dir/name.png" -> "dir/name_0003.png"
+fn snapshot_path(path: &Path, pass: u32) -> PathBuf {
+    let stem = path.file_stem().unwrap().to_string_lossy();
+    let name = match path.extension() {
+        Some(ext) => format!("{}_{:04}.{}", stem, pass, ext.to_string_lossy()),
+        None => format!("{}_{:04}", stem, pass),
+    };
+    path.with_file_name(name)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{env, fs, process};
+
+    // a small render of a builtin scene, of the whole image
+    fn context(scene: &str, size: u32, integrator: IntegratorKind) -> RenderContext {
+        utility::seed(1);
+        let config = load_scene(scene).unwrap();
+        let filter = Filter::new(FilterKind::Tent, None);
+        let full = Tile {
+            index: 0,
+            x0: 0,
+            y0: 0,
+            x1: size,
+            y1: size,
+        };
+        RenderContext {
+            cam: Camera::new(
+                &config.lookfrom,
+                &config.lookat,
+                &config.vup,
+                config.vfov,
+                1.,
+                config.aperture,
+                config.focus_dist,
+            ),
+            world: config.world,
+            lights: config.lights,
+            background: config.background,
+            width: size,
+            height: size,
+            max_depth: 8,
+            rr_depth: 3,
+            integrator,
+            photons: 2000,
+            photon_radius: None,
+            heuristic: MisHeuristic::Power,
+            clamp: None,
+            sampler: SamplerKind::Sobol,
+            samples_per_pixel: 4,
+            filter,
+            crop: full,
+            region: full,
+            time0: 0.,
+            time1: 1.,
+        }
+    }
+
+    fn render(ctx: &RenderContext, threads: usize) -> (Film, Framebuffer) {
+        let mut film = Film::new(ctx.width, ctx.height);
+        let mut image = Framebuffer::new(ctx.width, ctx.height);
+        render_next_pass(ctx, &mut film, &mut image, threads);
+        (film, image)
+    }
+
+    // a pass of `samples_per_pixel` samples more in every pixel
+    fn render_next_pass(
+        ctx: &RenderContext,
+        film: &mut Film,
+        image: &mut Framebuffer,
+        threads: usize,
+    ) {
+        let plan = vec![ctx.samples_per_pixel; film.samples.len()];
+        match ctx.integrator {
+            IntegratorKind::Photon | IntegratorKind::Sppm => render_photon_pass(
+                ctx,
+                film,
+                image,
+                &mut Diagnostics::default(),
+                &mut Counts::default(),
+                &plan,
+                42,
+                threads,
+                8,
+                &ProgressBar::hidden(),
+            ),
+            _ => render_pass(
+                ctx,
+                film,
+                image,
+                &mut Diagnostics::default(),
+                &mut Counts::default(),
+                &plan,
+                42,
+                threads,
+                8,
+                &ProgressBar::hidden(),
+                None,
+            ),
+        }
+        film.passes += 1;
+    }
+
+    // every sample draws from its own sampler, media included, so the threads the tiles go to
+    // change nothing, down to the bits
+    #[test]
+    fn image_does_not_depend_on_the_threads() {
+        for integrator in [
+            IntegratorKind::Path,
+            IntegratorKind::Bdpt,
+            IntegratorKind::Photon,
+        ] {
+            let ctx = context("cornell_smoke", 24, integrator);
+            let pixels = |(film, _): (Film, Framebuffer)| -> Vec<[f64; 3]> {
+                let framebuffer = film.resolve();
+                framebuffer.pixels.iter().map(|c| c.to_array()).collect()
+            };
+            let one = pixels(render(&ctx, 1));
+            assert!(one.iter().flatten().any(|&c| c > 0.));
+            assert_eq!(one, pixels(render(&ctx, 4)), "{:?}", integrator);
+        }
+    }
+
+    // each tile is resolved into the image when it is added, which ends up as the whole film
+    // resolved; a filter with negative lobes leaves no negative or blown up pixel
+    #[test]
+    fn tiles_resolve_into_the_image() {
+        let mut ctx = context("cornell_box", 24, IntegratorKind::Path);
+        ctx.filter = Filter::new(FilterKind::Lanczos, None);
+        let (film, mut image) = render(&ctx, 3);
+        film.add_light_paths(&mut image);
+        let resolved = film.resolve();
+        for (a, b) in image.pixels.iter().zip(&resolved.pixels) {
+            assert_eq!(a.to_array(), b.to_array());
+            assert!(a.to_array().iter().all(|c| c.is_finite() && *c >= 0.));
+        }
+    }
+
+    // a crop is the matching part of the whole image, and black around it, halo included
+    #[test]
+    fn crop_matches_the_whole_image() {
+        let mut ctx = context("cornell_box", 24, IntegratorKind::Path);
+        ctx.filter = Filter::new(FilterKind::Gaussian, None);
+        let whole = render(&ctx, 2).1;
+        ctx.crop = Tile {
+            index: 0,
+            x0: 5,
+            y0: 9,
+            x1: 14,
+            y1: 17,
+        };
+        ctx.region = ctx.crop.expand(ctx.filter.padding(), 24, 24);
+        let (film, image) = render(&ctx, 2);
+        assert!(film.samples.iter().any(|&n| n > 0) && ctx.region.area() > ctx.crop.area());
+        for (x, y) in ctx.region.pixels() {
+            let pixel = image.get(x, y).to_array();
+            if ctx.crop.contains(x, y) {
+                assert_eq!(pixel, whole.get(x, y).to_array());
             } else {
-                emitted
-                    + srec.attenuation
-                        * ray_color(
-                            &srec.scattered,
-                            background,
-                            world,
-                            lights,
-                            depth - 1,
-                            (u, v),
-                        )
+                assert_eq!(pixel, [0.; 3]);
             }
-        } else {
-            emitted
         }
-    } else if depth == MAX_DEPTH {
-        background.value(u, v, r.origin_borrow())
-    } else {
-        let dir = r.direction_borrow().unit();
-        background.value(0.5 * (dir.x + 1.), 0.5 * (dir.y + 1.), r.origin_borrow())
+    }
+
+    // a render saved after its first pass and resumed from the file is the uninterrupted one
+    #[test]
+    fn resumed_render_is_the_uninterrupted_one() {
+        let ctx = context("cornell_smoke", 16, IntegratorKind::Path);
+        let (mut whole, mut whole_image) = render(&ctx, 2);
+        render_next_pass(&ctx, &mut whole, &mut whole_image, 2);
+
+        let path = env::temp_dir().join(format!("raytracer_resume_{}.rtfilm", process::id()));
+        let (film, _) = render(&ctx, 2);
+        film.save(&path).unwrap();
+        let mut resumed = Film::load(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+        let mut image = Framebuffer::new(ctx.width, ctx.height);
+        resumed.resolve_region(&ctx.crop, &mut image);
+        render_next_pass(&ctx, &mut resumed, &mut image, 2);
+
+        assert_eq!(resumed.passes, 2);
+        assert_eq!(resumed.samples, whole.samples);
+        let pixels = |image: &Framebuffer| -> Vec<[f64; 3]> {
+            image.pixels.iter().map(|c| c.to_array()).collect()
+        };
+        assert_eq!(pixels(&image), pixels(&whole_image));
+        assert_eq!(pixels(&resumed.resolve()), pixels(&whole.resolve()));
+    }
+
+    // a builtin scene exported and loaded back renders the same image, down to the bits
+    #[test]
+    fn exported_scene_renders_the_same() {
+        for scene in ["random_scene", "cornell_smoke"] {
+            let path = env::temp_dir().join(format!("raytracer_{}_{}.json", scene, process::id()));
+            utility::seed(1);
+            save_scene_file(&builtin_scene(scene).unwrap(), &path).unwrap();
+            let loaded = context(path.to_str().unwrap(), 16, IntegratorKind::Path);
+            fs::remove_file(&path).unwrap();
+            let pixels = |(film, _): (Film, Framebuffer)| -> Vec<[f64; 3]> {
+                film.resolve().pixels.iter().map(|c| c.to_array()).collect()
+            };
+            let builtin = pixels(render(&context(scene, 16, IntegratorKind::Path), 2));
+            assert!(builtin.iter().flatten().any(|&c| c > 0.));
+            assert_eq!(pixels(render(&loaded, 2)), builtin, "{}", scene);
+        }
+    }
+
+    // the voxel plume shipped with the scenes loads and renders; its grid goes above 1, which the
+    // tracking must take as its majorant
+    #[test]
+    fn voxel_plume_renders() {
+        // the scene names its grid from the root of the repository
+        let scene = fs::read_to_string("../scenes/cornell_plume.json")
+            .unwrap()
+            .replace("volume/plume.vox", "../volume/plume.vox");
+        let path = env::temp_dir().join(format!("raytracer_plume_{}.json", process::id()));
+        fs::write(&path, scene).unwrap();
+        let ctx = context(path.to_str().unwrap(), 16, IntegratorKind::Path);
+        fs::remove_file(&path).unwrap();
+
+        let r = Ray::new(&Point3::new(278., 100., -800.), &Vec3::new(0., 0., 1.), 0.);
+        let mut segments = Vec::new();
+        ctx.world.media(&r, 0.001, INFINITY, &mut segments);
+        assert_eq!(segments.len(), 1);
+        assert!(segments[0].medium.majorant() > 0.04);
+
+        let (film, _) = render(&ctx, 2);
+        let pixels = film.resolve().pixels;
+        assert!(pixels
+            .iter()
+            .all(|c| c.to_array().iter().all(|v| v.is_finite())));
+        assert!(pixels.iter().any(|c| c.x > 0.));
+    }
+
+    // passes number their samples after those of the passes before, so they add up to the render
+    // of all the samples at once; each pass can be kept as a numbered snapshot
+    #[test]
+    fn passes_add_up_to_one_render() {
+        let ctx = context("cornell_box", 16, IntegratorKind::Path);
+        let (whole, _) = render(&ctx, 2);
+        let mut ctx = ctx;
+        ctx.samples_per_pixel = 2;
+        let (mut film, mut image) = render(&ctx, 2);
+        let first = film.resolve();
+        render_next_pass(&ctx, &mut film, &mut image, 2);
+
+        assert_eq!(film.samples, whole.samples);
+        let resolved = film.resolve();
+        let close = |a: &Framebuffer, b: &Framebuffer| {
+            a.pixels
+                .iter()
+                .zip(&b.pixels)
+                .all(|(a, b)| (*a - *b).length() <= 1e-9 * (1. + b.length()))
+        };
+        assert!(close(&resolved, &whole.resolve()));
+        assert!(!close(&first, &whole.resolve()));
+        assert!(close(&image, &resolved));
+
+        assert_eq!(
+            snapshot_path(Path::new("output/render.png"), 3),
+            Path::new("output/render_0003.png")
+        );
     }
 }
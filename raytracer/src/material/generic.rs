@@ -1,5 +1,8 @@
 #![allow(dead_code, unused_imports)]
-use crate::{hittable::*, material::*, pdf::onb::*, pdf::CosPDF, texture::*, utility::*};
+use crate::{
+    hittable::*, material::*, pdf::onb::*, pdf::CosPDF, sampler::Sampler,
+    scene::desc::MaterialDesc, texture::*, utility::*,
+};
 
 #[derive(Clone, Copy)]
 pub struct Generic<TDiff: Texture, TSpec: Texture, TEmit: Texture, TRough: Texture> {
@@ -42,9 +45,15 @@ impl<TDiff: Texture, TSpec: Texture, TEmit: Texture, TRough: Texture>
 impl<TDiff: Texture, TSpec: Texture, TEmit: Texture, TRough: Texture> Material
     for Generic<TDiff, TSpec, TEmit, TRough>
 {
-    fn scatter(&self, r_in: &Ray, rec: &HitRecord, srec: &mut ScatterRecord) -> bool {
+    fn scatter<'a>(
+        &'a self,
+        r_in: &Ray,
+        rec: &HitRecord,
+        srec: &mut ScatterRecord<'a>,
+        sampler: &mut dyn Sampler,
+    ) -> bool {
         let (u, v, p, normal) = (rec.u, rec.v, rec.p, rec.normal);
-        if random() >= self.alpha {
+        if sampler.get_1d() >= self.alpha {
             let refraction_ratio = if rec.front_face {
                 1. / self.optical_density
             } else {
@@ -54,7 +63,7 @@ impl<TDiff: Texture, TSpec: Texture, TEmit: Texture, TRough: Texture> Material
             let sin_theta = (1. - cos_theta * cos_theta).sqrt();
             let cannot_refract = refraction_ratio * sin_theta > 1.;
             let is_reflected =
-                cannot_refract || random() < Self::reflectance(cos_theta, refraction_ratio);
+                cannot_refract || sampler.get_1d() < Self::reflectance(cos_theta, refraction_ratio);
             return if !is_reflected {
                 let scatter_direction = refract(r_in.direction_borrow(), &normal, refraction_ratio);
                 srec.scattered = Ray::new(&p, &scatter_direction, r_in.time());
@@ -71,7 +80,7 @@ impl<TDiff: Texture, TSpec: Texture, TEmit: Texture, TRough: Texture> Material
         } // refract
         let pdf_ptr = Box::new(CosPDF::new(&rec.normal));
         let rough = self.rough.value(u, v, &p)[0];
-        if random() < rough {
+        if sampler.get_1d() < rough {
             srec.attenuation = self.diffuse.value(u, v, &p);
             srec.pdf_ptr = Some(pdf_ptr);
             return true;
@@ -82,6 +91,10 @@ impl<TDiff: Texture, TSpec: Texture, TEmit: Texture, TRough: Texture> Material
         // srec.pdf_ptr = None;
         true
     }
+    fn albedo(&self, rec: &HitRecord) -> Color {
+        self.diffuse.value(rec.u, rec.v, &rec.p)
+    }
+
     fn scattering_pdf(&self, _r_in: &Ray, rec: &HitRecord, scattered: &Ray) -> f64 {
         let cosine = dot(&rec.normal, &scattered.direction_borrow().unit());
         cosine.max(0.) / PI
@@ -96,4 +109,14 @@ impl<TDiff: Texture, TSpec: Texture, TEmit: Texture, TRough: Texture> Material
             Color::default()
         }
     }
+    fn describe(&self) -> MaterialDesc {
+        MaterialDesc::Generic {
+            diffuse: self.diffuse.describe(),
+            specular: self.specular.describe(),
+            emit: self.emit.describe(),
+            rough: self.rough.describe(),
+            alpha: self.alpha,
+            optical_density: self.optical_density,
+        }
+    }
 }
@@ -1,22 +1,32 @@
 use crate::{
     hittable::HitRecord,
-    pdf::CosPDF,
+    pdf::{CosPDF, PDF},
+    sampler::Sampler,
+    scene::desc::MaterialDesc,
     texture::{SolidColor, Texture},
     utility::*,
 };
 pub use generic::*;
+pub use phase::*;
 
 pub mod generic;
+pub mod phase;
 
 #[derive(Default)]
-pub struct ScatterRecord {
+pub struct ScatterRecord<'a> {
     pub scattered: Ray,
     pub attenuation: Color,
-    pub pdf_ptr: Option<Box<CosPDF>>,
+    pub pdf_ptr: Option<Box<dyn PDF + 'a>>, //None for specular scattering
 }
 
 pub trait Material: Send + Sync {
-    fn scatter(&self, _r_in: &Ray, _rec: &HitRecord, _srec: &mut ScatterRecord) -> bool {
+    fn scatter<'a>(
+        &'a self,
+        _r_in: &Ray,
+        _rec: &HitRecord,
+        _srec: &mut ScatterRecord<'a>,
+        _sampler: &mut dyn Sampler,
+    ) -> bool {
         false
     }
 
@@ -27,11 +37,50 @@ pub trait Material: Send + Sync {
     fn emitted(&self, _r_in: &Ray, _rec: &HitRecord, _u: f64, _v: f64, _p: &Point3) -> Color {
         Color::default()
     }
+
+    // base color at a hit, for the albedo AOV and the denoiser
+    fn albedo(&self, _rec: &HitRecord) -> Color {
+        Color::default()
+    }
+
+    fn describe(&self) -> MaterialDesc;
+}
+
+impl<M: Material + ?Sized> Material for Arc<M> {
+    fn scatter<'a>(
+        &'a self,
+        r_in: &Ray,
+        rec: &HitRecord,
+        srec: &mut ScatterRecord<'a>,
+        sampler: &mut dyn Sampler,
+    ) -> bool {
+        self.as_ref().scatter(r_in, rec, srec, sampler)
+    }
+
+    fn scattering_pdf(&self, r_in: &Ray, rec: &HitRecord, scattered: &Ray) -> f64 {
+        self.as_ref().scattering_pdf(r_in, rec, scattered)
+    }
+
+    fn emitted(&self, r_in: &Ray, rec: &HitRecord, u: f64, v: f64, p: &Point3) -> Color {
+        self.as_ref().emitted(r_in, rec, u, v, p)
+    }
+
+    fn albedo(&self, rec: &HitRecord) -> Color {
+        self.as_ref().albedo(rec)
+    }
+
+    fn describe(&self) -> MaterialDesc {
+        self.as_ref().describe()
+    }
 }
 
 #[derive(Copy, Clone, Default)]
 pub struct EmptyMaterial {}
-impl Material for EmptyMaterial {}
+impl Material for EmptyMaterial {
+    fn describe(&self) -> MaterialDesc {
+        MaterialDesc::Empty
+    }
+}
 pub const DEFAULT_MATERIAL: EmptyMaterial = EmptyMaterial {};
 
 #[derive(Clone, Default)]
@@ -54,7 +103,13 @@ impl Lambertian<SolidColor> {
 }
 
 impl<T: Texture> Material for Lambertian<T> {
-    fn scatter(&self, _r_in: &Ray, rec: &HitRecord, srec: &mut ScatterRecord) -> bool {
+    fn scatter<'a>(
+        &'a self,
+        _r_in: &Ray,
+        rec: &HitRecord,
+        srec: &mut ScatterRecord<'a>,
+        _sampler: &mut dyn Sampler,
+    ) -> bool {
         srec.attenuation = self.albedo.value(rec.u, rec.v, &rec.p);
         srec.pdf_ptr = Some(Box::new(CosPDF::new(&rec.normal)));
         true
@@ -68,6 +123,16 @@ impl<T: Texture> Material for Lambertian<T> {
             cosine / PI
         }
     }
+
+    fn albedo(&self, rec: &HitRecord) -> Color {
+        self.albedo.value(rec.u, rec.v, &rec.p)
+    }
+
+    fn describe(&self) -> MaterialDesc {
+        MaterialDesc::Lambertian {
+            albedo: self.albedo.describe(),
+        }
+    }
 }
 
 #[derive(Debug, Copy, Clone, Default)]
@@ -86,14 +151,31 @@ impl Metal {
 }
 
 impl Material for Metal {
-    fn scatter(&self, r_in: &Ray, rec: &HitRecord, srec: &mut ScatterRecord) -> bool {
+    fn scatter<'a>(
+        &'a self,
+        r_in: &Ray,
+        rec: &HitRecord,
+        srec: &mut ScatterRecord<'a>,
+        sampler: &mut dyn Sampler,
+    ) -> bool {
         let reflected = reflect(&r_in.direction_borrow().unit(), &rec.normal)
-            + self.fuzz * Vec3::random_in_unit_sphere();
+            + self.fuzz * Vec3::random_in_unit_sphere(sampler);
         srec.scattered = Ray::new(&rec.p, &reflected, r_in.time());
         srec.attenuation = self.albedo;
         srec.pdf_ptr = None;
         true
     }
+
+    fn albedo(&self, _rec: &HitRecord) -> Color {
+        self.albedo
+    }
+
+    fn describe(&self) -> MaterialDesc {
+        MaterialDesc::Metal {
+            albedo: self.albedo.to_array(),
+            fuzz: self.fuzz,
+        }
+    }
 }
 
 #[derive(Debug, Copy, Clone, Default)]
@@ -110,7 +192,13 @@ impl Dielectric {
 }
 
 impl Material for Dielectric {
-    fn scatter(&self, r_in: &Ray, rec: &HitRecord, srec: &mut ScatterRecord) -> bool {
+    fn scatter<'a>(
+        &'a self,
+        r_in: &Ray,
+        rec: &HitRecord,
+        srec: &mut ScatterRecord<'a>,
+        sampler: &mut dyn Sampler,
+    ) -> bool {
         srec.pdf_ptr = None;
         srec.attenuation = Color::new(1., 1., 1.);
 
@@ -123,15 +211,24 @@ impl Material for Dielectric {
         let cos_theta = dot(&(-unit_dir), &rec.normal).min(1.);
         let sin_theta = (1. - cos_theta * cos_theta).sqrt();
         let cannot_refract = refraction_ratio * sin_theta > 1.;
-        let direction = if cannot_refract || reflectance(cos_theta, refraction_ratio) > random() {
-            reflect(&unit_dir, &rec.normal)
-        } else {
-            refract(&unit_dir, &rec.normal, refraction_ratio)
-        };
+        let direction =
+            if cannot_refract || reflectance(cos_theta, refraction_ratio) > sampler.get_1d() {
+                reflect(&unit_dir, &rec.normal)
+            } else {
+                refract(&unit_dir, &rec.normal, refraction_ratio)
+            };
 
         srec.scattered = Ray::new(&rec.p, &direction, r_in.time());
         true
     }
+
+    fn albedo(&self, _rec: &HitRecord) -> Color {
+        Color::new(1., 1., 1.)
+    }
+
+    fn describe(&self) -> MaterialDesc {
+        MaterialDesc::Dielectric { ir: self.ir }
+    }
 }
 
 fn reflectance(cosine: f64, ref_idx: f64) -> f64 {
@@ -167,32 +264,78 @@ impl<T: Texture> Material for DiffuseLight<T> {
             Color::default()
         }
     }
+
+    fn albedo(&self, rec: &HitRecord) -> Color {
+        self.emit.value(rec.u, rec.v, &rec.p)
+    }
+
+    fn describe(&self) -> MaterialDesc {
+        MaterialDesc::DiffuseLight {
+            emit: self.emit.describe(),
+        }
+    }
 }
 
+// scattering in a medium: the light that collides goes on, dimmed by the albedo, in a direction
+// drawn from the phase function
 #[derive(Clone, Default)]
-pub struct Isotropic<T: Texture> {
+pub struct Volumetric<T: Texture, P: PhaseFunction> {
     pub albedo: T,
+    pub phase: P,
 }
 
-impl<T: Texture> Isotropic<T> {
+pub type Isotropic<T> = Volumetric<T, IsotropicPhase>;
+
+impl<T: Texture> Volumetric<T, IsotropicPhase> {
     pub fn new(a: T) -> Self {
-        Self { albedo: a }
+        Self::with_phase(a, IsotropicPhase::default())
     }
 }
 
-impl Isotropic<SolidColor> {
+impl Volumetric<SolidColor, IsotropicPhase> {
     pub fn new_from_color(color: &Color) -> Self {
-        Self {
-            albedo: SolidColor::new(color),
-        }
+        Self::new(SolidColor::new(color))
     }
 }
 
-impl<T: Texture> Material for Isotropic<T> {
-    fn scatter(&self, r_in: &Ray, rec: &HitRecord, srec: &mut ScatterRecord) -> bool {
-        srec.scattered = Ray::new(&rec.p, &Vec3::random_in_unit_sphere(), r_in.time());
+impl<T: Texture, P: PhaseFunction> Volumetric<T, P> {
+    pub fn with_phase(a: T, phase: P) -> Self {
+        Self { albedo: a, phase }
+    }
+}
+
+impl<T: Texture, P: PhaseFunction> Material for Volumetric<T, P> {
+    fn scatter<'a>(
+        &'a self,
+        r_in: &Ray,
+        rec: &HitRecord,
+        srec: &mut ScatterRecord<'a>,
+        _sampler: &mut dyn Sampler,
+    ) -> bool {
         srec.attenuation = self.albedo.value(rec.u, rec.v, &rec.p);
-        srec.pdf_ptr = None;
+        srec.pdf_ptr = Some(Box::new(PhasePDF::new(
+            r_in.direction_borrow(),
+            &self.phase,
+        )));
         true
     }
+
+    fn scattering_pdf(&self, r_in: &Ray, _rec: &HitRecord, scattered: &Ray) -> f64 {
+        let cos = dot(
+            &r_in.direction_borrow().unit(),
+            &scattered.direction_borrow().unit(),
+        );
+        self.phase.value(cos)
+    }
+
+    fn albedo(&self, rec: &HitRecord) -> Color {
+        self.albedo.value(rec.u, rec.v, &rec.p)
+    }
+
+    fn describe(&self) -> MaterialDesc {
+        MaterialDesc::Isotropic {
+            albedo: self.albedo.describe(),
+            phase: self.phase.describe(),
+        }
+    }
 }
@@ -0,0 +1,224 @@
+use crate::{
+    pdf::{onb::ONB, PDF},
+    sampler::Sampler,
+    scene::desc::PhaseDesc,
+    utility::*,
+};
+
+// how the light scattered in a medium spreads over the directions: a density over the sphere, of
+// the cosine between the direction the light travelled in and the one it leaves in. the density
+// is normalized, so scattering by sampling it weighs just the albedo
+pub trait PhaseFunction: Send + Sync {
+    fn value(&self, cos_theta: f64) -> f64;
+    // the cosine of a direction drawn from the density, by inverting its distribution at u
+    fn sample_cos(&self, u: f64) -> f64;
+    fn describe(&self) -> PhaseDesc;
+}
+
+impl<P: PhaseFunction + ?Sized> PhaseFunction for Arc<P> {
+    fn value(&self, cos_theta: f64) -> f64 {
+        self.as_ref().value(cos_theta)
+    }
+    fn sample_cos(&self, u: f64) -> f64 {
+        self.as_ref().sample_cos(u)
+    }
+    fn describe(&self) -> PhaseDesc {
+        self.as_ref().describe()
+    }
+}
+
+#[derive(Copy, Clone, Default)]
+pub struct IsotropicPhase {}
+
+impl PhaseFunction for IsotropicPhase {
+    fn value(&self, _cos_theta: f64) -> f64 {
+        0.25 / PI
+    }
+    fn sample_cos(&self, u: f64) -> f64 {
+        1. - 2. * u
+    }
+    fn describe(&self) -> PhaseDesc {
+        PhaseDesc::Isotropic
+    }
+}
+
+// Henyey-Greenstein: a lobe around the direction the light travelled in, forward for g > 0 and
+// backward for g < 0, g being the mean cosine
+#[derive(Copy, Clone, Default)]
+pub struct HenyeyGreenstein {
+    pub g: f64,
+}
+
+impl HenyeyGreenstein {
+    pub fn new(g: f64) -> Self {
+        Self { g }
+    }
+}
+
+impl PhaseFunction for HenyeyGreenstein {
+    fn value(&self, cos_theta: f64) -> f64 {
+        let g = self.g;
+        let denom = 1. + g * g - 2. * g * cos_theta;
+        0.25 / PI * (1. - g * g) / (denom * denom.sqrt())
+    }
+    fn sample_cos(&self, u: f64) -> f64 {
+        let g = self.g;
+        if g.abs() < 1e-3 {
+            return 1. - 2. * u;
+        }
+        let s = (1. - g * g) / (1. + g - 2. * g * u);
+        clamp((1. + g * g - s * s) / (2. * g), -1., 1.)
+    }
+    fn describe(&self) -> PhaseDesc {
+        PhaseDesc::HenyeyGreenstein { g: self.g }
+    }
+}
+
+// two Henyey-Greenstein lobes, usually one forward and one backward, with a share `weight` of the
+// light in the first
+#[derive(Copy, Clone, Default)]
+pub struct DoubleHenyeyGreenstein {
+    pub lobes: [HenyeyGreenstein; 2],
+    pub weight: f64,
+}
+
+impl DoubleHenyeyGreenstein {
+    pub fn new(g1: f64, g2: f64, weight: f64) -> Self {
+        Self {
+            lobes: [HenyeyGreenstein::new(g1), HenyeyGreenstein::new(g2)],
+            weight,
+        }
+    }
+}
+
+impl PhaseFunction for DoubleHenyeyGreenstein {
+    fn value(&self, cos_theta: f64) -> f64 {
+        self.weight * self.lobes[0].value(cos_theta)
+            + (1. - self.weight) * self.lobes[1].value(cos_theta)
+    }
+    // u picks the lobe, and what is left of it samples the lobe
+    fn sample_cos(&self, u: f64) -> f64 {
+        if u < self.weight {
+            self.lobes[0].sample_cos(u / self.weight)
+        } else {
+            self.lobes[1].sample_cos((u - self.weight) / (1. - self.weight))
+        }
+    }
+    fn describe(&self) -> PhaseDesc {
+        PhaseDesc::DoubleHenyeyGreenstein {
+            g1: self.lobes[0].g,
+            g2: self.lobes[1].g,
+            weight: self.weight,
+        }
+    }
+}
+
+// scattering by particles much smaller than the wavelength, like the molecules of the air: as much
+// backward as forward, and least to the sides
+#[derive(Copy, Clone, Default)]
+pub struct Rayleigh {}
+
+impl PhaseFunction for Rayleigh {
+    fn value(&self, cos_theta: f64) -> f64 {
+        3. / (16. * PI) * (1. + cos_theta * cos_theta)
+    }
+    // the distribution of the cosine is (c^3 + 3c + 4) / 8; its inverse is the real root of a
+    // depressed cubic (Cardano)
+    fn sample_cos(&self, u: f64) -> f64 {
+        let q = 4. - 8. * u;
+        let a = (-0.5 * q + (0.25 * q * q + 1.).sqrt()).cbrt();
+        clamp(a - 1. / a, -1., 1.)
+    }
+    fn describe(&self) -> PhaseDesc {
+        PhaseDesc::Rayleigh
+    }
+}
+
+// the directions the light travelling along `direction` scatters into, by the phase function of
+// the medium it scatters in
+#[derive(Clone, Copy)]
+pub struct PhasePDF<'a, P: PhaseFunction + ?Sized> {
+    uvw: ONB,
+    phase: &'a P,
+}
+
+impl<'a, P: PhaseFunction + ?Sized> PhasePDF<'a, P> {
+    pub fn new(direction: &Vec3, phase: &'a P) -> Self {
+        Self {
+            uvw: ONB::build_from_w(direction),
+            phase,
+        }
+    }
+}
+
+impl<P: PhaseFunction + ?Sized> PDF for PhasePDF<'_, P> {
+    fn value(&self, direction: &Vec3) -> f64 {
+        self.phase.value(dot(&direction.unit(), &self.uvw.w()))
+    }
+
+    fn generate(&self, sampler: &mut dyn Sampler) -> Vec3 {
+        let (u, v) = sampler.get_2d();
+        let cos = self.phase.sample_cos(u);
+        let sin = (1. - cos * cos).max(0.).sqrt();
+        let phi = 2. * PI * v;
+        self.uvw.local(sin * phi.cos(), sin * phi.sin(), cos)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        hittable::HitRecord,
+        material::{Material, ScatterRecord, Volumetric},
+        sampler::IndependentSampler,
+        texture::SolidColor,
+    };
+
+    // each phase function is a density over the sphere, and the directions the scattering in a
+    // medium draws from it follow it
+    #[test]
+    fn scattering_follows_the_phase() {
+        let phases: Vec<Arc<dyn PhaseFunction>> = vec![
+            Arc::new(IsotropicPhase::default()),
+            Arc::new(HenyeyGreenstein::new(0.6)),
+            Arc::new(HenyeyGreenstein::new(-0.3)),
+            Arc::new(DoubleHenyeyGreenstein::new(0.8, -0.4, 0.7)),
+            Arc::new(Rayleigh::default()),
+        ];
+        let steps = 100000;
+        let r_in = Ray::new(&Point3::default(), &Vec3::new(0., 0., 2.), 0.);
+        let rec = HitRecord::default();
+        for phase in phases {
+            // integrals over the cosine, by the midpoint rule
+            let (mut total, mut mean_cos) = (0., 0.);
+            for i in 0..steps {
+                let c = -1. + (i as f64 + 0.5) * 2. / steps as f64;
+                total += 2. * PI * phase.value(c) * 2. / steps as f64;
+                mean_cos += 2. * PI * c * phase.value(c) * 2. / steps as f64;
+            }
+            assert!((total - 1.).abs() < 1e-6, "{:?}", phase.describe());
+
+            let material = Volumetric::with_phase(SolidColor::new(&Color::new(1., 1., 1.)), phase);
+            let mut sampler = IndependentSampler::new(5);
+            let mut srec = ScatterRecord::default();
+            let n = 20000;
+            let mut sampled_cos = 0.;
+            for index in 0..n {
+                sampler.start_sample(0, index);
+                assert!(material.scatter(&r_in, &rec, &mut srec, &mut sampler));
+                let pdf = srec.pdf_ptr.as_ref().unwrap();
+                let direction = pdf.generate(&mut sampler);
+                let scattered = Ray::new(&rec.p, &direction, 0.);
+                let value = material.scattering_pdf(&r_in, &rec, &scattered);
+                assert!((pdf.value(&direction) - value).abs() < 1e-9);
+                sampled_cos += direction.unit().z / n as f64;
+            }
+            assert!(
+                (sampled_cos - mean_cos).abs() < 0.02,
+                "{:?}",
+                material.phase.describe()
+            );
+        }
+    }
+}
@@ -1,13 +1,124 @@
 use crate::{
-    hittable::{bvh::BVHNode, triangle::Triangle, HittableList},
+    hittable::{bvh::BVHNode, triangle::Triangle, *},
     // material::generic::*,
     material::*,
+    sampler::Sampler,
+    scene::{
+        desc::{MaterialDesc, MaterialRef, ObjectDesc},
+        my_scene::{rot_x, rot_y, rot_z},
+    },
     texture::*,
     utility::*,
 };
 use ndarray::Array2;
+use std::path::Path;
 use tobj::LoadOptions;
 
+// a loaded .obj model that remembers how it was loaded, so it can be described
+pub struct ObjModel {
+    pub file: String,
+    pub scale: f64,
+    pub rotate: [f64; 3], //degrees around x, y and z
+    pub translate: [f64; 3],
+    pub material: Option<MaterialDesc>,
+    pub normal_map: Option<String>,
+    pub model: HittableList,
+}
+
+impl ObjModel {
+    // materials come from the .mtl file next to the model; a given normal map replaces theirs
+    pub fn new(
+        file: &str,
+        normal_map: Option<&str>,
+        scale: f64,
+        rotate: [f64; 3],
+        translate: [f64; 3],
+    ) -> Self {
+        let path = Path::new(file);
+        let dir = match path.parent().map(|dir| dir.display().to_string()) {
+            Some(dir) if !dir.is_empty() => dir + "/",
+            _ => String::new(),
+        };
+        let name = path.file_name().unwrap().to_string_lossy();
+        Self {
+            file: file.to_owned(),
+            scale,
+            rotate,
+            translate,
+            material: None,
+            normal_map: normal_map.map(|s| s.to_owned()),
+            model: load_obj_and_mtl(
+                &dir,
+                &name,
+                normal_map,
+                scale,
+                Self::rotation(&rotate),
+                Vec3::from_array(&translate),
+            ),
+        }
+    }
+
+    pub fn new_with_material<M: Material + Clone + 'static>(
+        file: &str,
+        mat: M,
+        normal_map: Option<&str>,
+        scale: f64,
+        rotate: [f64; 3],
+        translate: [f64; 3],
+    ) -> Self {
+        Self {
+            file: file.to_owned(),
+            scale,
+            rotate,
+            translate,
+            material: Some(mat.describe()),
+            normal_map: normal_map.map(|s| s.to_owned()),
+            model: load_obj(
+                file,
+                mat,
+                normal_map,
+                scale,
+                Self::rotation(&rotate),
+                Vec3::from_array(&translate),
+            ),
+        }
+    }
+
+    fn rotation(rotate: &[f64; 3]) -> Array2<f64> {
+        rot_x(rotate[0])
+            .dot(&rot_y(rotate[1]))
+            .dot(&rot_z(rotate[2]))
+    }
+}
+
+impl Hittable for ObjModel {
+    fn hit(&self, r: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord<'_>> {
+        self.model.hit(r, t_min, t_max)
+    }
+    fn bounding_box(&self, time0: f64, time1: f64, output_box: &mut AABB) -> bool {
+        self.model.bounding_box(time0, time1, output_box)
+    }
+    fn pdf_value(&self, o: &Point3, v: &Vec3) -> f64 {
+        self.model.pdf_value(o, v)
+    }
+    fn random(&self, o: &Vec3, sampler: &mut dyn Sampler) -> Vec3 {
+        self.model.random(o, sampler)
+    }
+    fn describe(&self) -> ObjectDesc {
+        ObjectDesc::Obj {
+            file: self.file.clone(),
+            scale: self.scale,
+            rotate: self.rotate,
+            translate: self.translate,
+            material: self
+                .material
+                .as_ref()
+                .map(|material| MaterialRef::Inline(Box::new(material.clone()))),
+            normal_map: self.normal_map.clone(),
+        }
+    }
+}
+
 fn parse(color_str: &str) -> Vec<f64> {
     color_str
         .split_whitespace()
@@ -17,6 +128,7 @@ fn parse(color_str: &str) -> Vec<f64> {
 pub fn load_obj_and_mtl(
     path: &str,
     short_file_name: &str,
+    normal_map_name: Option<&str>, //instead of the normal maps of the materials
     scale: f64,
     rot: Array2<f64>, // rotation
     shift: Vec3,
@@ -37,10 +149,13 @@ pub fn load_obj_and_mtl(
     for model in models {
         let mesh = &model.mesh;
         let material = &materials[mesh.material_id.unwrap()];
-        let nmap = material
-            .normal_texture
-            .as_ref()
-            .map(|nmap_name| ImageTexture::new((path.to_owned() + nmap_name).as_str()));
+        let nmap = match normal_map_name {
+            Some(nmap_name) => Some(ImageTexture::new(nmap_name)),
+            None => material
+                .normal_texture
+                .as_ref()
+                .map(|nmap_name| ImageTexture::new((path.to_owned() + nmap_name).as_str())),
+        };
         let diffuse = if let Some(diffuse_name) = &material.diffuse_texture {
             MappingTexture::Texture(ImageTexture::new((path.to_owned() + diffuse_name).as_str()))
         } else {
@@ -1,10 +1,10 @@
 pub mod onb;
 
-use crate::{hittable::Hittable, pdf::onb::ONB, utility::*};
+use crate::{hittable::Hittable, pdf::onb::ONB, sampler::Sampler, utility::*};
 
 pub trait PDF {
     fn value(&self, direction: &Vec3) -> f64;
-    fn generate(&self) -> Vec3;
+    fn generate(&self, sampler: &mut dyn Sampler) -> Vec3;
 }
 
 #[derive(Copy, Clone, Default)]
@@ -30,8 +30,8 @@ impl PDF for CosPDF {
         }
     }
 
-    fn generate(&self) -> Vec3 {
-        self.uvw.local_vec(&Vec3::random_cosine_direction())
+    fn generate(&self, sampler: &mut dyn Sampler) -> Vec3 {
+        self.uvw.local_vec(&Vec3::random_cosine_direction(sampler))
     }
 }
 
@@ -52,33 +52,33 @@ impl<'a, H: Hittable> PDF for HittablePDF<'a, H> {
         self.ptr.pdf_value(&self.o, direction)
     }
 
-    fn generate(&self) -> Vec3 {
-        self.ptr.random(&self.o)
+    fn generate(&self, sampler: &mut dyn Sampler) -> Vec3 {
+        self.ptr.random(&self.o, sampler)
     }
 }
 
 #[derive(Clone)]
-pub struct MixturePDF<'a, P0: PDF, P1: PDF> {
+pub struct MixturePDF<'a, P0: PDF + ?Sized, P1: PDF + ?Sized> {
     p0: &'a P0,
     p1: &'a P1,
 }
 
-impl<'a, P0: PDF, P1: PDF> MixturePDF<'a, P0, P1> {
+impl<'a, P0: PDF + ?Sized, P1: PDF + ?Sized> MixturePDF<'a, P0, P1> {
     pub fn new(p0: &'a P0, p1: &'a P1) -> Self {
         Self { p0, p1 }
     }
 }
 
-impl<'a, P0: PDF, P1: PDF> PDF for MixturePDF<'a, P0, P1> {
+impl<'a, P0: PDF + ?Sized, P1: PDF + ?Sized> PDF for MixturePDF<'a, P0, P1> {
     fn value(&self, direction: &Vec3) -> f64 {
         0.5 * (self.p0.value(direction) + self.p1.value(direction))
     }
 
-    fn generate(&self) -> Vec3 {
-        if random() < 0.5 {
-            self.p0.generate()
+    fn generate(&self, sampler: &mut dyn Sampler) -> Vec3 {
+        if sampler.get_1d() < 0.5 {
+            self.p0.generate(sampler)
         } else {
-            self.p1.generate()
+            self.p1.generate(sampler)
         }
     }
 }
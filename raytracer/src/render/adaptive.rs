@@ -0,0 +1,108 @@
+use crate::render::{film::Film, scheduler::Tile};
+use image::{ImageBuffer, Rgb, RgbImage};
+
+// below this luminance the tolerance is absolute, so dark pixels can converge too
+const MIN_LUMINANCE: f64 = 0.01;
+const Z_95: f64 = 1.96;
+
+// spends the sample budget where the estimate of a pixel is the least certain
+#[derive(Copy, Clone, Debug)]
+pub struct AdaptiveSampling {
+    pub threshold: f64, //half-width of the 95% confidence interval, relative to the mean luminance
+    pub min_spp: u32,   //at least 2, for a variance
+    pub max_spp: u32,
+}
+
+impl AdaptiveSampling {
+    // confidence interval of a pixel over its tolerance; converged when at most 1
+    pub fn error(&self, film: &Film, index: usize) -> f64 {
+        let (mean, variance) = film.luminance_stats(index);
+        let n = film.samples[index] as f64;
+        Z_95 * (variance / n).sqrt() / (self.threshold * mean.max(MIN_LUMINANCE))
+    }
+
+    // samples of each pixel of `region` in the next pass: first `min_spp` everywhere, then
+    // about `pass_spp` per unconverged pixel, shared in proportion to their error
+    pub fn plan(&self, film: &Film, region: &Tile, pass_spp: u32, budget: u64) -> Vec<u32> {
+        let mut plan = vec![0; film.samples.len()];
+        let pixels: Vec<usize> = region.pixels().map(|(x, y)| film.index(x, y)).collect();
+        if pixels
+            .iter()
+            .any(|&index| film.samples[index] < self.min_spp)
+        {
+            for &index in &pixels {
+                plan[index] = self.min_spp.saturating_sub(film.samples[index]);
+            }
+            return plan;
+        }
+        let remaining = budget.saturating_sub(film.total_samples());
+        if remaining == 0 {
+            return plan;
+        }
+
+        let errors: Vec<(usize, f64)> = pixels
+            .into_iter()
+            .filter(|&index| film.samples[index] < self.max_spp)
+            .map(|index| (index, self.error(film, index)))
+            .filter(|&(_, error)| error > 1.)
+            .collect();
+        if errors.is_empty() {
+            return plan;
+        }
+        let pass_budget = remaining.min(errors.len() as u64 * pass_spp as u64) as f64;
+        let total: f64 = errors.iter().map(|&(_, error)| error).sum();
+        for (index, error) in errors {
+            let n = (pass_budget * error / total).round() as u32;
+            plan[index] = n.max(1).min(self.max_spp - film.samples[index]);
+        }
+        plan
+    }
+}
+
+// samples per pixel from black (none) through red and yellow to white (the most)
+pub fn spp_heatmap(film: &Film) -> RgbImage {
+    let max = film.samples.iter().copied().max().unwrap_or(0).max(1) as f64;
+    ImageBuffer::from_fn(film.width, film.height, |x, y| {
+        let t = film.samples[film.index(x, y)] as f64 / max;
+        let channel = |c: f64| ((c * 3.).clamp(0., 1.) * 255.).round() as u8;
+        Rgb([channel(t), channel(t - 1. / 3.), channel(t - 2. / 3.)])
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utility::*;
+
+    // samples go to the noisy pixels, up to their maximum and within the budget
+    #[test]
+    fn plan_follows_the_noise() {
+        let adaptive = AdaptiveSampling {
+            threshold: 0.05,
+            min_spp: 16,
+            max_spp: 20,
+        };
+        let mut film = Film::new(3, 1);
+        let region = Tile {
+            index: 0,
+            x0: 0,
+            y0: 0,
+            x1: 3,
+            y1: 1,
+        };
+        assert_eq!(adaptive.plan(&film, &region, 4, 1000), [16, 16, 16]);
+
+        // flat, noisy, and noisy at the most samples
+        film.samples = vec![16, 16, 20];
+        film.sum = vec![
+            Color::grayscale(8.),
+            Color::grayscale(16.),
+            Color::grayscale(20.),
+        ];
+        film.sum_sq = vec![4., 32., 40.];
+        assert!(adaptive.error(&film, 0) < 1.);
+        assert!(adaptive.error(&film, 1) > 1.);
+        assert_eq!(adaptive.plan(&film, &region, 8, 1000), [0, 4, 0]);
+        assert_eq!(adaptive.plan(&film, &region, 8, 52), [0, 0, 0]);
+    }
+}
@@ -0,0 +1,269 @@
+use crate::{
+    hittable::*, integrator::PathTracer, material::Material, render::scheduler::Tile, utility::*,
+};
+use clap::ValueEnum;
+use image::{ImageBuffer, Rgb, RgbImage};
+use std::collections::HashMap;
+
+// arbitrary output variables: layers written next to the image, from the first hit of the
+// camera rays
+#[derive(Copy, Clone, PartialEq, Eq, Hash, Debug, ValueEnum)]
+pub enum Aov {
+    Depth,  //distance along the camera ray, infinite where nothing is hit
+    Normal, //shading normal, facing the camera
+    Albedo, //base color of the material, the background where nothing is hit
+    Position,
+    Uv,
+    ObjectId,   //hash of the primitive hit, by where it is in the scene, 0 for none
+    MaterialId, //hash of the material description, 0 for none
+}
+
+impl Aov {
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Depth => "depth",
+            Self::Normal => "normal",
+            Self::Albedo => "albedo",
+            Self::Position => "position",
+            Self::Uv => "uv",
+            Self::ObjectId => "object_id",
+            Self::MaterialId => "material_id",
+        }
+    }
+
+    // channels of the layer, named "<aov>.<channel>" in an EXR file
+    pub fn channels(self) -> &'static [&'static str] {
+        match self {
+            Self::Depth => &["Z"],
+            Self::Normal | Self::Position => &["X", "Y", "Z"],
+            Self::Albedo => &["R", "G", "B"],
+            Self::Uv => &["U", "V"],
+            Self::ObjectId | Self::MaterialId => &["id"],
+        }
+    }
+}
+
+// id of the primitive of a hit record: 24 bits, like the materials
+pub fn object_id(id: u64) -> u32 {
+    (id >> 40) as u32 | 1
+}
+
+// ids of the materials met by a thread, by address, so each is described only once
+#[derive(Default)]
+pub struct MaterialIds(HashMap<*const (), u32>);
+
+impl MaterialIds {
+    // stable across runs and threads: 24 bits, so it is exact in a 32-bit float channel
+    pub fn get(&mut self, material: &dyn Material) -> u32 {
+        let key = material as *const dyn Material as *const ();
+        *self.0.entry(key).or_insert_with(|| {
+            let desc = serde_json::to_string(&material.describe()).unwrap();
+            (splitmix64(fnv1a(desc.as_bytes())) >> 40) as u32 | 1
+        })
+    }
+}
+
+// sums of the first hits of a pixel's samples; the ids are those of its first sample that hit
+#[derive(Clone, Copy, Default)]
+pub struct AovPixel {
+    pub count: u32,
+    pub hits: u32,
+    pub depth: f64,
+    pub normal: Vec3,
+    pub albedo: Color,
+    pub position: Point3,
+    pub uv: Vec3,
+    pub object_id: u32,
+    pub material_id: u32,
+}
+
+impl AovPixel {
+    pub fn add(
+        &mut self,
+        tracer: &PathTracer,
+        r: &Ray,
+        (u, v): (f64, f64),
+        materials: &mut MaterialIds,
+    ) {
+        self.count += 1;
+        let rec = match tracer.world.hit(r, 0.001, INFINITY) {
+            Some(hit) => hit,
+            None => {
+                self.albedo += tracer.background(r, tracer.max_depth, (u, v));
+                return;
+            }
+        };
+        if self.hits == 0 {
+            self.object_id = object_id(rec.id);
+            self.material_id = materials.get(rec.mat_ptr);
+        }
+        self.hits += 1;
+        self.depth += rec.t * r.direction_borrow().length();
+        self.normal += rec.normal;
+        self.albedo += rec.mat_ptr.albedo(&rec);
+        self.position += rec.p;
+        self.uv += Vec3::new(rec.u, rec.v, 0.);
+    }
+}
+
+// row by row from the top of the image, like the framebuffer
+pub struct AovBuffer {
+    pub width: u32,
+    pub height: u32,
+    pub pixels: Vec<AovPixel>,
+}
+
+impl AovBuffer {
+    pub fn new(width: u32, height: u32) -> Self {
+        Self {
+            width,
+            height,
+            pixels: vec![AovPixel::default(); (width * height) as usize],
+        }
+    }
+
+    pub fn crop(&self, region: &Tile) -> AovBuffer {
+        AovBuffer {
+            width: region.width(),
+            height: region.height(),
+            pixels: region.crop(&self.pixels, self.width),
+        }
+    }
+
+    pub fn set_tile(&mut self, tile: &Tile, pixels: &[AovPixel]) {
+        for ((x, y), pixel) in tile.pixels().zip(pixels) {
+            self.pixels[(y * self.width + x) as usize] = *pixel;
+        }
+    }
+
+    // per-pixel value of the layer, averaged over the samples (albedo) or the hits (the rest)
+    pub fn value(&self, aov: Aov, index: usize) -> Vec3 {
+        let pixel = &self.pixels[index];
+        let hits = pixel.hits.max(1) as f64;
+        match aov {
+            Aov::Depth if pixel.hits == 0 => Vec3::grayscale(INFINITY),
+            Aov::Depth => Vec3::grayscale(pixel.depth / hits),
+            Aov::Normal if pixel.normal.near_zero() => Vec3::default(),
+            Aov::Normal => pixel.normal.unit(),
+            Aov::Albedo => pixel.albedo / pixel.count.max(1) as f64,
+            Aov::Position => pixel.position / hits,
+            Aov::Uv => pixel.uv / hits,
+            Aov::ObjectId => Vec3::grayscale(pixel.object_id as f64),
+            Aov::MaterialId => Vec3::grayscale(pixel.material_id as f64),
+        }
+    }
+
+    // ("<aov>.<channel>", samples) for each channel of the layer
+    pub fn channels(&self, aov: Aov) -> Vec<(String, Vec<f32>)> {
+        let values: Vec<Vec3> = (0..self.pixels.len())
+            .map(|index| self.value(aov, index))
+            .collect();
+        aov.channels()
+            .iter()
+            .enumerate()
+            .map(|(i, channel)| {
+                let name = format!("{}.{}", aov.name(), channel);
+                (name, values.iter().map(|value| value[i] as f32).collect())
+            })
+            .collect()
+    }
+
+    // for formats without layers: a picture of the layer
+    pub fn to_rgb8(&self, aov: Aov) -> RgbImage {
+        let values: Vec<Vec3> = (0..self.pixels.len())
+            .map(|index| self.value(aov, index))
+            .collect();
+        let hits = |index: usize| self.pixels[index].hits > 0;
+        // depth and position are scaled to their range over the pixels that hit something
+        let (mut min, mut max) = (Vec3::grayscale(INFINITY), Vec3::grayscale(-INFINITY));
+        for (_, value) in values.iter().enumerate().filter(|&(i, _)| hits(i)) {
+            for c in 0..3 {
+                min[c] = min[c].min(value[c]);
+                max[c] = max[c].max(value[c]);
+            }
+        }
+        let normalize = |c: f64, i: usize| (c - min[i]) / (max[i] - min[i]).max(1e-8);
+        ImageBuffer::from_fn(self.width, self.height, |x, y| {
+            let index = (y * self.width + x) as usize;
+            let value = values[index];
+            let color = match aov {
+                _ if !hits(index) && aov != Aov::Albedo => Color::default(),
+                Aov::Depth => Vec3::grayscale(1. - 0.9 * normalize(value.x, 0)),
+                Aov::Normal => value * 0.5 + Vec3::grayscale(0.5),
+                Aov::Albedo => Color::new(value.x.sqrt(), value.y.sqrt(), value.z.sqrt()),
+                Aov::Position => Vec3::new(
+                    normalize(value.x, 0),
+                    normalize(value.y, 1),
+                    normalize(value.z, 2),
+                ),
+                Aov::Uv => value,
+                Aov::ObjectId | Aov::MaterialId => {
+                    let hash = splitmix64(value.x as u64);
+                    Color::new(
+                        (hash & 0xff) as f64 / 255.,
+                        (hash >> 8 & 0xff) as f64 / 255.,
+                        (hash >> 16 & 0xff) as f64 / 255.,
+                    )
+                }
+            };
+            let channel = |c: f64| (c.clamp(0., 1.) * 255.).round() as u8;
+            Rgb([channel(color.x), channel(color.y), channel(color.z)])
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        hittable::{bvh::BVHNode, sphere::Sphere},
+        integrator::MisHeuristic,
+        material::Lambertian,
+        texture::SolidColor,
+        utility,
+    };
+
+    // spheres in a grid, ordered differently along x and y, in a tree built with `seed`
+    fn world(seed: u64) -> HittableList {
+        utility::seed(seed);
+        let mut spheres = HittableList::default();
+        for i in 0..8 {
+            let center = Point3::new(i as f64 * 3., (i * 5 % 8) as f64 * 3., 0.);
+            let gray = Lambertian::new_from_color(&Color::new(0.5, 0.5, 0.5));
+            spheres.add(Box::new(Sphere::new(&center, 1., gray)));
+        }
+        HittableList::new(Box::new(BVHNode::new(spheres, TIME0, TIME1)))
+    }
+
+    fn object_ids(world: &HittableList) -> Vec<u32> {
+        let tracer = PathTracer {
+            world,
+            lights: &HittableList::default(),
+            background: &SolidColor::new(&Color::default()),
+            max_depth: 1,
+            rr_depth: 1,
+            heuristic: MisHeuristic::Power,
+        };
+        (0..8)
+            .map(|i| {
+                let mut pixel = AovPixel::default();
+                let origin = Point3::new(i as f64 * 3., (i * 5 % 8) as f64 * 3., -5.);
+                let ray = Ray::new(&origin, &Vec3::new(0., 0., 1.), 0.);
+                pixel.add(&tracer, &ray, (0., 0.), &mut MaterialIds::default());
+                pixel.object_id
+            })
+            .collect()
+    }
+
+    // the primitives of a tree get ids of their own, whatever shape the tree is built in
+    #[test]
+    fn object_ids_name_primitives() {
+        let ids = object_ids(&world(1));
+        let mut distinct = ids.clone();
+        distinct.sort_unstable();
+        distinct.dedup();
+        assert_eq!(distinct.len(), ids.len());
+        assert!(ids.iter().all(|&id| id != 0));
+        assert_eq!(ids, object_ids(&world(2)));
+    }
+}
@@ -0,0 +1,275 @@
+use crate::{
+    render::{
+        aov::{Aov, AovBuffer},
+        framebuffer::Framebuffer,
+        tonemap::luminance,
+    },
+    utility::*,
+};
+use clap::ValueEnum;
+use std::thread;
+
+const SIGMA_LUMINANCE: f64 = 4.; //standard deviations of noise two neighbours may differ by
+const SIGMA_NORMAL: i32 = 64; //exponent of the cosine between normals
+const SIGMA_DEPTH: f64 = 0.02; //relative depth difference per pixel of distance
+const SIGMA_ALBEDO: f64 = 0.1;
+const MAX_VARIANCE: f64 = 1e4; //for pixels with too few samples to estimate it
+
+const ATROUS_ITERATIONS: u32 = 5; //a 125x125 footprint
+const ATROUS_KERNEL: [f64; 5] = [1. / 16., 1. / 4., 3. / 8., 1. / 4., 1. / 16.]; //B3 spline
+const BILATERAL_RADIUS: i32 = 7;
+const BILATERAL_SIGMA: f64 = 3.5; //of the spatial gaussian, in pixels
+
+// post-process filters of the noisy image, guided by the first-hit albedo, normal and depth so
+// they keep the edges of the geometry and the textures
+#[derive(Copy, Clone, PartialEq, Eq, Debug, ValueEnum)]
+pub enum Denoiser {
+    Atrous,    //edge-avoiding à-trous wavelet, as in SVGF (Schied et al. 2017)
+    Bilateral, //joint (cross) bilateral filter
+}
+
+// per pixel: the irradiance (radiance divided by the albedo) and the variance of its luminance
+struct Signal {
+    color: Vec<Color>,
+    variance: Vec<f64>,
+}
+
+struct Guides {
+    width: i32,
+    height: i32,
+    albedo: Vec<Color>,
+    normal: Vec<Vec3>,
+    depth: Vec<f64>,
+}
+
+impl Guides {
+    // how much pixel q may contribute to pixel p, `distance` pixels apart, leaving out the color
+    fn weight(&self, p: usize, q: usize, distance: f64) -> f64 {
+        if p == q {
+            return 1.;
+        }
+        let w_albedo =
+            (-(self.albedo[p] - self.albedo[q]).length_squared() / SIGMA_ALBEDO.powi(2)).exp();
+        let (zp, zq) = (self.depth[p], self.depth[q]);
+        // pixels that hit nothing only mix with each other
+        if zp.is_infinite() || zq.is_infinite() {
+            return if zp == zq { w_albedo } else { 0. };
+        }
+        let w_depth = (-(zp - zq).abs() / (SIGMA_DEPTH * zp * distance + 1e-8)).exp();
+        let w_normal = dot(&self.normal[p], &self.normal[q])
+            .max(0.)
+            .powi(SIGMA_NORMAL);
+        w_albedo * w_depth * w_normal
+    }
+
+    fn neighbour(&self, p: usize, dx: i32, dy: i32) -> Option<usize> {
+        let (x, y) = (p as i32 % self.width + dx, p as i32 / self.width + dy);
+        if x < 0 || y < 0 || x >= self.width || y >= self.height {
+            return None;
+        }
+        Some((y * self.width + x) as usize)
+    }
+}
+
+// `variance` is that of each pixel's mean luminance, the guides come from the AOVs
+pub fn denoise(
+    denoiser: Denoiser,
+    framebuffer: &Framebuffer,
+    variance: &[f64],
+    aovs: &AovBuffer,
+    threads: usize,
+) -> Framebuffer {
+    let n = framebuffer.pixels.len();
+    let guides = Guides {
+        width: framebuffer.width as i32,
+        height: framebuffer.height as i32,
+        albedo: (0..n).map(|p| aovs.value(Aov::Albedo, p)).collect(),
+        normal: (0..n).map(|p| aovs.value(Aov::Normal, p)).collect(),
+        depth: (0..n).map(|p| aovs.value(Aov::Depth, p).x).collect(),
+    };
+    // filtering the irradiance instead of the radiance keeps the textures sharp
+    let mut signal = Signal {
+        color: Vec::with_capacity(n),
+        variance: Vec::with_capacity(n),
+    };
+    for p in 0..n {
+        let albedo = guides.albedo[p];
+        let mut color = framebuffer.pixels[p];
+        for c in 0..3 {
+            if albedo[c] > 1e-3 {
+                color[c] /= albedo[c];
+            }
+        }
+        let scale = luminance(&albedo).max(1e-3);
+        signal.color.push(color);
+        signal
+            .variance
+            .push((variance[p] / (scale * scale)).min(MAX_VARIANCE));
+    }
+
+    match denoiser {
+        Denoiser::Atrous => {
+            for iteration in 0..ATROUS_ITERATIONS {
+                signal = atrous(&signal, &guides, 1 << iteration, threads);
+            }
+        }
+        Denoiser::Bilateral => signal = bilateral(&signal, &guides, threads),
+    }
+
+    let mut denoised = framebuffer.clone();
+    for (p, pixel) in denoised.pixels.iter_mut().enumerate() {
+        let albedo = guides.albedo[p];
+        *pixel = signal.color[p];
+        for c in 0..3 {
+            if albedo[c] > 1e-3 {
+                pixel[c] *= albedo[c];
+            }
+        }
+    }
+    denoised
+}
+
+// one level of the wavelet: a 5x5 kernel with holes of `step` pixels
+fn atrous(signal: &Signal, guides: &Guides, step: i32, threads: usize) -> Signal {
+    let variance = blur_variance(signal, guides);
+    let filtered = map_pixels(signal.color.len(), threads, |p| {
+        let lp = luminance(&signal.color[p]);
+        let sigma = SIGMA_LUMINANCE * variance[p].sqrt() + 1e-8;
+        let (mut color, mut var, mut total) = (Color::default(), 0., 0.);
+        for (j, hy) in ATROUS_KERNEL.iter().enumerate() {
+            for (i, hx) in ATROUS_KERNEL.iter().enumerate() {
+                let (dx, dy) = (i as i32 - 2, j as i32 - 2);
+                let q = match guides.neighbour(p, dx * step, dy * step) {
+                    Some(q) => q,
+                    None => continue,
+                };
+                let distance = (step * dx.abs().max(dy.abs())).max(1) as f64;
+                let w_luminance = (-(lp - luminance(&signal.color[q])).abs() / sigma).exp();
+                let w = hx * hy * w_luminance * guides.weight(p, q, distance);
+                color += signal.color[q] * w;
+                var += w * w * signal.variance[q];
+                total += w;
+            }
+        }
+        // the center always has weight
+        (color / total, var / (total * total))
+    });
+    let (color, variance) = filtered.into_iter().unzip();
+    Signal { color, variance }
+}
+
+fn bilateral(signal: &Signal, guides: &Guides, threads: usize) -> Signal {
+    let variance = blur_variance(signal, guides);
+    let filtered = map_pixels(signal.color.len(), threads, |p| {
+        let lp = luminance(&signal.color[p]);
+        let sigma = SIGMA_LUMINANCE * variance[p].sqrt() + 1e-8;
+        let (mut color, mut total) = (Color::default(), 0.);
+        for dy in -BILATERAL_RADIUS..=BILATERAL_RADIUS {
+            for dx in -BILATERAL_RADIUS..=BILATERAL_RADIUS {
+                let q = match guides.neighbour(p, dx, dy) {
+                    Some(q) => q,
+                    None => continue,
+                };
+                let d2 = (dx * dx + dy * dy) as f64;
+                let w_spatial = (-d2 / (2. * BILATERAL_SIGMA * BILATERAL_SIGMA)).exp();
+                let w_luminance = (-(lp - luminance(&signal.color[q])).abs() / sigma).exp();
+                let w = w_spatial * w_luminance * guides.weight(p, q, d2.sqrt().max(1.));
+                color += signal.color[q] * w;
+                total += w;
+            }
+        }
+        (color / total, signal.variance[p])
+    });
+    let (color, variance) = filtered.into_iter().unzip();
+    Signal { color, variance }
+}
+
+// the variance estimates of few samples are noisy themselves, so they are averaged over 3x3
+fn blur_variance(signal: &Signal, guides: &Guides) -> Vec<f64> {
+    const KERNEL: [f64; 3] = [1. / 4., 1. / 2., 1. / 4.];
+    (0..signal.variance.len())
+        .map(|p| {
+            let (mut sum, mut total) = (0., 0.);
+            for (j, hy) in KERNEL.iter().enumerate() {
+                for (i, hx) in KERNEL.iter().enumerate() {
+                    if let Some(q) = guides.neighbour(p, i as i32 - 1, j as i32 - 1) {
+                        sum += hx * hy * signal.variance[q];
+                        total += hx * hy;
+                    }
+                }
+            }
+            sum / total
+        })
+        .collect()
+}
+
+// f of every pixel, the image split in bands across the threads
+fn map_pixels<T: Send>(n: usize, threads: usize, f: impl Fn(usize) -> T + Sync) -> Vec<T> {
+    let band = ((n + threads - 1) / threads).max(1);
+    thread::scope(|scope| {
+        let handles: Vec<_> = (0..n)
+            .step_by(band)
+            .map(|start| {
+                let f = &f;
+                scope.spawn(move || (start..(start + band).min(n)).map(f).collect::<Vec<_>>())
+            })
+            .collect();
+        handles
+            .into_iter()
+            .flat_map(|handle| handle.join().unwrap())
+            .collect()
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::render::aov::AovPixel;
+
+    // two flat walls of different albedo and facing, under the same light, with noise of a known
+    // variance: both filters take most of the noise away and keep the walls apart
+    #[test]
+    fn denoising_keeps_the_edges() {
+        let (width, height) = (32, 16);
+        let albedo = |x: u32| if x < width / 2 { 0.2 } else { 0.8 };
+        let mut aovs = AovBuffer::new(width, height);
+        let mut noisy = Framebuffer::new(width, height);
+        let mut rng = Rng::new(3);
+        let sigma = 0.1;
+        for (p, pixel) in aovs.pixels.iter_mut().enumerate() {
+            let x = p as u32 % width;
+            *pixel = AovPixel {
+                count: 1,
+                hits: 1,
+                depth: 1.,
+                normal: if x < width / 2 {
+                    Vec3::new(0., 0., 1.)
+                } else {
+                    Vec3::new(1., 0., 0.)
+                },
+                albedo: Color::grayscale(albedo(x)),
+                ..Default::default()
+            };
+            let noise = sigma * 3f64.sqrt() * (2. * rng.random() - 1.);
+            noisy.pixels[p] = Color::grayscale(albedo(x) * (1. + noise));
+        }
+        let variance: Vec<f64> = (0..noisy.pixels.len())
+            .map(|p| (albedo(p as u32 % width) * sigma).powi(2))
+            .collect();
+        let error = |image: &Framebuffer| {
+            let squares = image.pixels.iter().enumerate().map(|(p, c)| {
+                let truth = albedo(p as u32 % width);
+                (*c - Color::grayscale(truth)).length_squared() / 3.
+            });
+            squares.sum::<f64>() / image.pixels.len() as f64
+        };
+        for denoiser in [Denoiser::Atrous, Denoiser::Bilateral] {
+            let denoised = denoise(denoiser, &noisy, &variance, &aovs, 2);
+            assert!(error(&denoised) < 0.25 * error(&noisy), "{:?}", denoiser);
+            for y in 0..height {
+                let (left, right) = (denoised.get(15, y).x, denoised.get(16, y).x);
+                assert!((left - 0.2).abs() < 0.05 && (right - 0.8).abs() < 0.1);
+            }
+        }
+    }
+}
@@ -0,0 +1,91 @@
+use crate::{
+    hittable::{Hittable, HittableList},
+    integrator::{Fault, FaultKind},
+    material::Material,
+};
+use console::style;
+use serde::Serialize;
+use std::collections::HashMap;
+
+const WORST_PIXELS: usize = 10;
+
+// samples that came out NaN, infinite or negative, counted per pixel and per source
+#[derive(Default)]
+pub struct Diagnostics<'a> {
+    pub pixels: HashMap<usize, u32>,
+    pub sources: HashMap<Fault<'a>, u64>,
+}
+
+impl<'a> Diagnostics<'a> {
+    pub fn add(&mut self, index: usize, fault: Fault<'a>) {
+        *self.pixels.entry(index).or_default() += 1;
+        *self.sources.entry(fault).or_default() += 1;
+    }
+
+    pub fn merge(&mut self, other: Diagnostics<'a>) {
+        for (index, count) in other.pixels {
+            *self.pixels.entry(index).or_default() += count;
+        }
+        for (fault, count) in other.sources {
+            *self.sources.entry(fault).or_default() += count;
+        }
+    }
+
+    pub fn total(&self) -> u64 {
+        self.sources.values().sum()
+    }
+
+    pub fn count(&self, kind: FaultKind) -> u64 {
+        let sources = self.sources.iter().filter(|(fault, _)| fault.kind == kind);
+        sources.map(|(_, count)| count).sum()
+    }
+
+    // the pixels with the most invalid samples and what produced them
+    pub fn report(&self, world: &HittableList, width: u32) {
+        println!(
+            "{}: {} NaN, {} infinite and {} negative, in {} pixels",
+            style("Invalid samples").red(),
+            self.count(FaultKind::NaN),
+            self.count(FaultKind::Infinite),
+            self.count(FaultKind::Negative),
+            self.pixels.len()
+        );
+        let mut pixels: Vec<_> = self.pixels.iter().collect();
+        pixels.sort_by_key(|&(&index, &count)| (std::cmp::Reverse(count), index));
+        for (&index, count) in pixels.into_iter().take(WORST_PIXELS) {
+            let (x, y) = (index as u32 % width, index as u32 / width);
+            println!("  pixel ({}, {}): {}", x, y, count);
+        }
+        let mut sources: Vec<_> = self.sources.iter().collect();
+        sources.sort_by_key(|&(fault, &count)| {
+            (std::cmp::Reverse(count), fault.object, fault.primitive)
+        });
+        // each object and material is described once, however many faults it has
+        let mut objects = HashMap::new();
+        let mut materials = HashMap::new();
+        for (fault, count) in sources {
+            let source = match (fault.object, fault.material) {
+                (Some(object), Some(material)) => {
+                    let object_type = objects
+                        .entry(object)
+                        .or_insert_with(|| type_of(world.objects[object].describe()));
+                    let material_type = materials
+                        .entry(material as *const dyn Material as *const ())
+                        .or_insert_with(|| type_of(material.describe()));
+                    format!(
+                        "object #{} ({}), primitive {:#08x}, {} material",
+                        object, object_type, fault.primitive, material_type
+                    )
+                }
+                _ => "the background".to_owned(),
+            };
+            println!("  {:?} from {}: {}", fault.kind, source, count);
+        }
+    }
+}
+
+// the `type` of a description of the scene file format
+fn type_of(desc: impl Serialize) -> String {
+    let desc = serde_json::to_value(desc).unwrap();
+    desc["type"].as_str().unwrap_or("unknown").to_owned()
+}
@@ -0,0 +1,453 @@
+use crate::{
+    render::{filter::*, framebuffer::Framebuffer, scheduler::Tile, tonemap::luminance},
+    utility::*,
+};
+use std::{
+    collections::BTreeMap,
+    fs::{self, File},
+    io::{self, BufReader, BufWriter, Read, Write},
+    ops::RangeInclusive,
+    path::Path,
+};
+
+const CHECKPOINT_MAGIC: &[u8; 8] = b"RTFILM\0\x05";
+// magic, width, height, passes, seed and the length of the settings
+const HEADER_BYTES: u64 = 8 + 4 * 3 + 8 + 4;
+// sum, sum of squares, samples, filtered sum, weight and light of a pixel
+const PIXEL_BYTES: u64 = 24 + 8 + 4 + 24 + 8 + 24;
+// filter weights closer to 0 than this leave a pixel black: with the negative lobes of a filter,
+// a few samples can sum to a weight near 0 and blow up their mean
+const MIN_WEIGHT: f64 = 1e-6;
+
+// sums of the samples a pixel got in a render pass
+#[derive(Copy, Clone, Default, Debug)]
+pub struct PixelSamples {
+    pub sum: Color,
+    pub sum_sq: f64, //of the luminance
+    pub count: u32,
+}
+
+// the samples of a tile weighted by the reconstruction filter, over the tile and the border
+// of pixels its samples reach
+pub struct Splats {
+    pub x0: u32,
+    pub y0: u32,
+    pub x1: u32,
+    pub y1: u32,
+    pub sum: Vec<Color>,
+    pub weight: Vec<f64>,
+}
+
+impl Splats {
+    pub fn new(tile: &Tile, filter: &Filter, width: u32, height: u32) -> Self {
+        let pad = filter.padding();
+        let (x0, y0) = (tile.x0.saturating_sub(pad), tile.y0.saturating_sub(pad));
+        let (x1, y1) = ((tile.x1 + pad).min(width), (tile.y1 + pad).min(height));
+        let size = ((x1 - x0) * (y1 - y0)) as usize;
+        Self {
+            x0,
+            y0,
+            x1,
+            y1,
+            sum: vec![Color::default(); size],
+            weight: vec![0.; size],
+        }
+    }
+
+    // the pixels the splats reach
+    pub fn region(&self) -> Tile {
+        Tile {
+            index: 0,
+            x0: self.x0,
+            y0: self.y0,
+            x1: self.x1,
+            y1: self.y1,
+        }
+    }
+
+    // a sample at (x, y), in pixels from the top left corner of the image
+    pub fn add(&mut self, filter: &Filter, x: f64, y: f64, color: &Color) {
+        for py in footprint(filter, y, self.y0, self.y1) {
+            for px in footprint(filter, x, self.x0, self.x1) {
+                let weight = filter.evaluate(px as f64 + 0.5 - x, py as f64 + 0.5 - y);
+                if weight != 0. {
+                    let index = ((py - self.y0) * (self.x1 - self.x0) + px - self.x0) as usize;
+                    self.sum[index] += *color * weight;
+                    self.weight[index] += weight;
+                }
+            }
+        }
+    }
+}
+
+// the pixels in [lo, hi) of a row or column whose centers are within the radius of the filter
+// from `c`
+fn footprint(filter: &Filter, c: f64, lo: u32, hi: u32) -> RangeInclusive<u32> {
+    let first = (c - 0.5 - filter.radius).ceil().max(lo as f64) as u32;
+    let last = (c - 0.5 + filter.radius).floor().min(hi as f64 - 1.) as u32;
+    first..=last
+}
+
+// what the light paths of a tile bring to the pixels of the region they land in, spread by the
+// reconstruction filter, by pixel index. only the pixels reached are kept, in the order of their
+// index, so a tile holds at most one sum per pixel and adds them to the film in a fixed order
+pub struct LightSplats {
+    pub width: u32,
+    pub height: u32,
+    pub region: Tile,
+    pub sum: BTreeMap<usize, Color>,
+}
+
+impl LightSplats {
+    pub fn new(width: u32, height: u32, region: &Tile) -> Self {
+        Self {
+            width,
+            height,
+            region: *region,
+            sum: BTreeMap::new(),
+        }
+    }
+
+    // a light path that reached the film at (x, y), in pixels from the top left corner. the
+    // weights are normalized, so the filter spreads its light without changing how much there is
+    pub fn add(&mut self, filter: &Filter, x: f64, y: f64, color: &Color) {
+        let rows = footprint(filter, y, 0, self.height);
+        let columns = footprint(filter, x, 0, self.width);
+        let mut total = 0.;
+        for py in rows.clone() {
+            for px in columns.clone() {
+                total += filter.evaluate(px as f64 + 0.5 - x, py as f64 + 0.5 - y);
+            }
+        }
+        if total.abs() <= MIN_WEIGHT {
+            return;
+        }
+        for py in rows {
+            for px in columns.clone() {
+                let weight = filter.evaluate(px as f64 + 0.5 - x, py as f64 + 0.5 - y);
+                if weight != 0. && self.region.contains(px, py) {
+                    let index = (py * self.width + px) as usize;
+                    *self.sum.entry(index).or_default() += *color * (weight / total);
+                }
+            }
+        }
+    }
+}
+
+// running sums of the radiance samples of each pixel, row by row from the top of the image
+#[derive(Clone)]
+pub struct Film {
+    pub width: u32,
+    pub height: u32,
+    pub sum: Vec<Color>,
+    pub sum_sq: Vec<f64>, //of the luminance, for the variance
+    pub samples: Vec<u32>,
+    // the samples splatted through the reconstruction filter, which make the image
+    pub filtered: Vec<Color>,
+    pub weight: Vec<f64>,
+    // what the paths from the lights bring to the camera, added wherever they land; each sample
+    // traces one such path with the bidirectional integrator
+    pub light: Vec<Color>,
+    pub passes: u32, //completed render passes
+    // what the samples depend on, kept with a checkpoint so a render is only resumed with them
+    pub seed: u64,
+    pub settings: String,
+}
+
+impl Film {
+    pub fn new(width: u32, height: u32) -> Self {
+        let size = width as usize * height as usize;
+        Self {
+            width,
+            height,
+            sum: vec![Color::default(); size],
+            sum_sq: vec![0.; size],
+            samples: vec![0; size],
+            filtered: vec![Color::default(); size],
+            weight: vec![0.; size],
+            light: vec![Color::default(); size],
+            passes: 0,
+            seed: 0,
+            settings: String::new(),
+        }
+    }
+
+    pub fn index(&self, x: u32, y: u32) -> usize {
+        (y * self.width + x) as usize
+    }
+
+    // sums of the samples of each pixel of the tile, row by row, and their splats
+    pub fn add_tile(&mut self, tile: &Tile, sums: &[PixelSamples], splats: &Splats) {
+        for ((x, y), samples) in tile.pixels().zip(sums) {
+            let index = self.index(x, y);
+            self.sum[index] += samples.sum;
+            self.sum_sq[index] += samples.sum_sq;
+            self.samples[index] += samples.count;
+        }
+        let mut i = 0;
+        for y in splats.y0..splats.y1 {
+            for x in splats.x0..splats.x1 {
+                let index = self.index(x, y);
+                self.filtered[index] += splats.sum[i];
+                self.weight[index] += splats.weight[i];
+                i += 1;
+            }
+        }
+    }
+
+    // the light paths of a tile that reached the camera
+    pub fn add_light(&mut self, splats: &LightSplats) {
+        for (&index, color) in &splats.sum {
+            self.light[index] += *color;
+        }
+    }
+
+    // mean and variance of the luminance of a pixel's samples
+    pub fn luminance_stats(&self, index: usize) -> (f64, f64) {
+        let n = self.samples[index] as f64;
+        if n < 2. {
+            return (0., INFINITY);
+        }
+        let mean = luminance(&self.sum[index]) / n;
+        let variance = (self.sum_sq[index] / n - mean * mean).max(0.) * n / (n - 1.);
+        (mean, variance)
+    }
+
+    // variance of the mean luminance of each pixel, infinite below two samples
+    pub fn mean_variance(&self) -> Vec<f64> {
+        (0..self.samples.len())
+            .map(|index| self.luminance_stats(index).1 / self.samples[index].max(1) as f64)
+            .collect()
+    }
+
+    // the filtered radiance of a pixel; a filter with negative lobes can make it negative, which
+    // is clamped to black
+    pub fn resolve_pixel(&self, index: usize) -> Color {
+        let weight = self.weight[index];
+        if weight.abs() <= MIN_WEIGHT {
+            return Color::default();
+        }
+        let color = self.filtered[index] / weight;
+        Color::new(color.x.max(0.), color.y.max(0.), color.z.max(0.))
+    }
+
+    // the filtered radiance of the pixels of `region`, written into the image as tiles are added
+    pub fn resolve_region(&self, region: &Tile, framebuffer: &mut Framebuffer) {
+        for (x, y) in region.pixels() {
+            framebuffer.set(x, y, self.resolve_pixel(self.index(x, y)));
+        }
+    }
+
+    // what the light paths brought to each pixel, averaged over all the samples of the image
+    pub fn add_light_paths(&self, framebuffer: &mut Framebuffer) {
+        let light_paths = self.total_samples().max(1) as f64;
+        for (pixel, light) in framebuffer.pixels.iter_mut().zip(&self.light) {
+            *pixel += *light / light_paths;
+        }
+    }
+
+    // the image: the filtered radiance of each pixel and the light paths
+    pub fn resolve(&self) -> Framebuffer {
+        let mut framebuffer = Framebuffer::new(self.width, self.height);
+        let full = Tile {
+            index: 0,
+            x0: 0,
+            y0: 0,
+            x1: self.width,
+            y1: self.height,
+        };
+        self.resolve_region(&full, &mut framebuffer);
+        self.add_light_paths(&mut framebuffer);
+        framebuffer
+    }
+
+    // samples every pixel has
+    pub fn min_samples(&self) -> u32 {
+        self.samples.iter().copied().min().unwrap_or(0)
+    }
+
+    pub fn total_samples(&self) -> u64 {
+        self.samples.iter().map(|&n| n as u64).sum()
+    }
+
+    // checkpoint: magic, width, height, passes, seed, settings, then the sums, sample count,
+    // filtered sums and light path sums of each pixel,
+    // little endian; written to a temporary file first so an interruption leaves the old one intact
+    pub fn save(&self, path: &Path) -> io::Result<()> {
+        let temp = path.with_extension("tmp");
+        let mut writer = BufWriter::new(File::create(&temp)?);
+        writer.write_all(CHECKPOINT_MAGIC)?;
+        for n in [self.width, self.height, self.passes] {
+            writer.write_all(&n.to_le_bytes())?;
+        }
+        writer.write_all(&self.seed.to_le_bytes())?;
+        writer.write_all(&(self.settings.len() as u32).to_le_bytes())?;
+        writer.write_all(self.settings.as_bytes())?;
+        for index in 0..self.samples.len() {
+            for c in self.sum[index].to_array() {
+                writer.write_all(&c.to_le_bytes())?;
+            }
+            writer.write_all(&self.sum_sq[index].to_le_bytes())?;
+            writer.write_all(&self.samples[index].to_le_bytes())?;
+            for c in self.filtered[index].to_array() {
+                writer.write_all(&c.to_le_bytes())?;
+            }
+            writer.write_all(&self.weight[index].to_le_bytes())?;
+            for c in self.light[index].to_array() {
+                writer.write_all(&c.to_le_bytes())?;
+            }
+        }
+        writer.into_inner()?.sync_all()?;
+        fs::rename(temp, path)
+    }
+
+    pub fn load(path: &Path) -> io::Result<Self> {
+        let invalid = |message: &str| io::Error::new(io::ErrorKind::InvalidData, message);
+        let file = File::open(path)?;
+        let file_size = file.metadata()?.len();
+        let mut reader = BufReader::new(file);
+        let mut magic = [0; 8];
+        reader.read_exact(&mut magic)?;
+        if &magic != CHECKPOINT_MAGIC {
+            return Err(invalid("not a checkpoint file"));
+        }
+        let read_u32 = |reader: &mut BufReader<File>| -> io::Result<u32> {
+            let mut bytes = [0; 4];
+            reader.read_exact(&mut bytes)?;
+            Ok(u32::from_le_bytes(bytes))
+        };
+        let width = read_u32(&mut reader)?;
+        let height = read_u32(&mut reader)?;
+        let passes = read_u32(&mut reader)?;
+        let mut seed = [0; 8];
+        reader.read_exact(&mut seed)?;
+        let settings_len = read_u32(&mut reader)?;
+        // the size the header gives must be that of the file, before anything is allocated
+        let pixels = (width as u64)
+            .checked_mul(height as u64)
+            .filter(|&n| usize::try_from(n).is_ok())
+            .ok_or_else(|| invalid("the checkpoint is too large"))?;
+        let expected = pixels
+            .checked_mul(PIXEL_BYTES)
+            .and_then(|n| n.checked_add(HEADER_BYTES + settings_len as u64));
+        if expected != Some(file_size) {
+            return Err(invalid("the checkpoint is truncated or corrupt"));
+        }
+        let mut settings = vec![0; settings_len as usize];
+        reader.read_exact(&mut settings)?;
+        let settings =
+            String::from_utf8(settings).map_err(|_| invalid("the checkpoint is corrupt"))?;
+        let mut film = Film::new(width, height);
+        film.passes = passes;
+        film.seed = u64::from_le_bytes(seed);
+        film.settings = settings;
+        let read_f64 = |reader: &mut BufReader<File>| -> io::Result<f64> {
+            let mut bytes = [0; 8];
+            reader.read_exact(&mut bytes)?;
+            Ok(f64::from_le_bytes(bytes))
+        };
+        for index in 0..film.samples.len() {
+            for i in 0..3 {
+                film.sum[index][i] = read_f64(&mut reader)?;
+            }
+            film.sum_sq[index] = read_f64(&mut reader)?;
+            film.samples[index] = read_u32(&mut reader)?;
+            for i in 0..3 {
+                film.filtered[index][i] = read_f64(&mut reader)?;
+            }
+            film.weight[index] = read_f64(&mut reader)?;
+            for i in 0..3 {
+                film.light[index][i] = read_f64(&mut reader)?;
+            }
+        }
+        if reader.read(&mut [0])? != 0 {
+            return Err(invalid("trailing data after the checkpoint"));
+        }
+        Ok(film)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{env, process};
+
+    // a header that does not give the size of the file is refused before anything is allocated
+    #[test]
+    fn load_checks_the_size() {
+        let path = env::temp_dir().join(format!("raytracer_film_{}.rtfilm", process::id()));
+        let mut film = Film::new(3, 2);
+        film.seed = 7;
+        film.settings = "seed 7".to_string();
+        film.sum[4] = Color::new(1., 2., 3.);
+        film.save(&path).unwrap();
+        let loaded = Film::load(&path).unwrap();
+        assert_eq!((loaded.seed, loaded.settings.as_str()), (7, "seed 7"));
+        assert_eq!(loaded.sum[4].to_array(), [1., 2., 3.]);
+
+        let bytes = fs::read(&path).unwrap();
+        let mut huge = bytes.clone();
+        huge[8..16].copy_from_slice(&[0xff; 8]);
+        let truncated = &bytes[..bytes.len() - 1];
+        for corrupt in [&huge[..], truncated] {
+            fs::write(&path, corrupt).unwrap();
+            let error = Film::load(&path).err().unwrap();
+            assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+        }
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn resolve_guards_the_filter_weight() {
+        let mut film = Film::new(3, 1);
+        film.filtered = vec![Color::new(1., 1., 1.); 3];
+        film.weight = vec![1e-9, -0.5, 2.];
+        let pixels = film.resolve().pixels;
+        assert_eq!(pixels[0].to_array(), [0.; 3]); //too little weight
+        assert_eq!(pixels[1].to_array(), [0.; 3]); //negative
+        assert_eq!(pixels[2].to_array(), [0.5; 3]);
+    }
+
+    // a sample on the edge between two pixels counts in exactly one of them with the box filter
+    #[test]
+    fn box_counts_edge_samples_once() {
+        let whole = Tile {
+            index: 0,
+            x0: 0,
+            y0: 0,
+            x1: 4,
+            y1: 4,
+        };
+        let filter = Filter::default();
+        let mut splats = Splats::new(&whole, &filter, 4, 4);
+        splats.add(&filter, 2., 1.5, &Color::new(1., 1., 1.));
+        splats.add(&filter, 1.5, 3., &Color::new(1., 1., 1.));
+        assert_eq!(splats.weight.iter().sum::<f64>(), 2.);
+        assert_eq!(splats.weight[4 + 2], 1.); //the pixel at (2, 1) holds x = 2
+        assert_eq!(splats.weight[3 * 4 + 1], 1.);
+    }
+
+    // the filter spreads the light of a path without changing how much there is, within the region
+    #[test]
+    fn light_splats_keep_their_light() {
+        let filter = Filter::new(FilterKind::Gaussian, None);
+        let whole = Tile {
+            index: 0,
+            x0: 0,
+            y0: 0,
+            x1: 8,
+            y1: 8,
+        };
+        let mut splats = LightSplats::new(8, 8, &whole);
+        splats.add(&filter, 3.3, 4.6, &Color::new(1., 2., 3.));
+        assert!(splats.sum.len() > 1);
+        let total = splats.sum.values().fold(Color::default(), |a, &b| a + b);
+        assert!((total - Color::new(1., 2., 3.)).length() < 1e-9);
+
+        let corner = Tile { x1: 3, ..whole };
+        let mut clipped = LightSplats::new(8, 8, &corner);
+        clipped.add(&filter, 3.3, 4.6, &Color::new(1., 2., 3.));
+        assert!(clipped.sum.keys().all(|&index| index % 8 < 3));
+        assert!(clipped.sum.len() < splats.sum.len());
+    }
+}
@@ -0,0 +1,105 @@
+use clap::ValueEnum;
+
+#[derive(Copy, Clone, PartialEq, Eq, Debug, ValueEnum)]
+pub enum FilterKind {
+    Box,
+    Tent,
+    Gaussian, //sigma of a third of the radius
+    Mitchell, //Mitchell-Netravali, B = C = 1/3
+    Lanczos,  //sinc windowed by a sinc as wide as the radius
+}
+
+impl FilterKind {
+    pub fn default_radius(self) -> f64 {
+        match self {
+            Self::Box => 0.5,
+            Self::Tent => 1.,
+            Self::Gaussian => 1.5,
+            Self::Mitchell => 2.,
+            Self::Lanczos => 3.,
+        }
+    }
+}
+
+// separable reconstruction filter; a sample contributes to every pixel whose center is closer
+// than the radius (in pixels) on both axes, weighted by the filter
+#[derive(Copy, Clone, Debug)]
+pub struct Filter {
+    pub kind: FilterKind,
+    pub radius: f64,
+}
+
+impl Default for Filter {
+    fn default() -> Self {
+        Self {
+            kind: FilterKind::Box,
+            radius: FilterKind::Box.default_radius(),
+        }
+    }
+}
+
+impl Filter {
+    pub fn new(kind: FilterKind, radius: Option<f64>) -> Self {
+        Self {
+            kind,
+            radius: radius.unwrap_or_else(|| kind.default_radius()),
+        }
+    }
+
+    pub fn evaluate(&self, dx: f64, dy: f64) -> f64 {
+        self.evaluate_1d(dx) * self.evaluate_1d(dy)
+    }
+
+    // `x` is from the sample to the center of the pixel
+    fn evaluate_1d(&self, x: f64) -> f64 {
+        // half-open, so that a sample on the edge between two pixels counts once with the box
+        // filter, in the pixel it is in; the other filters are 0 at the radius
+        let r = self.radius;
+        if x <= -r || x > r {
+            return 0.;
+        }
+        let x = x.abs();
+        match self.kind {
+            FilterKind::Box => 1.,
+            FilterKind::Tent => 1. - x / r,
+            FilterKind::Gaussian => {
+                let sigma = r / 3.;
+                let gaussian = |x: f64| (-x * x / (2. * sigma * sigma)).exp();
+                // shifted to reach zero at the radius
+                gaussian(x) - gaussian(r)
+            }
+            FilterKind::Mitchell => mitchell(2. * x / r),
+            FilterKind::Lanczos => sinc(x) * sinc(x / r),
+        }
+    }
+
+    // pixels a sample can reach beyond its own, on each side
+    pub fn padding(&self) -> u32 {
+        (self.radius - 0.5).ceil().max(0.) as u32
+    }
+}
+
+// for x in [0, 2)
+fn mitchell(x: f64) -> f64 {
+    const B: f64 = 1. / 3.;
+    const C: f64 = 1. / 3.;
+    let (x2, x3) = (x * x, x * x * x);
+    if x < 1. {
+        ((12. - 9. * B - 6. * C) * x3 + (-18. + 12. * B + 6. * C) * x2 + (6. - 2. * B)) / 6.
+    } else {
+        ((-B - 6. * C) * x3
+            + (6. * B + 30. * C) * x2
+            + (-12. * B - 48. * C) * x
+            + (8. * B + 24. * C))
+            / 6.
+    }
+}
+
+fn sinc(x: f64) -> f64 {
+    let x = std::f64::consts::PI * x;
+    if x.abs() < 1e-5 {
+        1.
+    } else {
+        x.sin() / x
+    }
+}
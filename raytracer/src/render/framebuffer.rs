@@ -0,0 +1,43 @@
+use crate::{render::scheduler::Tile, utility::*};
+
+// linear radiance of each pixel, row by row from the top of the image
+#[derive(Clone)]
+pub struct Framebuffer {
+    pub width: u32,
+    pub height: u32,
+    pub pixels: Vec<Color>,
+}
+
+impl Framebuffer {
+    pub fn new(width: u32, height: u32) -> Self {
+        Self {
+            width,
+            height,
+            pixels: vec![Color::default(); (width * height) as usize],
+        }
+    }
+
+    pub fn get(&self, x: u32, y: u32) -> Color {
+        self.pixels[(y * self.width + x) as usize]
+    }
+
+    pub fn set(&mut self, x: u32, y: u32, color: Color) {
+        self.pixels[(y * self.width + x) as usize] = color;
+    }
+
+    // the pixels of the region, as an image of its own
+    pub fn crop(&self, region: &Tile) -> Framebuffer {
+        Framebuffer {
+            width: region.width(),
+            height: region.height(),
+            pixels: region.crop(&self.pixels, self.width),
+        }
+    }
+
+    // pixels of the tile row by row
+    pub fn set_tile(&mut self, tile: &Tile, pixels: &[Color]) {
+        for ((x, y), color) in tile.pixels().zip(pixels) {
+            self.set(x, y, *color);
+        }
+    }
+}
@@ -0,0 +1,23 @@
+pub mod adaptive;
+pub mod aov;
+pub mod denoise;
+pub mod diagnostics;
+pub mod film;
+pub mod filter;
+pub mod framebuffer;
+pub mod output;
+pub mod scheduler;
+pub mod stats;
+pub mod tonemap;
+
+pub use adaptive::*;
+pub use aov::*;
+pub use denoise::*;
+pub use diagnostics::*;
+pub use film::*;
+pub use filter::*;
+pub use framebuffer::*;
+pub use output::*;
+pub use scheduler::*;
+pub use stats::*;
+pub use tonemap::*;
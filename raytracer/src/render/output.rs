@@ -0,0 +1,173 @@
+use crate::render::{framebuffer::Framebuffer, tonemap::ColorPipeline};
+use clap::ValueEnum;
+use image::{
+    codecs::{
+        hdr::HdrEncoder,
+        pnm::{PNMSubtype, SampleEncoding},
+    },
+    DynamicImage, ImageBuffer, ImageOutputFormat, Rgb, RgbImage,
+};
+use std::{error::Error, fs::File, io::BufWriter, path::Path};
+
+const JPEG_QUALITY: u8 = 100;
+
+#[derive(Copy, Clone, PartialEq, Eq, Debug, ValueEnum)]
+pub enum OutputFormat {
+    Jpeg,
+    Png,
+    Ppm, //plain (ASCII) PPM
+    Hdr, //Radiance RGBE
+    Exr, //OpenEXR, 32-bit float
+}
+
+impl OutputFormat {
+    pub fn from_path(path: &Path) -> Option<Self> {
+        let extension = path.extension()?.to_str()?.to_lowercase();
+        Some(match extension.as_str() {
+            "jpg" | "jpeg" => Self::Jpeg,
+            "png" => Self::Png,
+            "ppm" => Self::Ppm,
+            "hdr" => Self::Hdr,
+            "exr" => Self::Exr,
+            _ => return None,
+        })
+    }
+
+    // high-dynamic-range formats store the linear radiance, without the display transform
+    pub fn is_hdr(self) -> bool {
+        matches!(self, Self::Hdr | Self::Exr)
+    }
+}
+
+pub fn to_rgb8(framebuffer: &Framebuffer, pipeline: &ColorPipeline) -> RgbImage {
+    let display = pipeline.apply(framebuffer);
+    ImageBuffer::from_fn(framebuffer.width, framebuffer.height, |x, y| {
+        let pixel_color = display[(y * framebuffer.width + x) as usize] * 255.;
+        let (r, g, b) = pixel_color.to_tuple();
+        Rgb([r.round() as u8, g.round() as u8, b.round() as u8])
+    })
+}
+
+pub fn write_image(
+    framebuffer: &Framebuffer,
+    path: &Path,
+    format: OutputFormat,
+    pipeline: &ColorPipeline,
+) -> Result<(), Box<dyn Error>> {
+    let (width, height) = (framebuffer.width as usize, framebuffer.height as usize);
+    match format {
+        OutputFormat::Jpeg | OutputFormat::Png | OutputFormat::Ppm => {
+            let output_format = match format {
+                OutputFormat::Jpeg => ImageOutputFormat::Jpeg(JPEG_QUALITY),
+                OutputFormat::Png => ImageOutputFormat::Png,
+                _ => ImageOutputFormat::Pnm(PNMSubtype::Pixmap(SampleEncoding::Ascii)),
+            };
+            let mut output_file = BufWriter::new(File::create(path)?);
+            DynamicImage::ImageRgb8(to_rgb8(framebuffer, pipeline))
+                .write_to(&mut output_file, output_format)?;
+        }
+        OutputFormat::Hdr => {
+            let data: Vec<Rgb<f32>> = framebuffer
+                .pixels
+                .iter()
+                .map(|color| Rgb([color.x as f32, color.y as f32, color.z as f32]))
+                .collect();
+            HdrEncoder::new(BufWriter::new(File::create(path)?)).encode(&data, width, height)?;
+        }
+        OutputFormat::Exr => {
+            exr::prelude::write_rgb_file(path, width, height, |x, y| {
+                let color = framebuffer.pixels[y * width + x];
+                (color.x as f32, color.y as f32, color.z as f32)
+            })?;
+        }
+    }
+    Ok(())
+}
+
+// the image as R, G, B and the extra `layers`, (channel name, samples row by row), in one EXR
+pub fn write_exr_layers(
+    framebuffer: &Framebuffer,
+    layers: Vec<(String, Vec<f32>)>,
+    path: &Path,
+) -> Result<(), Box<dyn Error>> {
+    use exr::prelude::*;
+    let rgb = ["R", "G", "B"].iter().enumerate().map(|(i, &name)| {
+        let samples = framebuffer.pixels.iter().map(|color| color[i] as f32);
+        (name.to_owned(), samples.collect())
+    });
+    let channels = rgb
+        .chain(layers)
+        .map(|(name, samples)| AnyChannel::new(name.as_str(), FlatSamples::F32(samples)))
+        .collect();
+    let size = (framebuffer.width as usize, framebuffer.height as usize);
+    Image::from_channels(size, AnyChannels::sort(channels))
+        .write()
+        .to_file(path)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utility::Color;
+    use image::codecs::hdr::HdrDecoder;
+    use std::{env, fs, io::BufReader, process};
+
+    // the high-dynamic-range formats keep radiance above 1; the others keep the display image
+    #[test]
+    fn formats_keep_what_they_store() {
+        let mut framebuffer = Framebuffer::new(3, 2);
+        for (i, pixel) in framebuffer.pixels.iter_mut().enumerate() {
+            *pixel = Color::new(i as f64 * 2.5, 0.25, 0.01 * i as f64);
+        }
+        let pipeline = ColorPipeline::default();
+        let display = to_rgb8(&framebuffer, &pipeline);
+        for format in [
+            OutputFormat::Png,
+            OutputFormat::Ppm,
+            OutputFormat::Hdr,
+            OutputFormat::Exr,
+        ] {
+            let path =
+                env::temp_dir().join(format!("raytracer_output_{}.{:?}", process::id(), format));
+            write_image(&framebuffer, &path, format, &pipeline).unwrap();
+            let stored: Vec<[f64; 3]> = match format {
+                OutputFormat::Hdr => {
+                    let decoder = HdrDecoder::new(BufReader::new(File::open(&path).unwrap()));
+                    let pixels = decoder.unwrap().read_image_hdr().unwrap();
+                    pixels.iter().map(|p| p.0.map(|c| c as f64)).collect()
+                }
+                OutputFormat::Exr => {
+                    let image = exr::prelude::read_first_rgba_layer_from_file(
+                        &path,
+                        |resolution, _| vec![[0.; 3]; resolution.width() * resolution.height()],
+                        |pixels: &mut Vec<[f64; 3]>,
+                         position,
+                         (r, g, b, _): (f32, f32, f32, f32)| {
+                            pixels[position.y() * 3 + position.x()] = [r, g, b].map(|c| c as f64)
+                        },
+                    )
+                    .unwrap();
+                    image.layer_data.channel_data.pixels
+                }
+                _ => {
+                    if format == OutputFormat::Ppm {
+                        assert!(fs::read(&path).unwrap().starts_with(b"P3"));
+                    }
+                    let image = image::open(&path).unwrap().to_rgb8();
+                    assert_eq!(image.into_raw(), display.clone().into_raw());
+                    fs::remove_file(&path).unwrap();
+                    continue;
+                }
+            };
+            fs::remove_file(&path).unwrap();
+            // RGBE shares an exponent between the channels, so it is as precise as the largest
+            for (stored, pixel) in stored.iter().zip(&framebuffer.pixels) {
+                let largest = pixel.x.max(pixel.y).max(pixel.z);
+                for (a, b) in stored.iter().zip(pixel.to_array()) {
+                    assert!((a - b).abs() <= largest / 128., "{:?}", format);
+                }
+            }
+        }
+    }
+}
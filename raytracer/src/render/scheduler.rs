@@ -0,0 +1,171 @@
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+pub const DEFAULT_TILE_SIZE: u32 = 32;
+
+// a rectangle of pixels [x0, x1) x [y0, y1), rows counted from the top of the image
+#[derive(Copy, Clone, Debug)]
+pub struct Tile {
+    pub index: usize,
+    pub x0: u32,
+    pub y0: u32,
+    pub x1: u32,
+    pub y1: u32,
+}
+
+impl Tile {
+    pub fn width(&self) -> u32 {
+        self.x1 - self.x0
+    }
+
+    pub fn height(&self) -> u32 {
+        self.y1 - self.y0
+    }
+
+    pub fn area(&self) -> u32 {
+        self.width() * self.height()
+    }
+
+    // the pixels row by row
+    pub fn pixels(&self) -> impl Iterator<Item = (u32, u32)> {
+        let (x0, x1) = (self.x0, self.x1);
+        (self.y0..self.y1).flat_map(move |y| (x0..x1).map(move |x| (x, y)))
+    }
+
+    pub fn contains(&self, x: u32, y: u32) -> bool {
+        x >= self.x0 && x < self.x1 && y >= self.y0 && y < self.y1
+    }
+
+    pub fn intersects(&self, other: &Tile) -> bool {
+        self.x0 < other.x1 && other.x0 < self.x1 && self.y0 < other.y1 && other.y0 < self.y1
+    }
+
+    // the pixels in both tiles, an empty tile if there are none
+    pub fn intersection(&self, other: &Tile) -> Tile {
+        let (x0, y0) = (self.x0.max(other.x0), self.y0.max(other.y0));
+        Tile {
+            index: self.index,
+            x0,
+            y0,
+            x1: self.x1.min(other.x1).max(x0),
+            y1: self.y1.min(other.y1).max(y0),
+        }
+    }
+
+    // grown by `pad` pixels on every side, within a width x height image
+    pub fn expand(&self, pad: u32, width: u32, height: u32) -> Tile {
+        Tile {
+            index: self.index,
+            x0: self.x0.saturating_sub(pad),
+            y0: self.y0.saturating_sub(pad),
+            x1: (self.x1 + pad).min(width),
+            y1: (self.y1 + pad).min(height),
+        }
+    }
+
+    // values of the pixels of the tile, out of those of an image `width` wide
+    pub fn crop<T: Copy>(&self, pixels: &[T], width: u32) -> Vec<T> {
+        self.pixels()
+            .map(|(x, y)| pixels[(y * width + x) as usize])
+            .collect()
+    }
+}
+
+// tiles shared by the render threads; each thread takes the next tile when it is done with one,
+// so a slow region of the image does not hold back the others
+pub struct TileQueue {
+    tiles: Vec<Tile>,
+    next: AtomicUsize,
+}
+
+impl TileQueue {
+    // the tiles of the image that overlap `region`; they stay on the grid of the whole image, so
+    // a region is rendered exactly as in the whole image
+    pub fn new(width: u32, height: u32, tile_size: u32, region: &Tile) -> Self {
+        let mut tiles = Vec::new();
+        for y0 in (0..height).step_by(tile_size as usize) {
+            for x0 in (0..width).step_by(tile_size as usize) {
+                let tile = Tile {
+                    index: tiles.len(),
+                    x0,
+                    y0,
+                    x1: (x0 + tile_size).min(width),
+                    y1: (y0 + tile_size).min(height),
+                };
+                if tile.intersects(region) {
+                    tiles.push(tile);
+                }
+            }
+        }
+        Self {
+            tiles,
+            next: AtomicUsize::new(0),
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.tiles.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.tiles.is_empty()
+    }
+
+    pub fn pop(&self) -> Option<Tile> {
+        let index = self.next.fetch_add(1, Ordering::Relaxed);
+        self.tiles.get(index).copied()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{sync::Mutex, thread};
+
+    // threads pulling from the queue get every tile once, and the tiles cover each pixel once
+    #[test]
+    fn queue_covers_the_image_once() {
+        let (width, height) = (70, 45);
+        let whole = Tile {
+            index: 0,
+            x0: 0,
+            y0: 0,
+            x1: width,
+            y1: height,
+        };
+        let queue = TileQueue::new(width, height, 16, &whole);
+        assert_eq!(queue.len(), 5 * 3);
+        let taken = Mutex::new(Vec::new());
+        thread::scope(|s| {
+            for _ in 0..4 {
+                s.spawn(|| {
+                    while let Some(tile) = queue.pop() {
+                        taken.lock().unwrap().push(tile);
+                    }
+                });
+            }
+        });
+        let mut taken = taken.into_inner().unwrap();
+        taken.sort_by_key(|tile| tile.index);
+        assert!(taken.iter().enumerate().all(|(i, tile)| tile.index == i));
+        let mut covered = vec![0; (width * height) as usize];
+        for (x, y) in taken.iter().flat_map(|tile| tile.pixels()) {
+            covered[(y * width + x) as usize] += 1;
+        }
+        assert!(covered.iter().all(|&n| n == 1));
+
+        // a region takes the tiles of the grid it overlaps
+        let region = Tile {
+            index: 0,
+            x0: 20,
+            y0: 10,
+            x1: 33,
+            y1: 17,
+        };
+        let queue = TileQueue::new(width, height, 16, &region);
+        let tiles: Vec<(u32, u32)> = (0..queue.len())
+            .map(|_| queue.pop().unwrap())
+            .map(|tile| (tile.x0, tile.y0))
+            .collect();
+        assert_eq!(tiles, [(16, 0), (32, 0), (16, 16), (32, 16)]);
+    }
+}
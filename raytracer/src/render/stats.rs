@@ -0,0 +1,92 @@
+use crate::utility::*;
+use console::style;
+use serde::Serialize;
+use std::{error::Error, fs, path::Path};
+
+// time spent in each phase, in seconds
+#[derive(Clone, Default, Debug, Serialize)]
+pub struct Timings {
+    pub scene: f64, //loading or building the scene, the BVH included
+    pub bvh: f64,
+    pub aovs: f64,
+    pub render: f64,
+    pub output: f64, //writing the images, denoising included
+    pub total: f64,
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct RenderStats {
+    pub scene: String,
+    pub width: u32,
+    pub height: u32,
+    pub samples_per_pixel: u32,
+    pub threads: usize,
+    pub timings: Timings,
+    // counted with the `stats` feature only
+    pub counts: Option<Counts>,
+    pub rays_per_second: Option<f64>,
+    pub average_path_length: Option<f64>, //surfaces a path hits
+}
+
+impl RenderStats {
+    pub fn new(
+        scene: String,
+        (width, height): (u32, u32),
+        samples_per_pixel: u32,
+        threads: usize,
+        timings: Timings,
+        counts: Counts,
+    ) -> Self {
+        let counts = Some(counts).filter(|_| cfg!(feature = "stats"));
+        Self {
+            scene,
+            width,
+            height,
+            samples_per_pixel,
+            threads,
+            rays_per_second: counts.map(|c| c.rays as f64 / timings.render.max(1e-9)),
+            average_path_length: counts.map(|c| c.path_vertices as f64 / c.paths.max(1) as f64),
+            timings,
+            counts,
+        }
+    }
+
+    pub fn print(&self) {
+        let t = &self.timings;
+        println!("{}", style("Render statistics").bold());
+        println!("  scene            {:>10.3} s", t.scene);
+        println!("    BVH build      {:>10.3} s", t.bvh);
+        if t.aovs > 0. {
+            println!("  AOVs             {:>10.3} s", t.aovs);
+        }
+        println!("  render           {:>10.3} s", t.render);
+        println!("  output           {:>10.3} s", t.output);
+        println!("  total            {:>10.3} s", t.total);
+        let c = match &self.counts {
+            Some(c) => c,
+            None => {
+                println!("  (build with --features stats for the ray counts)");
+                return;
+            }
+        };
+        println!("  primary rays     {:>12}", c.primary_rays);
+        println!(
+            "  rays             {:>12} ({:.2} M/s)",
+            c.rays,
+            self.rays_per_second.unwrap_or(0.) / 1e6
+        );
+        println!("  shadow rays      {:>12}", c.shadow_rays);
+        println!("  AABB tests       {:>12}", c.aabb_tests);
+        println!("  primitive tests  {:>12}", c.primitive_tests);
+        println!("  BVH node visits  {:>12}", c.bvh_visits);
+        println!(
+            "  path length      {:>12.2}",
+            self.average_path_length.unwrap_or(0.)
+        );
+    }
+
+    pub fn save(&self, path: &Path) -> Result<(), Box<dyn Error>> {
+        fs::write(path, serde_json::to_string_pretty(self)? + "\n")?;
+        Ok(())
+    }
+}
@@ -0,0 +1,254 @@
+use crate::{render::framebuffer::Framebuffer, utility::*};
+use clap::ValueEnum;
+use ndarray::{array, Array2};
+
+#[derive(Copy, Clone, PartialEq, Eq, Debug, ValueEnum)]
+pub enum ToneMap {
+    None, //clip to [0, 1]
+    Reinhard,
+    ReinhardExtended,
+    Aces, //Narkowicz's fit of the ACES filmic curve
+    Hable,
+}
+
+#[derive(Copy, Clone, PartialEq, Eq, Debug, ValueEnum)]
+pub enum Transfer {
+    Srgb,
+    Gamma2, //sqrt, the old display transform
+}
+
+// display transform from linear radiance to 8-bit values:
+// exposure -> white balance -> tone map -> transfer function
+#[derive(Clone, Debug)]
+pub struct ColorPipeline {
+    pub exposure: f64,              //stops
+    pub white_balance: Option<f64>, //color temperature of the light to neutralize, in kelvin
+    pub tonemap: ToneMap,
+    pub white_point: Option<f64>, //of extended Reinhard; the brightest luminance if None
+    pub transfer: Transfer,
+}
+
+impl Default for ColorPipeline {
+    fn default() -> Self {
+        Self {
+            exposure: 0.,
+            white_balance: None,
+            tonemap: ToneMap::None,
+            white_point: None,
+            transfer: Transfer::Srgb,
+        }
+    }
+}
+
+impl ColorPipeline {
+    // display values in [0, 1] of a whole framebuffer, which stays untouched
+    pub fn apply(&self, framebuffer: &Framebuffer) -> Vec<Color> {
+        let scale = self.exposure.exp2();
+        let balance = self.white_balance.map(white_balance_matrix);
+        let exposed: Vec<Color> = framebuffer
+            .pixels
+            .iter()
+            .map(|&color| {
+                let color = color * scale;
+                match &balance {
+                    Some(balance) => matmul(balance, &color),
+                    None => color,
+                }
+            })
+            .collect();
+        let white_point = self
+            .white_point
+            .unwrap_or_else(|| exposed.iter().map(luminance).fold(0., f64::max).max(1.));
+        exposed
+            .iter()
+            .map(|color| {
+                let mut color = self.tonemap(color, white_point);
+                for i in 0..3 {
+                    color[i] = self.transfer(clamp(color[i], 0., 1.));
+                }
+                color
+            })
+            .collect()
+    }
+
+    fn tonemap(&self, color: &Color, white_point: f64) -> Color {
+        match self.tonemap {
+            ToneMap::None => *color,
+            ToneMap::Reinhard | ToneMap::ReinhardExtended => {
+                // on the luminance, to keep the hue of bright colors
+                let l = luminance(color);
+                if l <= 0. {
+                    return Color::default();
+                }
+                let mapped = if self.tonemap == ToneMap::Reinhard {
+                    l / (1. + l)
+                } else {
+                    l * (1. + l / (white_point * white_point)) / (1. + l)
+                };
+                *color * (mapped / l)
+            }
+            ToneMap::Aces => {
+                let mut mapped = Color::default();
+                for i in 0..3 {
+                    let x = color[i].max(0.) * 0.6;
+                    mapped[i] = (x * (2.51 * x + 0.03)) / (x * (2.43 * x + 0.59) + 0.14);
+                }
+                mapped
+            }
+            ToneMap::Hable => {
+                const EXPOSURE_BIAS: f64 = 2.;
+                const W: f64 = 11.2; //linear white
+                let mut mapped = Color::default();
+                for i in 0..3 {
+                    mapped[i] = hable_partial(color[i].max(0.) * EXPOSURE_BIAS) / hable_partial(W);
+                }
+                mapped
+            }
+        }
+    }
+
+    fn transfer(&self, c: f64) -> f64 {
+        match self.transfer {
+            Transfer::Srgb => {
+                if c <= 0.0031308 {
+                    12.92 * c
+                } else {
+                    1.055 * c.powf(1. / 2.4) - 0.055
+                }
+            }
+            Transfer::Gamma2 => c.sqrt(),
+        }
+    }
+}
+
+pub fn luminance(color: &Color) -> f64 {
+    0.2126 * color.x + 0.7152 * color.y + 0.0722 * color.z
+}
+
+// Uncharted 2 filmic curve by John Hable
+fn hable_partial(x: f64) -> f64 {
+    let (a, b, c, d, e, f) = (0.15, 0.50, 0.10, 0.20, 0.02, 0.30);
+    ((x * (a * x + c * b) + d * e) / (x * (a * x + b) + d * f)) - e / f
+}
+
+// chromaticity of a black body (Kang et al. 2002), for 1667 K to 25000 K
+fn planckian_xy(kelvin: f64) -> (f64, f64) {
+    let t = kelvin.clamp(1667., 25000.);
+    let (t2, t3) = (t * t, t * t * t);
+    let x = if t <= 4000. {
+        -0.2661239e9 / t3 - 0.2343589e6 / t2 + 0.8776956e3 / t + 0.179910
+    } else {
+        -3.0258469e9 / t3 + 2.1070379e6 / t2 + 0.2226347e3 / t + 0.240390
+    };
+    let (x2, x3) = (x * x, x * x * x);
+    let y = if t <= 2222. {
+        -1.1063814 * x3 - 1.34811020 * x2 + 2.18555832 * x - 0.20219683
+    } else if t <= 4000. {
+        -0.9549476 * x3 - 1.37418593 * x2 + 2.09137015 * x - 0.16748867
+    } else {
+        3.0817580 * x3 - 5.87338670 * x2 + 3.75112997 * x - 0.37001483
+    };
+    (x, y)
+}
+
+// Bradford chromatic adaptation of linear sRGB from a white of the given temperature to D65
+pub fn white_balance_matrix(kelvin: f64) -> Array2<f64> {
+    let rgb_to_xyz = array![
+        [0.4124564, 0.3575761, 0.1804375],
+        [0.2126729, 0.7151522, 0.0721750],
+        [0.0193339, 0.1191920, 0.9503041],
+    ];
+    let xyz_to_rgb = array![
+        [3.2404542, -1.5371385, -0.4985314],
+        [-0.9692660, 1.8760108, 0.0415560],
+        [0.0556434, -0.2040259, 1.0572252],
+    ];
+    let bradford = array![
+        [0.8951, 0.2664, -0.1614],
+        [-0.7502, 1.7135, 0.0367],
+        [0.0389, -0.0685, 1.0296],
+    ];
+    let bradford_inv = array![
+        [0.9869929, -0.1470543, 0.1599627],
+        [0.4323053, 0.5183603, 0.0492912],
+        [-0.0085287, 0.0400428, 0.9684867],
+    ];
+    let (x, y) = planckian_xy(kelvin);
+    let source = matmul(&bradford, &Vec3::new(x / y, 1., (1. - x - y) / y));
+    let target = matmul(&bradford, &Vec3::new(0.95047, 1., 1.08883));
+    let mut scale = Array2::<f64>::zeros((3, 3));
+    for i in 0..3 {
+        scale[(i, i)] = target[i] / source[i];
+    }
+    xyz_to_rgb
+        .dot(&bradford_inv)
+        .dot(&scale)
+        .dot(&bradford)
+        .dot(&rgb_to_xyz)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn display(pipeline: &ColorPipeline, colors: &[Color]) -> Vec<Color> {
+        let mut framebuffer = Framebuffer::new(colors.len() as u32, 1);
+        framebuffer.pixels = colors.to_vec();
+        pipeline.apply(&framebuffer)
+    }
+
+    // the operators keep black, rise with the radiance and leave bright emitters apart instead of
+    // clipping them to the same white
+    #[test]
+    fn operators_compress_highlights() {
+        let grays: Vec<Color> = [0., 0.1, 0.5, 1., 2., 5.]
+            .iter()
+            .map(|&c| Color::grayscale(c))
+            .collect();
+        for tonemap in [
+            ToneMap::Reinhard,
+            ToneMap::ReinhardExtended,
+            ToneMap::Aces,
+            ToneMap::Hable,
+        ] {
+            let pipeline = ColorPipeline {
+                tonemap,
+                ..Default::default()
+            };
+            let shown: Vec<f64> = display(&pipeline, &grays).iter().map(|c| c.y).collect();
+            assert!(shown[0].abs() < 1e-9, "{:?}", tonemap);
+            assert!(shown.windows(2).all(|w| w[0] < w[1]), "{:?}", tonemap);
+            assert!(shown.iter().all(|&c| c <= 1.), "{:?}", tonemap);
+        }
+        // the brightest pixel is the white of extended Reinhard unless given
+        let pipeline = ColorPipeline {
+            tonemap: ToneMap::ReinhardExtended,
+            ..Default::default()
+        };
+        assert!((display(&pipeline, &grays)[5].y - 1.).abs() < 1e-9);
+    }
+
+    #[test]
+    fn exposure_and_transfer() {
+        let srgb = ColorPipeline::default();
+        let brighter = ColorPipeline {
+            exposure: 1.,
+            ..Default::default()
+        };
+        let half = display(&srgb, &[Color::grayscale(0.5)])[0];
+        assert!((display(&brighter, &[Color::grayscale(0.25)])[0].x - half.x).abs() < 1e-12);
+        assert!((half.x - 0.735357).abs() < 1e-6);
+        let gamma = ColorPipeline {
+            transfer: Transfer::Gamma2,
+            ..Default::default()
+        };
+        assert!((display(&gamma, &[Color::grayscale(0.25)])[0].x - 0.5).abs() < 1e-12);
+
+        // a black body of 6500 K is close to D65, the white of sRGB, and leaves gray nearly gray;
+        // the light of a colder one is reddish, and is made bluer
+        let neutral = matmul(&white_balance_matrix(6504.), &Color::grayscale(0.5));
+        assert!(neutral.to_array().iter().all(|c| (c - 0.5).abs() < 0.03));
+        let warm = matmul(&white_balance_matrix(3000.), &Color::grayscale(0.5));
+        assert!(warm.z > warm.x);
+    }
+}
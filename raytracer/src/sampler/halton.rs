@@ -0,0 +1,84 @@
+use crate::{
+    sampler::{hash, permute, Sampler},
+    utility::*,
+};
+
+// bases of the dimensions; deeper dimensions of long paths are independent
+const HALTON_DIMENSIONS: usize = 128;
+
+// the Halton sequence, one prime base per dimension, with the digits of every dimension of every
+// pixel randomly permuted; unscrambled, large bases are badly correlated over the first samples
+pub struct HaltonSampler {
+    seed: u64,
+    primes: Vec<u64>,
+    pixel: u64,
+    index: u64,
+    dimension: usize,
+    rng: Rng,
+}
+
+impl HaltonSampler {
+    pub fn new(seed: u64) -> Self {
+        let mut primes = Vec::with_capacity(HALTON_DIMENSIONS);
+        let mut n = 2;
+        while primes.len() < HALTON_DIMENSIONS {
+            if primes
+                .iter()
+                .take_while(|&&p| p * p <= n)
+                .all(|&p| n % p != 0)
+            {
+                primes.push(n);
+            }
+            n += 1;
+        }
+        Self {
+            seed,
+            primes,
+            pixel: 0,
+            index: 0,
+            dimension: 0,
+            rng: Rng::new(seed),
+        }
+    }
+}
+
+impl Sampler for HaltonSampler {
+    fn start_sample(&mut self, pixel: u64, index: u64) {
+        self.pixel = pixel;
+        self.index = index;
+        self.dimension = 0;
+        self.rng = Rng::for_sample(self.seed, pixel, index);
+    }
+
+    fn get_1d(&mut self) -> f64 {
+        let dimension = self.dimension;
+        self.dimension += 1;
+        match self.primes.get(dimension) {
+            Some(&base) => {
+                let seed = hash(&[self.seed, self.pixel, dimension as u64]);
+                scrambled_radical_inverse(self.index, base, seed)
+            }
+            None => self.rng.random(),
+        }
+    }
+
+    fn get_2d(&mut self) -> (f64, f64) {
+        (self.get_1d(), self.get_1d())
+    }
+}
+
+// the digits of `i` in `base` mirrored around the radix point, each position with its own
+// permutation of the digits; past the last digit of `i`, the permuted zeros still count
+pub fn scrambled_radical_inverse(mut i: u64, base: u64, seed: u32) -> f64 {
+    let inv_base = 1. / base as f64;
+    let (mut result, mut inv_base_n, mut position) = (0., inv_base, 0);
+    while inv_base_n > f64::EPSILON {
+        let p = seed ^ hash(&[position]);
+        let digit = permute((i % base) as u32, base as u32, p);
+        result += digit as f64 * inv_base_n;
+        inv_base_n *= inv_base;
+        i /= base;
+        position += 1;
+    }
+    result.min(1. - f64::EPSILON / 2.)
+}
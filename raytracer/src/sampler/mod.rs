@@ -0,0 +1,128 @@
+pub mod halton;
+pub mod sobol;
+pub mod stratified;
+
+pub use halton::*;
+pub use sobol::*;
+pub use stratified::*;
+
+use crate::utility::*;
+use clap::ValueEnum;
+
+// the numbers a path is sampled from: every call of `get_1d` / `get_2d` is the next dimension
+// of the current sample, so the same vertex of different samples of a pixel is well distributed
+pub trait Sampler {
+    // start the `index`-th sample of a pixel
+    fn start_sample(&mut self, pixel: u64, index: u64);
+    fn get_1d(&mut self) -> f64;
+    fn get_2d(&mut self) -> (f64, f64);
+}
+
+#[derive(Copy, Clone, PartialEq, Eq, Debug, ValueEnum)]
+pub enum SamplerKind {
+    Independent,
+    Stratified, //jittered, with `spp` strata
+    Halton,
+    Sobol, //Owen-scrambled (0, 2)-sequence, padded to any number of dimensions
+}
+
+impl SamplerKind {
+    pub fn create(self, seed: u64, spp: u32) -> Box<dyn Sampler> {
+        match self {
+            Self::Independent => Box::new(IndependentSampler::new(seed)),
+            Self::Stratified => Box::new(StratifiedSampler::new(seed, spp)),
+            Self::Halton => Box::new(HaltonSampler::new(seed)),
+            Self::Sobol => Box::new(SobolSampler::new(seed)),
+        }
+    }
+}
+
+pub struct IndependentSampler {
+    seed: u64,
+    rng: Rng,
+}
+
+impl IndependentSampler {
+    pub fn new(seed: u64) -> Self {
+        Self {
+            seed,
+            rng: Rng::new(seed),
+        }
+    }
+}
+
+impl Sampler for IndependentSampler {
+    fn start_sample(&mut self, pixel: u64, index: u64) {
+        self.rng = Rng::for_sample(self.seed, pixel, index);
+    }
+
+    fn get_1d(&mut self) -> f64 {
+        self.rng.random()
+    }
+
+    fn get_2d(&mut self) -> (f64, f64) {
+        (self.rng.random(), self.rng.random())
+    }
+}
+
+// a 32-bit hash of the arguments, to seed the scrambling of a dimension
+pub fn hash(values: &[u64]) -> u32 {
+    let h = values
+        .iter()
+        .fold(0x2545F4914F6CDD1D, |h, &v| splitmix64(h ^ v));
+    (h >> 32) as u32
+}
+
+// [0, 1) from the bits of a 32-bit fraction
+pub fn to_unit(x: u32) -> f64 {
+    x as f64 / 4294967296.
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // the first 2d points of the samples of a pixel, `n` of them
+    fn points(kind: SamplerKind, n: u32, pixel: u64) -> Vec<(f64, f64)> {
+        let mut sampler = kind.create(9, n);
+        (0..n as u64)
+            .map(|index| {
+                sampler.start_sample(pixel, index);
+                sampler.get_2d()
+            })
+            .collect()
+    }
+
+    // stratified and Sobol points fall one in each cell of a grid; all of them are repeatable and
+    // integrate a smooth function better than chance
+    #[test]
+    fn samples_are_well_spread() {
+        for kind in [SamplerKind::Stratified, SamplerKind::Sobol] {
+            let mut cells = [0; 16];
+            for (u, v) in points(kind, 16, 3) {
+                cells[(v * 4.) as usize * 4 + (u * 4.) as usize] += 1;
+            }
+            assert_eq!(cells, [1; 16], "{:?}", kind);
+        }
+        for kind in [
+            SamplerKind::Independent,
+            SamplerKind::Stratified,
+            SamplerKind::Halton,
+            SamplerKind::Sobol,
+        ] {
+            let pts = points(kind, 256, 5);
+            assert_eq!(pts, points(kind, 256, 5), "{:?}", kind);
+            assert!(pts
+                .iter()
+                .all(|&(u, v)| (0. ..1.).contains(&u) && (0. ..1.).contains(&v)));
+            // the integral of u v over the square is 1 / 4
+            let estimate = pts.iter().map(|(u, v)| u * v).sum::<f64>() / pts.len() as f64;
+            let tolerance = if kind == SamplerKind::Independent {
+                0.05
+            } else {
+                0.005
+            };
+            assert!((estimate - 0.25).abs() < tolerance, "{:?}", kind);
+        }
+    }
+}
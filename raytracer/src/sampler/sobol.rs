@@ -0,0 +1,79 @@
+use crate::sampler::{hash, to_unit, Sampler};
+
+// the first two dimensions of Sobol, a (0, 2)-sequence, with hash-based Owen scrambling
+// (Burley 2020): every dimension (or pair) gets its own scrambling and its own shuffle of the
+// sample order, which pads the 2D sequence to any number of dimensions
+pub struct SobolSampler {
+    seed: u64,
+    pixel: u64,
+    index: u32,
+    dimension: u64,
+}
+
+impl SobolSampler {
+    pub fn new(seed: u64) -> Self {
+        Self {
+            seed,
+            pixel: 0,
+            index: 0,
+            dimension: 0,
+        }
+    }
+
+    // the shuffled index of the current sample and the scrambling seed of the next dimension
+    fn next_dimension(&mut self) -> (u32, u32) {
+        let seed = hash(&[self.seed, self.pixel, self.dimension]);
+        self.dimension += 1;
+        (nested_uniform_scramble(self.index, seed), seed)
+    }
+}
+
+impl Sampler for SobolSampler {
+    fn start_sample(&mut self, pixel: u64, index: u64) {
+        self.pixel = pixel;
+        self.index = index as u32;
+        self.dimension = 0;
+    }
+
+    fn get_1d(&mut self) -> f64 {
+        let (index, seed) = self.next_dimension();
+        let x = index.reverse_bits();
+        to_unit(nested_uniform_scramble(x, hash(&[seed as u64, 0])))
+    }
+
+    fn get_2d(&mut self) -> (f64, f64) {
+        let (index, seed) = self.next_dimension();
+        let (x, y) = (index.reverse_bits(), sobol_dimension_1(index));
+        (
+            to_unit(nested_uniform_scramble(x, hash(&[seed as u64, 0]))),
+            to_unit(nested_uniform_scramble(y, hash(&[seed as u64, 1]))),
+        )
+    }
+}
+
+// the second dimension of the Sobol sequence, from the primitive polynomial x + 1
+pub fn sobol_dimension_1(mut index: u32) -> u32 {
+    let (mut v, mut result) = (1u32 << 31, 0);
+    while index != 0 {
+        if index & 1 != 0 {
+            result ^= v;
+        }
+        index >>= 1;
+        v ^= v >> 1;
+    }
+    result
+}
+
+fn laine_karras_permutation(mut x: u32, seed: u32) -> u32 {
+    x = x.wrapping_add(seed);
+    x ^= x.wrapping_mul(0x6c50b47c);
+    x ^= x.wrapping_mul(0xb82f1e52);
+    x ^= x.wrapping_mul(0xc7afe638);
+    x ^= x.wrapping_mul(0x8d22f6e6);
+    x
+}
+
+// Owen scrambling of the bits of a fraction: each bit flipped depending on the bits above it
+pub fn nested_uniform_scramble(x: u32, seed: u32) -> u32 {
+    laine_karras_permutation(x.reverse_bits(), seed).reverse_bits()
+}
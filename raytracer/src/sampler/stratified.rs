@@ -0,0 +1,96 @@
+use crate::{
+    sampler::{hash, Sampler},
+    utility::*,
+};
+
+// jittered strata: the samples of a pixel visit the `spp` strata of every 1D dimension, and the
+// m x m (m = ceil(sqrt(spp))) strata of every 2D dimension, in a random order per dimension
+pub struct StratifiedSampler {
+    seed: u64,
+    spp: u32,
+    m: u32,
+    pixel: u64,
+    index: u64,
+    dimension: u64,
+    rng: Rng, //the jitter inside the strata
+}
+
+impl StratifiedSampler {
+    pub fn new(seed: u64, spp: u32) -> Self {
+        let spp = spp.max(1);
+        Self {
+            seed,
+            spp,
+            m: (spp as f64).sqrt().ceil() as u32,
+            pixel: 0,
+            index: 0,
+            dimension: 0,
+            rng: Rng::new(seed),
+        }
+    }
+
+    // the stratum of the current sample among `n`; samples past `n` start a new round of strata
+    fn stratum(&mut self, n: u32) -> u32 {
+        let round = self.index / n as u64;
+        let p = hash(&[self.seed, self.pixel, self.dimension, round]);
+        self.dimension += 1;
+        permute((self.index % n as u64) as u32, n, p)
+    }
+}
+
+impl Sampler for StratifiedSampler {
+    fn start_sample(&mut self, pixel: u64, index: u64) {
+        self.pixel = pixel;
+        self.index = index;
+        self.dimension = 0;
+        self.rng = Rng::for_sample(self.seed, pixel, index);
+    }
+
+    fn get_1d(&mut self) -> f64 {
+        let stratum = self.stratum(self.spp);
+        (stratum as f64 + self.rng.random()) / self.spp as f64
+    }
+
+    fn get_2d(&mut self) -> (f64, f64) {
+        let m = self.m;
+        let stratum = self.stratum(m * m);
+        (
+            ((stratum % m) as f64 + self.rng.random()) / m as f64,
+            ((stratum / m) as f64 + self.rng.random()) / m as f64,
+        )
+    }
+}
+
+// the i-th element of a random permutation of [0, l) chosen by p (Kensler 2013)
+pub fn permute(mut i: u32, l: u32, p: u32) -> u32 {
+    let mut w = l - 1;
+    w |= w >> 1;
+    w |= w >> 2;
+    w |= w >> 4;
+    w |= w >> 8;
+    w |= w >> 16;
+    loop {
+        i ^= p;
+        i = i.wrapping_mul(0xe170893d);
+        i ^= p >> 16;
+        i ^= (i & w) >> 4;
+        i ^= p >> 8;
+        i = i.wrapping_mul(0x0929eb3f);
+        i ^= p >> 23;
+        i ^= (i & w) >> 1;
+        i = i.wrapping_mul(1 | p >> 27);
+        i = i.wrapping_mul(0x6935fa69);
+        i ^= (i & w) >> 11;
+        i = i.wrapping_mul(0x74dcb303);
+        i ^= (i & w) >> 2;
+        i = i.wrapping_mul(0x9e501cc3);
+        i ^= (i & w) >> 2;
+        i = i.wrapping_mul(0xc860a3df);
+        i &= w;
+        i ^= i >> 5;
+        if i < l {
+            break;
+        }
+    }
+    i.wrapping_add(p) % l
+}
@@ -0,0 +1,539 @@
+// Data types of the scene file format (JSON or TOML)
+use crate::texture::perlin::Perlin;
+use serde::{
+    de::{self, value::MapAccessDeserializer, Error as _},
+    Deserialize, Deserializer, Serialize,
+};
+use std::{
+    cell::{Cell, RefCell},
+    collections::BTreeMap,
+    fmt,
+    path::Path,
+};
+
+pub type V3 = [f64; 3];
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(deny_unknown_fields)]
+pub struct SceneDesc {
+    #[serde(default)]
+    pub camera: CameraDesc,
+    #[serde(default = "default_width", deserialize_with = "width")]
+    pub width: u32,
+    // derived from the aspect ratio if not given
+    #[serde(
+        default,
+        skip_serializing_if = "Option::is_none",
+        deserialize_with = "height"
+    )]
+    pub height: Option<u32>,
+    #[serde(
+        default = "default_samples_per_pixel",
+        deserialize_with = "samples_per_pixel"
+    )]
+    pub samples_per_pixel: u32,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub background: Option<TextureRef>,
+    #[serde(
+        default,
+        skip_serializing_if = "BTreeMap::is_empty",
+        deserialize_with = "texture_definitions"
+    )]
+    pub textures: BTreeMap<String, TextureDesc>,
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub materials: BTreeMap<String, MaterialDesc>,
+    #[serde(default)]
+    pub objects: Vec<ObjectDesc>,
+    #[serde(default)]
+    pub lights: Vec<ObjectDesc>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(deny_unknown_fields, default)]
+pub struct CameraDesc {
+    pub lookfrom: V3,
+    pub lookat: V3,
+    pub vup: V3,
+    #[serde(deserialize_with = "vfov")]
+    pub vfov: f64, //vertical field-of-view in degrees
+    pub aperture: f64,
+    pub focus_dist: f64,
+    #[serde(deserialize_with = "aspect_ratio")]
+    pub aspect_ratio: f64,
+}
+
+impl Default for CameraDesc {
+    fn default() -> Self {
+        Self {
+            lookfrom: [13., 2., 3.],
+            lookat: [0., 0., 0.],
+            vup: [0., 1., 0.],
+            vfov: 40.,
+            aperture: 0.,
+            focus_dist: 10.,
+            aspect_ratio: 1.,
+        }
+    }
+}
+
+fn default_width() -> u32 {
+    600
+}
+fn default_samples_per_pixel() -> u32 {
+    100
+}
+fn default_time1() -> f64 {
+    1.
+}
+fn default_scale() -> f64 {
+    1.
+}
+fn default_uvs() -> [[f64; 2]; 3] {
+    [[0., 0.], [1., 0.], [0., 1.]]
+}
+
+// values are checked as they are read, so an error comes with the position of the value
+fn check<'de, D: Deserializer<'de>, T: Deserialize<'de>>(
+    deserializer: D,
+    valid: impl FnOnce(&T) -> bool,
+    message: &str,
+) -> Result<T, D::Error> {
+    let value = T::deserialize(deserializer)?;
+    if valid(&value) {
+        Ok(value)
+    } else {
+        Err(D::Error::custom(message))
+    }
+}
+fn width<'de, D: Deserializer<'de>>(deserializer: D) -> Result<u32, D::Error> {
+    check(deserializer, |&w| w >= 2, "width must be at least 2")
+}
+fn height<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Option<u32>, D::Error> {
+    let valid = |h: &Option<u32>| h.map_or(true, |h| h >= 2);
+    check(deserializer, valid, "height must be at least 2")
+}
+fn samples_per_pixel<'de, D: Deserializer<'de>>(deserializer: D) -> Result<u32, D::Error> {
+    check(
+        deserializer,
+        |&n| n >= 1,
+        "samples_per_pixel must be at least 1",
+    )
+}
+fn vfov<'de, D: Deserializer<'de>>(deserializer: D) -> Result<f64, D::Error> {
+    let valid = |&vfov: &f64| vfov > 0. && vfov < 180.;
+    check(deserializer, valid, "vfov must be in the range (0, 180)")
+}
+fn aspect_ratio<'de, D: Deserializer<'de>>(deserializer: D) -> Result<f64, D::Error> {
+    check(deserializer, |&r| r > 0., "aspect_ratio must be positive")
+}
+fn density<'de, D: Deserializer<'de>>(deserializer: D) -> Result<f64, D::Error> {
+    check(
+        deserializer,
+        |&d| d > 0.,
+        "density of a medium must be positive",
+    )
+}
+fn mean_cosine<'de, D: Deserializer<'de>>(deserializer: D) -> Result<f64, D::Error> {
+    let valid = |g: &f64| g.abs() < 1.;
+    check(
+        deserializer,
+        valid,
+        "g of a lobe must be in the range (-1, 1)",
+    )
+}
+fn weight<'de, D: Deserializer<'de>>(deserializer: D) -> Result<f64, D::Error> {
+    let valid = |w: &f64| (0. ..=1.).contains(w);
+    check(deserializer, valid, "weight must be in the range [0, 1]")
+}
+fn perlin<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Option<PerlinDesc>, D::Error> {
+    let valid = |perlin: &Option<PerlinDesc>| {
+        perlin
+            .as_ref()
+            .map_or(true, |perlin| Perlin::from_desc(perlin).is_some())
+    };
+    check(
+        deserializer,
+        valid,
+        "perlin tables must hold 256 entries, with permutations below 256",
+    )
+}
+fn bvh_objects<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Vec<ObjectDesc>, D::Error> {
+    let valid = |objects: &Vec<ObjectDesc>| !objects.is_empty();
+    check(deserializer, valid, "a bvh needs at least one object")
+}
+fn file<'de, D: Deserializer<'de>>(deserializer: D) -> Result<String, D::Error> {
+    let file = String::deserialize(deserializer)?;
+    if !Path::new(&file).is_file() {
+        return Err(D::Error::custom(format!("cannot find file \"{}\"", file)));
+    }
+    located(Locate::File(&file))?;
+    Ok(file)
+}
+fn optional_file<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Option<String>, D::Error> {
+    Ok(Some(file(deserializer)?))
+}
+
+// what an error found when the scene is built, after it is read, is about. the scene is read
+// again with the error set to be located, which fails at the first value it names, so the error
+// gets the position of that value
+#[derive(Clone, Copy)]
+pub enum Locate<'a> {
+    Texture(&'a str),
+    // a reference from the definition of a texture
+    TextureDefinition(&'a str),
+    Material(&'a str),
+    File(&'a str),
+}
+
+thread_local! {
+    static LOCATING: RefCell<Option<(String, String)>> = RefCell::new(None);
+    static IN_TEXTURES: Cell<bool> = Cell::new(false);
+}
+
+impl Locate<'_> {
+    fn key(&self) -> String {
+        match self {
+            Self::Texture(name) => format!("texture {}", name),
+            Self::TextureDefinition(name) => format!("texture definition {}", name),
+            Self::Material(name) => format!("material {}", name),
+            Self::File(file) => format!("file {}", file),
+        }
+    }
+}
+
+// runs `parse` failing with `message` at the value `at` names
+pub fn locate<T>(at: Locate, message: &str, parse: impl FnOnce() -> T) -> T {
+    LOCATING.with(|locating| *locating.borrow_mut() = Some((at.key(), message.to_owned())));
+    let result = parse();
+    LOCATING.with(|locating| *locating.borrow_mut() = None);
+    result
+}
+
+fn located<E: de::Error>(found: Locate) -> Result<(), E> {
+    LOCATING.with(|locating| match &*locating.borrow() {
+        Some((key, message)) if *key == found.key() => Err(E::custom(message)),
+        _ => Ok(()),
+    })
+}
+
+fn texture_definitions<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> Result<BTreeMap<String, TextureDesc>, D::Error> {
+    IN_TEXTURES.with(|in_textures| in_textures.set(true));
+    let textures = BTreeMap::deserialize(deserializer);
+    IN_TEXTURES.with(|in_textures| in_textures.set(false));
+    textures
+}
+
+// a texture is a color, the name of a texture in `textures`, or an inline description
+#[derive(Serialize, Clone, Debug)]
+#[serde(untagged)]
+pub enum TextureRef {
+    Color(V3),
+    Named(String),
+    Inline(Box<TextureDesc>),
+}
+
+// by the kind of value, rather than untagged, so that the errors of an inline texture are kept
+impl<'de> Deserialize<'de> for TextureRef {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        struct Visitor;
+        impl<'de> de::Visitor<'de> for Visitor {
+            type Value = TextureRef;
+            fn expecting(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+                write!(
+                    f,
+                    "a color [r, g, b], the name of a texture or a texture table"
+                )
+            }
+            fn visit_str<E: de::Error>(self, name: &str) -> Result<TextureRef, E> {
+                located(Locate::Texture(name))?;
+                if IN_TEXTURES.with(|in_textures| in_textures.get()) {
+                    located(Locate::TextureDefinition(name))?;
+                }
+                Ok(TextureRef::Named(name.to_owned()))
+            }
+            fn visit_seq<A: de::SeqAccess<'de>>(self, seq: A) -> Result<TextureRef, A::Error> {
+                V3::deserialize(de::value::SeqAccessDeserializer::new(seq)).map(TextureRef::Color)
+            }
+            fn visit_map<A: de::MapAccess<'de>>(self, map: A) -> Result<TextureRef, A::Error> {
+                let desc = TextureDesc::deserialize(MapAccessDeserializer::new(map))?;
+                Ok(TextureRef::Inline(Box::new(desc)))
+            }
+        }
+        deserializer.deserialize_any(Visitor)
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(tag = "type", rename_all = "snake_case", deny_unknown_fields)]
+pub enum TextureDesc {
+    Solid {
+        color: V3,
+    },
+    Checker {
+        even: TextureRef,
+        odd: TextureRef,
+    },
+    Noise {
+        scale: f64,
+        // random tables of the noise; generated when loading if not given
+        #[serde(
+            default,
+            skip_serializing_if = "Option::is_none",
+            deserialize_with = "perlin"
+        )]
+        perlin: Option<PerlinDesc>,
+    },
+    Turbulence {
+        scale: f64,
+        #[serde(
+            default,
+            skip_serializing_if = "Option::is_none",
+            deserialize_with = "perlin"
+        )]
+        perlin: Option<PerlinDesc>,
+    },
+    Image {
+        #[serde(deserialize_with = "file")]
+        file: String,
+    },
+    // raw voxel grid stretched over the box from min to max
+    Voxel {
+        #[serde(deserialize_with = "file")]
+        file: String,
+        min: V3,
+        max: V3,
+    },
+    GrayImage {
+        #[serde(deserialize_with = "file")]
+        file: String,
+    },
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(deny_unknown_fields)]
+pub struct PerlinDesc {
+    pub ranvec: Vec<V3>,
+    pub perm_x: Vec<usize>,
+    pub perm_y: Vec<usize>,
+    pub perm_z: Vec<usize>,
+}
+
+// a material is the name of a material in `materials` or an inline description
+#[derive(Serialize, Clone, Debug)]
+#[serde(untagged)]
+pub enum MaterialRef {
+    Named(String),
+    Inline(Box<MaterialDesc>),
+}
+
+impl<'de> Deserialize<'de> for MaterialRef {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        struct Visitor;
+        impl<'de> de::Visitor<'de> for Visitor {
+            type Value = MaterialRef;
+            fn expecting(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+                write!(f, "the name of a material or a material table")
+            }
+            fn visit_str<E: de::Error>(self, name: &str) -> Result<MaterialRef, E> {
+                located(Locate::Material(name))?;
+                Ok(MaterialRef::Named(name.to_owned()))
+            }
+            fn visit_map<A: de::MapAccess<'de>>(self, map: A) -> Result<MaterialRef, A::Error> {
+                let desc = MaterialDesc::deserialize(MapAccessDeserializer::new(map))?;
+                Ok(MaterialRef::Inline(Box::new(desc)))
+            }
+        }
+        deserializer.deserialize_any(Visitor)
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(tag = "type", rename_all = "snake_case", deny_unknown_fields)]
+pub enum MaterialDesc {
+    Lambertian {
+        albedo: TextureRef,
+    },
+    Metal {
+        albedo: V3,
+        #[serde(default)]
+        fuzz: f64,
+    },
+    Dielectric {
+        ir: f64,
+    },
+    DiffuseLight {
+        emit: TextureRef,
+    },
+    // scattering in a medium
+    Isotropic {
+        albedo: TextureRef,
+        #[serde(default, skip_serializing_if = "PhaseDesc::is_isotropic")]
+        phase: PhaseDesc,
+    },
+    Generic {
+        diffuse: TextureRef,
+        specular: TextureRef,
+        emit: TextureRef,
+        rough: TextureRef,
+        alpha: f64,
+        optical_density: f64,
+    },
+    Empty,
+}
+
+// how the light scattered in a medium spreads; g is the mean cosine of the angle it turns by
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+#[serde(tag = "type", rename_all = "snake_case", deny_unknown_fields)]
+pub enum PhaseDesc {
+    #[default]
+    Isotropic,
+    HenyeyGreenstein {
+        #[serde(deserialize_with = "mean_cosine")]
+        g: f64,
+    },
+    // weight of the first lobe
+    DoubleHenyeyGreenstein {
+        #[serde(deserialize_with = "mean_cosine")]
+        g1: f64,
+        #[serde(deserialize_with = "mean_cosine")]
+        g2: f64,
+        #[serde(deserialize_with = "weight")]
+        weight: f64,
+    },
+    Rayleigh,
+}
+
+impl PhaseDesc {
+    pub fn is_isotropic(&self) -> bool {
+        matches!(self, Self::Isotropic)
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(tag = "type", rename_all = "snake_case", deny_unknown_fields)]
+pub enum ObjectDesc {
+    Sphere {
+        center: V3,
+        radius: f64,
+        material: MaterialRef,
+    },
+    MovingSphere {
+        center0: V3,
+        center1: V3,
+        radius: f64,
+        #[serde(default)]
+        time0: f64,
+        #[serde(default = "default_time1")]
+        time1: f64,
+        material: MaterialRef,
+    },
+    XyRect {
+        x0: f64,
+        x1: f64,
+        y0: f64,
+        y1: f64,
+        k: f64,
+        material: MaterialRef,
+    },
+    XzRect {
+        x0: f64,
+        x1: f64,
+        z0: f64,
+        z1: f64,
+        k: f64,
+        material: MaterialRef,
+    },
+    YzRect {
+        y0: f64,
+        y1: f64,
+        z0: f64,
+        z1: f64,
+        k: f64,
+        material: MaterialRef,
+    },
+    RectBox {
+        p0: V3,
+        p1: V3,
+        material: MaterialRef,
+    },
+    ConstantMedium {
+        boundary: Box<ObjectDesc>,
+        #[serde(deserialize_with = "density")]
+        density: f64,
+        albedo: TextureRef,
+        #[serde(default, skip_serializing_if = "PhaseDesc::is_isotropic")]
+        phase: PhaseDesc,
+    },
+    // density times the mean of the channels of density_field
+    HeterogeneousMedium {
+        boundary: Box<ObjectDesc>,
+        #[serde(deserialize_with = "density")]
+        density: f64,
+        density_field: TextureRef,
+        albedo: TextureRef,
+        #[serde(default, skip_serializing_if = "PhaseDesc::is_isotropic")]
+        phase: PhaseDesc,
+    },
+    // .obj model; materials come from its .mtl file unless `material` is given
+    Obj {
+        #[serde(deserialize_with = "file")]
+        file: String,
+        #[serde(default = "default_scale")]
+        scale: f64,
+        #[serde(default)]
+        rotate: V3, //degrees around x, y and z
+        #[serde(default)]
+        translate: V3,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        material: Option<MaterialRef>,
+        #[serde(
+            default,
+            skip_serializing_if = "Option::is_none",
+            deserialize_with = "optional_file"
+        )]
+        normal_map: Option<String>,
+    },
+    Triangle {
+        vertices: [V3; 3],
+        #[serde(default = "default_uvs")]
+        uvs: [[f64; 2]; 3],
+        // per-vertex normals; the face normal if not given
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        normals: Option<[V3; 3]>,
+        material: MaterialRef,
+        #[serde(
+            default,
+            skip_serializing_if = "Option::is_none",
+            deserialize_with = "optional_file"
+        )]
+        normal_map: Option<String>,
+    },
+    Translate {
+        offset: V3,
+        object: Box<ObjectDesc>,
+    },
+    RotateX {
+        angle: f64,
+        object: Box<ObjectDesc>,
+    },
+    RotateY {
+        angle: f64,
+        object: Box<ObjectDesc>,
+    },
+    RotateZ {
+        angle: f64,
+        object: Box<ObjectDesc>,
+    },
+    FlipFace {
+        object: Box<ObjectDesc>,
+    },
+    List {
+        objects: Vec<ObjectDesc>,
+    },
+    Bvh {
+        #[serde(deserialize_with = "bvh_objects")]
+        objects: Vec<ObjectDesc>,
+    },
+}
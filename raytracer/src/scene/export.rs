@@ -0,0 +1,216 @@
+use crate::{
+    hittable::Hittable,
+    scene::{desc::*, loader::SceneError, SceneConfig},
+};
+use std::{
+    collections::{BTreeMap, HashMap},
+    fs,
+    path::Path,
+};
+
+impl SceneConfig {
+    // the scene in the file format; materials used more than once become named materials
+    pub fn describe(&self) -> SceneDesc {
+        let mut objects: Vec<ObjectDesc> =
+            self.world.objects.iter().map(|o| o.describe()).collect();
+        let mut lights: Vec<ObjectDesc> =
+            self.lights.objects.iter().map(|o| o.describe()).collect();
+        let materials = name_shared_materials(objects.iter_mut().chain(lights.iter_mut()));
+        SceneDesc {
+            camera: CameraDesc {
+                lookfrom: self.lookfrom.to_array(),
+                lookat: self.lookat.to_array(),
+                vup: self.vup.to_array(),
+                vfov: self.vfov,
+                aperture: self.aperture,
+                focus_dist: self.focus_dist,
+                aspect_ratio: self.aspect_ratio,
+            },
+            width: self.width,
+            height: self.height,
+            samples_per_pixel: self.samples_per_pixel,
+            background: Some(self.background.describe()),
+            textures: Default::default(),
+            materials,
+            objects,
+            lights,
+        }
+    }
+}
+
+// write the scene as JSON, or TOML for a .toml path
+pub fn save_scene_file(config: &SceneConfig, path: &Path) -> Result<(), SceneError> {
+    let error = |message: String| SceneError {
+        file: path.display().to_string(),
+        line: None,
+        column: None,
+        message,
+    };
+    let desc = config.describe();
+    let text = match path.extension().and_then(|ext| ext.to_str()) {
+        // through toml::Value, which orders plain values before tables
+        Some("toml") => toml::Value::try_from(&desc)
+            .map(|value| value.to_string())
+            .map_err(|e| error(e.to_string()))?,
+        _ => serde_json::to_string_pretty(&desc).map_err(|e| error(e.to_string()))? + "\n",
+    };
+    fs::write(path, text).map_err(|e| error(e.to_string()))
+}
+
+fn name_shared_materials<'a>(
+    objects: impl Iterator<Item = &'a mut ObjectDesc>,
+) -> BTreeMap<String, MaterialDesc> {
+    let mut refs: Vec<&mut MaterialRef> = Vec::new();
+    for object in objects {
+        material_refs(object, &mut refs);
+    }
+
+    // identical descriptions have identical serializations
+    let key = |material: &MaterialDesc| serde_json::to_string(material).unwrap();
+    let mut count: HashMap<String, usize> = HashMap::new();
+    for material in refs.iter() {
+        if let MaterialRef::Inline(desc) = &**material {
+            *count.entry(key(desc)).or_default() += 1;
+        }
+    }
+
+    let mut names: HashMap<String, String> = HashMap::new();
+    let mut kinds: HashMap<&str, usize> = HashMap::new();
+    let mut materials = BTreeMap::new();
+    for material in refs {
+        let desc = match &*material {
+            MaterialRef::Inline(desc) => desc,
+            MaterialRef::Named(_) => continue,
+        };
+        let id = key(desc);
+        if count[&id] < 2 {
+            continue;
+        }
+        let name = names
+            .entry(id)
+            .or_insert_with(|| {
+                let kind = material_kind(desc);
+                let n = kinds.entry(kind).or_default();
+                *n += 1;
+                let name = format!("{}_{}", kind, n);
+                materials.insert(name.clone(), (**desc).clone());
+                name
+            })
+            .clone();
+        *material = MaterialRef::Named(name);
+    }
+    materials
+}
+
+fn material_kind(desc: &MaterialDesc) -> &'static str {
+    match desc {
+        MaterialDesc::Lambertian { .. } => "lambertian",
+        MaterialDesc::Metal { .. } => "metal",
+        MaterialDesc::Dielectric { .. } => "dielectric",
+        MaterialDesc::DiffuseLight { .. } => "diffuse_light",
+        MaterialDesc::Isotropic { .. } => "isotropic",
+        MaterialDesc::Generic { .. } => "generic",
+        MaterialDesc::Empty => "empty",
+    }
+}
+
+fn material_refs<'a>(object: &'a mut ObjectDesc, refs: &mut Vec<&'a mut MaterialRef>) {
+    match object {
+        ObjectDesc::Sphere { material, .. }
+        | ObjectDesc::MovingSphere { material, .. }
+        | ObjectDesc::XyRect { material, .. }
+        | ObjectDesc::XzRect { material, .. }
+        | ObjectDesc::YzRect { material, .. }
+        | ObjectDesc::RectBox { material, .. }
+        | ObjectDesc::Triangle { material, .. } => refs.push(material),
+        ObjectDesc::Obj { material, .. } => refs.extend(material.as_mut()),
+        ObjectDesc::ConstantMedium { boundary, .. }
+        | ObjectDesc::HeterogeneousMedium { boundary, .. } => material_refs(boundary, refs),
+        ObjectDesc::Translate { object, .. }
+        | ObjectDesc::RotateX { object, .. }
+        | ObjectDesc::RotateY { object, .. }
+        | ObjectDesc::RotateZ { object, .. }
+        | ObjectDesc::FlipFace { object } => material_refs(object, refs),
+        ObjectDesc::List { objects } | ObjectDesc::Bvh { objects } => {
+            for object in objects {
+                material_refs(object, refs);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        scene::{builtin_scene, loader::load_scene_file},
+        utility::*,
+    };
+    use std::{env, process};
+
+    fn export_and_load(config: &SceneConfig, name: &str, ext: &str) -> SceneConfig {
+        let path = env::temp_dir().join(format!(
+            "raytracer_export_{}_{}.{}",
+            name,
+            process::id(),
+            ext
+        ));
+        save_scene_file(config, &path).unwrap();
+        let loaded = load_scene_file(&path);
+        fs::remove_file(&path).unwrap();
+        loaded.unwrap_or_else(|e| panic!("{}", e))
+    }
+
+    fn describe(config: &SceneConfig) -> String {
+        serde_json::to_string(&config.describe()).unwrap()
+    }
+
+    // what a fan of rays from the camera hits; a rebuilt bvh lists its objects in another order
+    fn probe(config: &SceneConfig) -> Vec<Option<(f64, [f64; 3], String)>> {
+        let forward = config.lookat - config.lookfrom;
+        let mut hits = Vec::new();
+        for i in 0..32 {
+            for j in 0..32 {
+                let offset = Vec3::new(i as f64 - 15.5, j as f64 - 15.5, 0.) * 0.02;
+                let r = Ray::new(&config.lookfrom, &(forward.unit() + offset), 0.5);
+                hits.push(config.world.hit(&r, 0.001, INFINITY).map(|rec| {
+                    let material = serde_json::to_string(&rec.mat_ptr.describe()).unwrap();
+                    (rec.t, rec.normal.to_array(), material)
+                }));
+            }
+        }
+        hits
+    }
+
+    #[test]
+    fn export_round_trip() {
+        for (name, ext) in [("cornell_cloud", "json"), ("cornell_smoke", "toml")] {
+            let config = builtin_scene(name).unwrap();
+            let loaded = export_and_load(&config, name, ext);
+            assert_eq!(describe(&loaded), describe(&config));
+        }
+    }
+
+    #[test]
+    fn exported_random_scene_hits_the_same() {
+        seed(7);
+        let config = builtin_scene("random_scene").unwrap();
+        for ext in ["json", "toml"] {
+            let loaded = export_and_load(&config, "random_scene", ext);
+            assert_eq!(probe(&loaded), probe(&config));
+        }
+    }
+
+    // the scene files shipped with the builtin scenes describe the same scenes
+    #[test]
+    fn builtin_scenes_match_their_files() {
+        for name in ["cornell_box", "cornell_smoke", "cornell_cloud"] {
+            seed(3);
+            let builtin = builtin_scene(name).unwrap();
+            seed(3);
+            let path = format!("../scenes/{}.json", name);
+            let file = load_scene_file(Path::new(&path)).unwrap_or_else(|e| panic!("{}", e));
+            assert_eq!(describe(&file), describe(&builtin), "{}", name);
+        }
+    }
+}
@@ -0,0 +1,570 @@
+use crate::{
+    hittable::{aarect::*, bvh::BVHNode, medium::*, rect_box::*, sphere::*, triangle::*, *},
+    material::*,
+    obj_loader::*,
+    scene::{desc::*, source::*, SceneConfig},
+    texture::{perlin::Perlin, *},
+    utility::*,
+};
+use std::{collections::HashMap, fmt, fs, path::Path};
+
+#[derive(Debug)]
+pub struct SceneError {
+    pub file: String,
+    pub line: Option<usize>,
+    pub column: Option<usize>,
+    pub message: String,
+}
+
+impl fmt::Display for SceneError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.file)?;
+        if let Some(line) = self.line {
+            write!(f, ":{}", line)?;
+            if let Some(column) = self.column {
+                write!(f, ":{}", column)?;
+            }
+        }
+        write!(f, ": {}", self.message)
+    }
+}
+
+impl std::error::Error for SceneError {}
+
+// serde_json and toml append " at line L column C" to their messages
+fn strip_position(message: String) -> String {
+    match message.rfind(" at line ") {
+        Some(pos) => message[..pos].to_owned(),
+        None => message,
+    }
+}
+
+fn parse_scene_file(path: &Path, source: &str) -> Result<Node, SceneError> {
+    let error = |line, column, message| SceneError {
+        file: path.display().to_string(),
+        line,
+        column,
+        message,
+    };
+    match path.extension().and_then(|ext| ext.to_str()) {
+        Some("toml") => parse_toml(source).map_err(|e| {
+            let (line, column) = e.line_col().map_or((None, None), |(line, column)| {
+                (Some(line + 1), Some(column + 1))
+            });
+            error(line, column, strip_position(e.to_string()))
+        }),
+        _ => parse_json(source).map_err(|e| {
+            error(
+                Some(e.line()),
+                Some(e.column()),
+                strip_position(e.to_string()),
+            )
+        }),
+    }
+}
+
+fn scene_desc(path: &Path, source: &str, node: &Node) -> Result<SceneDesc, SceneError> {
+    node.deserialize().map_err(|e| {
+        let (line, column) = e.at.map_or((None, None), |at| {
+            let (line, column) = line_col(source, at);
+            (Some(line), Some(column))
+        });
+        SceneError {
+            file: path.display().to_string(),
+            line,
+            column,
+            message: e.message,
+        }
+    })
+}
+
+pub fn load_scene_file(path: &Path) -> Result<SceneConfig, SceneError> {
+    let source = fs::read_to_string(path).map_err(|e| SceneError {
+        file: path.display().to_string(),
+        line: None,
+        column: None,
+        message: e.to_string(),
+    })?;
+    let node = parse_scene_file(path, &source)?;
+    let desc = scene_desc(path, &source, &node)?;
+    Loader::new(&desc)
+        .build()
+        .map_err(|BuildError { at, message }| {
+            match locate(at, &message, || scene_desc(path, &source, &node)) {
+                Err(error) => error,
+                Ok(_) => SceneError {
+                    file: path.display().to_string(),
+                    line: None,
+                    column: None,
+                    message,
+                },
+            }
+        })
+}
+
+// an error of the scene as a whole, such as an unknown name, found once the file is read; the
+// scene is read again from the parsed file to find the position of what it is about
+struct BuildError<'a> {
+    at: Locate<'a>,
+    message: String,
+}
+
+struct Loader<'a> {
+    desc: &'a SceneDesc,
+    textures: HashMap<String, Arc<dyn Texture>>,
+    materials: HashMap<String, Arc<dyn Material>>,
+    resolving: Vec<String>, //named textures being built, to detect cycles
+}
+
+impl<'a> Loader<'a> {
+    fn new(desc: &'a SceneDesc) -> Self {
+        Self {
+            desc,
+            textures: HashMap::new(),
+            materials: HashMap::new(),
+            resolving: Vec::new(),
+        }
+    }
+
+    fn build(mut self) -> Result<SceneConfig, BuildError<'a>> {
+        let scene = self.desc;
+        let camera = &scene.camera;
+        let background: Box<dyn Texture> = match &scene.background {
+            Some(background) => Box::new(self.texture(background)?),
+            None => Box::new(SolidColor::new(&Color::default())),
+        };
+        let mut world = HittableList::default();
+        for object in &scene.objects {
+            world.add(self.object(object)?);
+        }
+        let mut lights = HittableList::default();
+        for light in &scene.lights {
+            lights.add(self.object(light)?);
+        }
+
+        Ok(SceneConfig {
+            world,
+            lights,
+            background,
+            // a given height fixes the aspect ratio of the image
+            aspect_ratio: match scene.height {
+                Some(height) => scene.width as f64 / height as f64,
+                None => camera.aspect_ratio,
+            },
+            width: scene.width,
+            height: scene.height,
+            samples_per_pixel: scene.samples_per_pixel,
+            lookfrom: Vec3::from_array(&camera.lookfrom),
+            lookat: Vec3::from_array(&camera.lookat),
+            vup: Vec3::from_array(&camera.vup),
+            vfov: camera.vfov,
+            aperture: camera.aperture,
+            focus_dist: camera.focus_dist,
+        })
+    }
+
+    fn texture(&mut self, texture: &'a TextureRef) -> Result<Arc<dyn Texture>, BuildError<'a>> {
+        match texture {
+            TextureRef::Color(color) => Ok(Arc::new(SolidColor::new(&Vec3::from_array(color)))),
+            TextureRef::Named(name) => {
+                if let Some(texture) = self.textures.get(name) {
+                    return Ok(texture.clone());
+                }
+                let scene = self.desc;
+                let desc = scene.textures.get(name).ok_or_else(|| BuildError {
+                    at: Locate::Texture(name),
+                    message: format!("unknown texture \"{}\"", name),
+                })?;
+                if self.resolving.contains(name) {
+                    return Err(BuildError {
+                        at: Locate::TextureDefinition(name),
+                        message: format!("texture \"{}\" refers to itself", name),
+                    });
+                }
+                self.resolving.push(name.clone());
+                let texture = self.texture_desc(desc)?;
+                self.resolving.pop();
+                self.textures.insert(name.clone(), texture.clone());
+                Ok(texture)
+            }
+            TextureRef::Inline(desc) => self.texture_desc(desc),
+        }
+    }
+
+    fn texture_desc(&mut self, desc: &'a TextureDesc) -> Result<Arc<dyn Texture>, BuildError<'a>> {
+        Ok(match desc {
+            TextureDesc::Solid { color } => Arc::new(SolidColor::new(&Vec3::from_array(color))),
+            TextureDesc::Checker { even, odd } => {
+                Arc::new(CheckerTexture::new(self.texture(even)?, self.texture(odd)?))
+            }
+            TextureDesc::Noise { scale, perlin } => match perlin {
+                Some(perlin) => Arc::new(NoiseTexture::new_from_perlin(perlin_of(perlin), *scale)),
+                None => Arc::new(NoiseTexture::new(*scale)),
+            },
+            TextureDesc::Turbulence { scale, perlin } => match perlin {
+                Some(perlin) => Arc::new(TurbulenceTexture::new_from_perlin(
+                    perlin_of(perlin),
+                    *scale,
+                )),
+                None => Arc::new(TurbulenceTexture::new(*scale)),
+            },
+            TextureDesc::Image { file } => Arc::new(ImageTexture::new(file)),
+            TextureDesc::Voxel { file, min, max } => {
+                let error = |message| BuildError {
+                    at: Locate::File(file),
+                    message,
+                };
+                if (0..3).any(|axis| min[axis] >= max[axis]) {
+                    return Err(error(
+                        "min of a voxel grid must be below max on every axis".to_owned(),
+                    ));
+                }
+                let texture =
+                    VoxelTexture::new(file, &Vec3::from_array(min), &Vec3::from_array(max))
+                        .map_err(error)?;
+                Arc::new(texture)
+            }
+            TextureDesc::GrayImage { file } => Arc::new(GrayImageTexture::new(file)),
+        })
+    }
+
+    fn material(&mut self, material: &'a MaterialRef) -> Result<Arc<dyn Material>, BuildError<'a>> {
+        match material {
+            MaterialRef::Named(name) => {
+                if let Some(material) = self.materials.get(name) {
+                    return Ok(material.clone());
+                }
+                let scene = self.desc;
+                let desc = scene.materials.get(name).ok_or_else(|| BuildError {
+                    at: Locate::Material(name),
+                    message: format!("unknown material \"{}\"", name),
+                })?;
+                let material = self.material_desc(desc)?;
+                self.materials.insert(name.clone(), material.clone());
+                Ok(material)
+            }
+            MaterialRef::Inline(desc) => self.material_desc(desc),
+        }
+    }
+
+    fn material_desc(
+        &mut self,
+        desc: &'a MaterialDesc,
+    ) -> Result<Arc<dyn Material>, BuildError<'a>> {
+        Ok(match desc {
+            MaterialDesc::Lambertian { albedo } => Arc::new(Lambertian::new(self.texture(albedo)?)),
+            MaterialDesc::Metal { albedo, fuzz } => {
+                Arc::new(Metal::new(&Vec3::from_array(albedo), *fuzz))
+            }
+            MaterialDesc::Dielectric { ir } => Arc::new(Dielectric::new(*ir)),
+            MaterialDesc::DiffuseLight { emit } => Arc::new(DiffuseLight::new(self.texture(emit)?)),
+            MaterialDesc::Isotropic { albedo, phase } => Arc::new(Volumetric::with_phase(
+                self.texture(albedo)?,
+                phase_of(phase),
+            )),
+            MaterialDesc::Generic {
+                diffuse,
+                specular,
+                emit,
+                rough,
+                alpha,
+                optical_density,
+            } => Arc::new(Generic::new(
+                self.texture(diffuse)?,
+                self.texture(specular)?,
+                self.texture(emit)?,
+                self.texture(rough)?,
+                *alpha,
+                *optical_density,
+            )),
+            MaterialDesc::Empty => Arc::new(EmptyMaterial {}),
+        })
+    }
+
+    fn objects(&mut self, objects: &'a [ObjectDesc]) -> Result<HittableList, BuildError<'a>> {
+        let mut list = HittableList::default();
+        for object in objects {
+            list.add(self.object(object)?);
+        }
+        Ok(list)
+    }
+
+    fn object(&mut self, desc: &'a ObjectDesc) -> Result<Box<dyn Hittable>, BuildError<'a>> {
+        Ok(match desc {
+            ObjectDesc::Sphere {
+                center,
+                radius,
+                material,
+            } => Box::new(Sphere::new(
+                &Vec3::from_array(center),
+                *radius,
+                self.material(material)?,
+            )),
+            ObjectDesc::MovingSphere {
+                center0,
+                center1,
+                radius,
+                time0,
+                time1,
+                material,
+            } => Box::new(MovingSphere::new(
+                &Vec3::from_array(center0),
+                &Vec3::from_array(center1),
+                *radius,
+                *time0,
+                *time1,
+                self.material(material)?,
+            )),
+            ObjectDesc::XyRect {
+                x0,
+                x1,
+                y0,
+                y1,
+                k,
+                material,
+            } => Box::new(XYRect::new(
+                *x0,
+                *x1,
+                *y0,
+                *y1,
+                *k,
+                self.material(material)?,
+            )),
+            ObjectDesc::XzRect {
+                x0,
+                x1,
+                z0,
+                z1,
+                k,
+                material,
+            } => Box::new(XZRect::new(
+                *x0,
+                *x1,
+                *z0,
+                *z1,
+                *k,
+                self.material(material)?,
+            )),
+            ObjectDesc::YzRect {
+                y0,
+                y1,
+                z0,
+                z1,
+                k,
+                material,
+            } => Box::new(YZRect::new(
+                *y0,
+                *y1,
+                *z0,
+                *z1,
+                *k,
+                self.material(material)?,
+            )),
+            ObjectDesc::RectBox { p0, p1, material } => Box::new(RectBox::new(
+                &Vec3::from_array(p0),
+                &Vec3::from_array(p1),
+                self.material(material)?,
+            )),
+            ObjectDesc::ConstantMedium {
+                boundary,
+                density,
+                albedo,
+                phase,
+            } => Box::new(ConstantMedium::with_phase(
+                self.object(boundary)?,
+                *density,
+                self.texture(albedo)?,
+                phase_of(phase),
+            )),
+            ObjectDesc::HeterogeneousMedium {
+                boundary,
+                density,
+                density_field,
+                albedo,
+                phase,
+            } => Box::new(HeterogeneousMedium::with_phase(
+                self.object(boundary)?,
+                *density,
+                self.texture(density_field)?,
+                self.texture(albedo)?,
+                phase_of(phase),
+            )),
+            ObjectDesc::Obj {
+                file,
+                scale,
+                rotate,
+                translate,
+                material,
+                normal_map,
+            } => match material {
+                Some(material) => Box::new(ObjModel::new_with_material(
+                    file,
+                    self.material(material)?,
+                    normal_map.as_deref(),
+                    *scale,
+                    *rotate,
+                    *translate,
+                )),
+                None => Box::new(ObjModel::new(
+                    file,
+                    normal_map.as_deref(),
+                    *scale,
+                    *rotate,
+                    *translate,
+                )),
+            },
+            ObjectDesc::Triangle {
+                vertices,
+                uvs,
+                normals,
+                material,
+                normal_map,
+            } => {
+                let [a, b, c] = vertices.map(|v| Vec3::from_array(&v));
+                let normals = match normals {
+                    Some(normals) => normals.map(|n| Vec3::from_array(&n)),
+                    None => [cross(&(b - a), &(c - a)).unit(); 3],
+                };
+                let nmap = normal_map.as_deref().map(ImageTexture::new);
+                Box::new(Triangle::new(
+                    (a, b, c),
+                    self.material(material)?,
+                    (uvs[0][0], uvs[0][1]),
+                    (uvs[1][0], uvs[1][1]),
+                    (uvs[2][0], uvs[2][1]),
+                    (normals[0], normals[1], normals[2]),
+                    nmap,
+                ))
+            }
+            ObjectDesc::Translate { offset, object } => Box::new(Translate::new(
+                self.object(object)?,
+                &Vec3::from_array(offset),
+            )),
+            ObjectDesc::RotateX { angle, object } => {
+                Box::new(RotateX::new(self.object(object)?, *angle))
+            }
+            ObjectDesc::RotateY { angle, object } => {
+                Box::new(RotateY::new(self.object(object)?, *angle))
+            }
+            ObjectDesc::RotateZ { angle, object } => {
+                Box::new(RotateZ::new(self.object(object)?, *angle))
+            }
+            ObjectDesc::FlipFace { object } => Box::new(FlipFace::new(self.object(object)?)),
+            ObjectDesc::List { objects } => Box::new(self.objects(objects)?),
+            ObjectDesc::Bvh { objects } => {
+                Box::new(BVHNode::new(self.objects(objects)?, TIME0, TIME1))
+            }
+        })
+    }
+}
+
+// the tables and the parameters of the lobes are checked when parsed
+fn perlin_of(desc: &PerlinDesc) -> Perlin {
+    Perlin::from_desc(desc).expect("perlin tables are checked when parsed")
+}
+
+fn phase_of(desc: &PhaseDesc) -> Arc<dyn PhaseFunction> {
+    match *desc {
+        PhaseDesc::Isotropic => Arc::new(IsotropicPhase::default()),
+        PhaseDesc::HenyeyGreenstein { g } => Arc::new(HenyeyGreenstein::new(g)),
+        PhaseDesc::DoubleHenyeyGreenstein { g1, g2, weight } => {
+            Arc::new(DoubleHenyeyGreenstein::new(g1, g2, weight))
+        }
+        PhaseDesc::Rayleigh => Arc::new(Rayleigh::default()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{env, process};
+
+    fn load(name: &str, ext: &str, text: &str) -> Result<SceneConfig, SceneError> {
+        let path = env::temp_dir().join(format!("raytracer_{}_{}.{}", name, process::id(), ext));
+        fs::write(&path, text).unwrap();
+        let config = load_scene_file(&path);
+        fs::remove_file(&path).unwrap();
+        config
+    }
+
+    fn line(name: &str, ext: &str, text: &str) -> Option<usize> {
+        match load(name, ext, text) {
+            Ok(_) => panic!("{} loaded", name),
+            Err(e) => e.line,
+        }
+    }
+
+    #[test]
+    fn errors_point_at_the_value() {
+        let toml = "width = 400\nsamples_per_pixel = 0\n";
+        assert_eq!(line("top_level", "toml", toml), Some(2));
+        let toml =
+            "width = 400\n[camera]\nvfov = 200\n[[objects]]\ntype = \"list\"\nobjects = []\n";
+        assert_eq!(line("camera", "toml", toml), Some(3));
+        let json = "{\n  \"width\": 400,\n  \"height\": 1\n}\n";
+        assert_eq!(line("height", "json", json), Some(3));
+    }
+
+    // a field of an object given by `type` is checked once the object is read
+    #[test]
+    fn errors_point_at_the_object() {
+        let sphere =
+            "type = \"sphere\"\ncenter = [0, 0, 0]\nradius = 1\nmaterial = { type = \"empty\" }\n";
+        let toml = format!(
+            "[[objects]]\n{}[[objects]]\ntype = \"constant_medium\"\ndensity = -1\nalbedo = [1, 1, 1]\nboundary = {{ type = \"sphere\", center = [0, 0, 0], radius = 1, material = {{ type = \"empty\" }} }}\n[[objects]]\n{}",
+            sphere, sphere
+        );
+        assert_eq!(line("table", "toml", &toml), Some(7));
+        let medium = |density| {
+            format!(
+                "    {{\n      \"type\": \"constant_medium\",\n      \"density\": {},\n      \"albedo\": [1, 1, 1],\n      \"boundary\": {{ \"type\": \"sphere\", \"center\": [0, 0, 0], \"radius\": 1, \"material\": {{ \"type\": \"empty\" }} }}\n    }}",
+                density
+            )
+        };
+        let json = format!(
+            "{{\n  \"objects\": [\n{},\n{}\n  ]\n}}\n",
+            medium(1),
+            medium(-1)
+        );
+        assert_eq!(line("repeated_key", "json", &json), Some(9));
+    }
+
+    #[test]
+    fn unknown_names_point_at_the_reference() {
+        let json = "{\n  \"materials\": { \"red\": { \"type\": \"lambertian\", \"albedo\": [1, 0, 0] } },\n  \"objects\": [\n    { \"type\": \"sphere\", \"center\": [0, 0, 0], \"radius\": 1, \"material\": \"red\" },\n    { \"type\": \"sphere\", \"center\": [0, 0, 0], \"radius\": 1,\n      \"material\": \"blue\" }\n  ]\n}\n";
+        assert_eq!(line("material", "json", json), Some(5));
+        let toml = "[textures.a]\ntype = \"checker\"\neven = \"b\"\nodd = [0, 0, 0]\n[textures.b]\ntype = \"checker\"\neven = [1, 1, 1]\nodd = \"a\"\n[[objects]]\ntype = \"sphere\"\ncenter = [0, 0, 0]\nradius = 1\nmaterial = { type = \"lambertian\", albedo = \"a\" }\n";
+        let error = load("cycle", "toml", toml).err().unwrap();
+        assert_eq!(error.line, Some(6));
+        assert_eq!(error.message, "texture \"a\" refers to itself");
+    }
+
+    #[test]
+    fn obj_takes_a_normal_map_without_a_material() {
+        let dir = env::temp_dir();
+        let obj = dir.join(format!("raytracer_quad_{}.obj", process::id()));
+        let mtl = format!("raytracer_quad_{}.mtl", process::id());
+        fs::write(
+            &obj,
+            format!(
+                "mtllib {}\nv -1 -1 0\nv 1 -1 0\nv 1 1 0\nv -1 1 0\nvt 0 0\nvt 1 0\nvt 1 1\nvt 0 1\nusemtl gray\nf 1/1 2/2 3/3 4/4\n",
+                mtl
+            ),
+        )
+        .unwrap();
+        fs::write(dir.join(&mtl), "newmtl gray\nKd 0.5 0.5 0.5\n").unwrap();
+        let file = obj.display().to_string();
+        let plain = ObjModel::new(&file, None, 1., [0.; 3], [0.; 3]);
+        let mapped = ObjModel::new(&file, Some("../image/normal.jpg"), 1., [0.; 3], [0.; 3]);
+        fs::remove_file(&obj).unwrap();
+        fs::remove_file(dir.join(&mtl)).unwrap();
+
+        let r = Ray::new(&Point3::new(0.3, 0.2, 5.), &Vec3::new(0., 0., -1.), 0.);
+        let normal = |model: &ObjModel| model.hit(&r, 0.001, INFINITY).unwrap().normal;
+        assert_ne!(normal(&plain).to_array(), normal(&mapped).to_array());
+        assert!(matches!(
+            mapped.describe(),
+            ObjectDesc::Obj { normal_map: Some(map), .. } if map == "../image/normal.jpg"
+        ));
+    }
+}
@@ -1,4 +1,8 @@
+pub mod desc;
+pub mod export;
+pub mod loader;
 pub mod my_scene;
+pub mod source;
 
 use crate::{
     hittable::{aarect::*, bvh::BVHNode, medium::*, rect_box::*, sphere::*, *},
@@ -6,6 +10,141 @@ use crate::{
     texture::*,
     utility::*,
 };
+pub use export::*;
+pub use loader::*;
+use my_scene::*;
+use std::path::Path;
+
+pub const BUILTIN_SCENES: [&str; 11] = [
+    "random_scene",
+    "two_spheres",
+    "two_perlin_spheres",
+    "earth",
+    "simple_light",
+    "cornell_box",
+    "cornell_smoke",
+    "cornell_cloud",
+    "final_scene",
+    "scifi1",
+    "test1",
+];
+
+pub struct SceneConfig {
+    pub world: HittableList,
+    pub lights: HittableList,
+    pub background: Box<dyn Texture>,
+    pub aspect_ratio: f64,
+    pub width: u32,
+    pub height: Option<u32>, //width / aspect_ratio if None
+    pub samples_per_pixel: u32,
+    pub lookfrom: Point3,
+    pub lookat: Point3,
+    pub vup: Vec3,
+    pub vfov: f64, //vertical field-of-view in degrees
+    pub aperture: f64,
+    pub focus_dist: f64,
+}
+
+impl SceneConfig {
+    pub fn new((world, lights): (HittableList, HittableList)) -> Self {
+        Self {
+            world,
+            lights,
+            background: Box::new(SolidColor::new(&Color::default())),
+            aspect_ratio: 1.,
+            width: 600,
+            height: None,
+            samples_per_pixel: 100,
+            lookfrom: Point3::new(13., 2., 3.),
+            lookat: Point3::default(),
+            vup: Vec3::new(0., 1., 0.),
+            vfov: 40.,
+            aperture: 0.,
+            focus_dist: 10.,
+        }
+    }
+}
+
+// a built-in scene name or the path of a .json / .toml scene file
+pub fn load_scene(name: &str) -> Result<SceneConfig, SceneError> {
+    match builtin_scene(name) {
+        Some(config) => Ok(config),
+        None => load_scene_file(Path::new(name)),
+    }
+}
+
+// camera, resolution and background presets of the built-in scenes
+pub fn builtin_scene(name: &str) -> Option<SceneConfig> {
+    let sky = || Box::new(SolidColor::new(&Color::new(0.70, 0.80, 1.00)));
+    let no_lights = |world| (world, HittableList::default());
+    let config = match name {
+        "random_scene" => SceneConfig {
+            aspect_ratio: 3. / 2.,
+            width: 1200,
+            samples_per_pixel: 500,
+            background: sky(),
+            aperture: 0.1,
+            vfov: 20.,
+            ..SceneConfig::new(no_lights(random_scene()))
+        },
+        "two_spheres" | "two_perlin_spheres" | "earth" => SceneConfig {
+            aspect_ratio: 16. / 9.,
+            width: 400,
+            background: sky(),
+            vfov: 20.,
+            ..SceneConfig::new(no_lights(match name {
+                "two_spheres" => two_spheres(),
+                "two_perlin_spheres" => two_perlin_spheres(),
+                _ => earth(),
+            }))
+        },
+        "simple_light" => SceneConfig {
+            aspect_ratio: 16. / 9.,
+            width: 400,
+            samples_per_pixel: 400,
+            lookfrom: Point3::new(26., 3., 6.),
+            lookat: Point3::new(0., 2., 0.),
+            vfov: 20.,
+            ..SceneConfig::new(simple_light())
+        },
+        "cornell_box" | "cornell_smoke" | "cornell_cloud" => SceneConfig {
+            samples_per_pixel: 200,
+            lookfrom: Point3::new(278., 278., -800.),
+            lookat: Point3::new(278., 278., 0.),
+            ..SceneConfig::new(match name {
+                "cornell_box" => cornell_box(),
+                "cornell_smoke" => cornell_smoke(),
+                _ => cornell_cloud(),
+            })
+        },
+        "final_scene" => SceneConfig {
+            width: 800,
+            lookfrom: Point3::new(478., 278., -600.),
+            lookat: Point3::new(278., 278., 0.),
+            ..SceneConfig::new(final_scene())
+        },
+        "scifi1" => SceneConfig {
+            aspect_ratio: 16. / 9.,
+            width: 3200,
+            samples_per_pixel: 2000,
+            lookfrom: Point3::new(0., 0., 10.),
+            background: Box::new(ImageTexture::new("image/milky_way.png")),
+            aperture: 0.1,
+            ..SceneConfig::new(scifi1())
+        },
+        "test1" => SceneConfig {
+            aspect_ratio: 16. / 9.,
+            width: 300,
+            samples_per_pixel: 200,
+            background: sky(),
+            aperture: 0.1,
+            vfov: 20.,
+            ..SceneConfig::new(no_lights(test1()))
+        },
+        _ => return None,
+    };
+    Some(config)
+}
 
 pub fn random_scene() -> HittableList {
     let mut world = HittableList::default();
@@ -254,6 +393,40 @@ pub fn cornell_smoke() -> (HittableList, HittableList) {
     (objects, lights)
 }
 
+pub fn cornell_cloud() -> (HittableList, HittableList) {
+    let mut objects = HittableList::default();
+    let red = Lambertian::new_from_color(&Color::new(0.65, 0.05, 0.05));
+    let white = Lambertian::new_from_color(&Color::new(0.73, 0.73, 0.73));
+    let green = Lambertian::new_from_color(&Color::new(0.12, 0.45, 0.15));
+    let light = DiffuseLight::new_from_color(&Color::new(7., 7., 7.));
+    objects.add(Box::new(YZRect::new(0., 555., 0., 555., 555., green)));
+    objects.add(Box::new(YZRect::new(0., 555., 0., 555., 0., red)));
+    let mut lights = HittableList::default();
+    let light1 = FlipFace::new(XZRect::new(113., 343., 127., 432., 554., light));
+    lights.add(Box::new(light1.clone()));
+    objects.add(Box::new(light1));
+    objects.add(Box::new(XZRect::new(0., 555., 0., 555., 0., white.clone())));
+    objects.add(Box::new(XZRect::new(
+        0.,
+        555.,
+        0.,
+        555.,
+        555.,
+        white.clone(),
+    )));
+    objects.add(Box::new(XYRect::new(0., 555., 0., 555., 555., white)));
+
+    let boundary = Sphere::new(&Point3::new(278., 250., 278.), 180., EmptyMaterial {});
+    objects.add(Box::new(HeterogeneousMedium::with_phase(
+        boundary,
+        0.05,
+        TurbulenceTexture::new(0.015),
+        SolidColor::new(&Color::new(0.9, 0.9, 0.9)),
+        HenyeyGreenstein::new(0.6),
+    )));
+    (objects, lights)
+}
+
 pub fn final_scene() -> (HittableList, HittableList) {
     let mut boxes1 = HittableList::default();
     let ground = Lambertian::new_from_color(&Color::new(0.48, 0.83, 0.53));
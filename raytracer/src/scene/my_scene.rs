@@ -155,12 +155,12 @@ pub fn object(
     rotz: f64,
     pos: [f64; 3],
 ) -> Box<dyn Hittable> {
-    Box::new(load_obj_and_mtl(
-        format!("object/{}/", name).as_str(),
-        (name.to_owned() + ".obj").as_str(),
+    Box::new(ObjModel::new(
+        &format!("object/{0}/{0}.obj", name),
+        None,
         scale,
-        rot_x(rotx).dot(&rot_y(roty)).dot(&rot_z(rotz)),
-        Vec3::from_array(&pos),
+        [rotx, roty, rotz],
+        pos,
     ))
 }
 
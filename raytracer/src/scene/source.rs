@@ -0,0 +1,290 @@
+// a scene file parsed into a tree of values that remember where they are in the file. the scene
+// is deserialized from the tree, so an error points at the innermost value it comes out of; a
+// field of an object given by `type` is checked after the whole object is read, so its errors
+// point at the object
+use serde::de::{
+    self, value::BorrowedStrDeserializer, DeserializeSeed, Deserializer, MapAccess, SeqAccess,
+    Visitor,
+};
+use serde_json::value::RawValue;
+use std::{fmt, slice};
+
+pub struct Node {
+    at: Option<usize>, //byte offset in the file
+    value: Value,
+}
+
+enum Value {
+    Null,
+    Bool(bool),
+    Int(i64),
+    UInt(u64),
+    Float(f64),
+    Str(String),
+    Seq(Vec<Node>),
+    Map(Vec<(String, Node)>),
+}
+
+#[derive(Debug)]
+pub struct Error {
+    pub at: Option<usize>,
+    pub message: String,
+}
+
+impl Error {
+    fn or_at(mut self, at: Option<usize>) -> Self {
+        self.at = self.at.or(at);
+        self
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.message)
+    }
+}
+
+impl std::error::Error for Error {}
+
+impl de::Error for Error {
+    fn custom<T: fmt::Display>(message: T) -> Self {
+        Self {
+            at: None,
+            message: message.to_string(),
+        }
+    }
+}
+
+// 1-based line and column of a byte offset
+pub fn line_col(source: &str, at: usize) -> (usize, usize) {
+    let before = &source[..at];
+    let line_start = before.rfind('\n').map_or(0, |i| i + 1);
+    (
+        before.matches('\n').count() + 1,
+        before[line_start..].chars().count() + 1,
+    )
+}
+
+pub fn parse_json(source: &str) -> Result<Node, serde_json::Error> {
+    let raw: &RawValue = serde_json::from_str(source)?;
+    Json(source).node(raw)
+}
+
+pub fn parse_toml(source: &str) -> Result<Node, toml::de::Error> {
+    let value: TomlValue = toml::from_str(source)?;
+    Ok(Node::new(None, value.0))
+}
+
+impl Node {
+    // tables of toml have no span; they start where their first value does
+    fn new(at: Option<usize>, value: Value) -> Self {
+        let at = at.or_else(|| match &value {
+            Value::Seq(nodes) => nodes.iter().filter_map(|node| node.at).min(),
+            Value::Map(entries) => entries.iter().filter_map(|(_, node)| node.at).min(),
+            _ => None,
+        });
+        Self { at, value }
+    }
+
+    pub fn deserialize<'a, T: de::Deserialize<'a>>(&'a self) -> Result<T, Error> {
+        T::deserialize(self).map_err(|e| e.or_at(self.at))
+    }
+}
+
+// how the values in a sequence or a map are read, with their offsets
+trait Format: Copy {
+    fn element<'de, A: SeqAccess<'de>>(self, seq: &mut A) -> Result<Option<Node>, A::Error>;
+    fn value<'de, A: MapAccess<'de>>(self, map: &mut A) -> Result<Node, A::Error>;
+}
+
+struct Build<F>(F);
+
+impl<'de, F: Format> Visitor<'de> for Build<F> {
+    type Value = Value;
+
+    fn expecting(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "a value")
+    }
+    fn visit_unit<E>(self) -> Result<Value, E> {
+        Ok(Value::Null)
+    }
+    fn visit_none<E>(self) -> Result<Value, E> {
+        Ok(Value::Null)
+    }
+    fn visit_some<D: Deserializer<'de>>(self, deserializer: D) -> Result<Value, D::Error> {
+        deserializer.deserialize_any(self)
+    }
+    fn visit_bool<E>(self, b: bool) -> Result<Value, E> {
+        Ok(Value::Bool(b))
+    }
+    fn visit_i64<E>(self, i: i64) -> Result<Value, E> {
+        Ok(Value::Int(i))
+    }
+    fn visit_u64<E>(self, u: u64) -> Result<Value, E> {
+        Ok(Value::UInt(u))
+    }
+    fn visit_f64<E>(self, x: f64) -> Result<Value, E> {
+        Ok(Value::Float(x))
+    }
+    fn visit_str<E>(self, s: &str) -> Result<Value, E> {
+        Ok(Value::Str(s.to_owned()))
+    }
+    fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<Value, A::Error> {
+        let mut nodes = Vec::new();
+        while let Some(node) = self.0.element(&mut seq)? {
+            nodes.push(node);
+        }
+        Ok(Value::Seq(nodes))
+    }
+    fn visit_map<A: MapAccess<'de>>(self, mut map: A) -> Result<Value, A::Error> {
+        let mut entries = Vec::new();
+        while let Some(key) = map.next_key::<String>()? {
+            entries.push((key, self.0.value(&mut map)?));
+        }
+        Ok(Value::Map(entries))
+    }
+}
+
+// every value is read as raw text first, whose place in the file is its offset
+#[derive(Clone, Copy)]
+struct Json<'s>(&'s str);
+
+impl Json<'_> {
+    fn node<E: de::Error>(self, raw: &RawValue) -> Result<Node, E> {
+        let text = raw.get();
+        let at = text.as_ptr() as usize - self.0.as_ptr() as usize;
+        let mut deserializer = serde_json::Deserializer::from_str(text);
+        let value = deserializer
+            .deserialize_any(Build(self))
+            .map_err(E::custom)?;
+        Ok(Node::new(Some(at), value))
+    }
+}
+
+impl Format for Json<'_> {
+    fn element<'de, A: SeqAccess<'de>>(self, seq: &mut A) -> Result<Option<Node>, A::Error> {
+        match seq.next_element::<&RawValue>()? {
+            Some(raw) => self.node(raw).map(Some),
+            None => Ok(None),
+        }
+    }
+    fn value<'de, A: MapAccess<'de>>(self, map: &mut A) -> Result<Node, A::Error> {
+        self.node(map.next_value::<&RawValue>()?)
+    }
+}
+
+// values come spanned; tables given by a header come with an empty span
+#[derive(Clone, Copy)]
+struct Toml;
+
+struct TomlValue(Value);
+
+impl<'de> de::Deserialize<'de> for TomlValue {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        deserializer.deserialize_any(Build(Toml)).map(TomlValue)
+    }
+}
+
+fn toml_node(spanned: toml::Spanned<TomlValue>) -> Node {
+    let at = Some(spanned.start()).filter(|_| spanned.start() < spanned.end());
+    Node::new(at, spanned.into_inner().0)
+}
+
+impl Format for Toml {
+    fn element<'de, A: SeqAccess<'de>>(self, seq: &mut A) -> Result<Option<Node>, A::Error> {
+        Ok(seq.next_element()?.map(toml_node))
+    }
+    fn value<'de, A: MapAccess<'de>>(self, map: &mut A) -> Result<Node, A::Error> {
+        Ok(toml_node(map.next_value()?))
+    }
+}
+
+impl<'de> Deserializer<'de> for &'de Node {
+    type Error = Error;
+
+    fn deserialize_any<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Error> {
+        let result = match &self.value {
+            Value::Null => visitor.visit_unit(),
+            Value::Bool(b) => visitor.visit_bool(*b),
+            Value::Int(i) => visitor.visit_i64(*i),
+            Value::UInt(u) => visitor.visit_u64(*u),
+            Value::Float(x) => visitor.visit_f64(*x),
+            Value::Str(s) => visitor.visit_borrowed_str(s),
+            Value::Seq(nodes) => visitor.visit_seq(Elements(nodes.iter())),
+            Value::Map(entries) => visitor.visit_map(Entries {
+                entries: entries.iter(),
+                value: None,
+            }),
+        };
+        result.map_err(|e| e.or_at(self.at))
+    }
+
+    fn deserialize_option<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Error> {
+        match self.value {
+            Value::Null => visitor.visit_none(),
+            _ => visitor.visit_some(self),
+        }
+    }
+
+    serde::forward_to_deserialize_any! {
+        bool i8 i16 i32 i64 i128 u8 u16 u32 u64 u128 f32 f64 char str string bytes byte_buf
+        unit unit_struct newtype_struct seq tuple tuple_struct map struct enum identifier
+        ignored_any
+    }
+}
+
+struct Elements<'de>(slice::Iter<'de, Node>);
+
+impl<'de> SeqAccess<'de> for Elements<'de> {
+    type Error = Error;
+
+    fn next_element_seed<T: DeserializeSeed<'de>>(
+        &mut self,
+        seed: T,
+    ) -> Result<Option<T::Value>, Error> {
+        match self.0.next() {
+            Some(node) => seed
+                .deserialize(node)
+                .map(Some)
+                .map_err(|e| e.or_at(node.at)),
+            None => Ok(None),
+        }
+    }
+
+    fn size_hint(&self) -> Option<usize> {
+        Some(self.0.len())
+    }
+}
+
+struct Entries<'de> {
+    entries: slice::Iter<'de, (String, Node)>,
+    value: Option<&'de Node>,
+}
+
+impl<'de> MapAccess<'de> for Entries<'de> {
+    type Error = Error;
+
+    fn next_key_seed<K: DeserializeSeed<'de>>(
+        &mut self,
+        seed: K,
+    ) -> Result<Option<K::Value>, Error> {
+        match self.entries.next() {
+            Some((key, node)) => {
+                self.value = Some(node);
+                seed.deserialize(BorrowedStrDeserializer::<Error>::new(key))
+                    .map(Some)
+                    .map_err(|e| e.or_at(node.at))
+            }
+            None => Ok(None),
+        }
+    }
+
+    fn next_value_seed<V: DeserializeSeed<'de>>(&mut self, seed: V) -> Result<V::Value, Error> {
+        let node = self.value.take().expect("a value follows its key");
+        seed.deserialize(node).map_err(|e| e.or_at(node.at))
+    }
+
+    fn size_hint(&self) -> Option<usize> {
+        Some(self.entries.len())
+    }
+}
@@ -1,9 +1,26 @@
-use crate::utility::*;
+use crate::{scene::desc::*, utility::*};
 use perlin::*;
+pub use voxel::*;
 pub mod perlin;
+pub mod voxel;
 
 pub trait Texture: Send + Sync {
     fn value(&self, u: f64, v: f64, p: &Point3) -> Color;
+    fn describe(&self) -> TextureRef;
+    // bound of the channels, the majorant of a medium whose density it gives
+    fn max_value(&self) -> f64;
+}
+
+impl<T: Texture + ?Sized> Texture for Arc<T> {
+    fn value(&self, u: f64, v: f64, p: &Point3) -> Color {
+        self.as_ref().value(u, v, p)
+    }
+    fn describe(&self) -> TextureRef {
+        self.as_ref().describe()
+    }
+    fn max_value(&self) -> f64 {
+        self.as_ref().max_value()
+    }
 }
 
 #[derive(Clone, Copy, Default)]
@@ -21,6 +38,13 @@ impl Texture for SolidColor {
     fn value(&self, _u: f64, _v: f64, _p: &Point3) -> Color {
         self.color_value
     }
+    fn describe(&self) -> TextureRef {
+        TextureRef::Color(self.color_value.to_array())
+    }
+    fn max_value(&self) -> f64 {
+        let c = self.color_value;
+        c.x.max(c.y).max(c.z)
+    }
 }
 
 #[derive(Clone, Copy)]
@@ -47,6 +71,15 @@ impl<T0: Texture, T1: Texture> Texture for CheckerTexture<T0, T1> {
             self.even.value(u, v, p)
         }
     }
+    fn describe(&self) -> TextureRef {
+        TextureRef::Inline(Box::new(TextureDesc::Checker {
+            even: self.even.describe(),
+            odd: self.odd.describe(),
+        }))
+    }
+    fn max_value(&self) -> f64 {
+        self.even.max_value().max(self.odd.max_value())
+    }
 }
 
 #[derive(Clone)]
@@ -62,19 +95,67 @@ impl NoiseTexture {
             scale,
         }
     }
+    pub fn new_from_perlin(noise: Perlin, scale: f64) -> Self {
+        Self { noise, scale }
+    }
 }
 
 impl Texture for NoiseTexture {
     fn value(&self, _u: f64, _v: f64, p: &Point3) -> Color {
         Color::new(1., 1., 1.) * 0.5 * (1. + (self.scale * p.z + 10. * self.noise.turb7(p)).sin())
     }
+    fn describe(&self) -> TextureRef {
+        TextureRef::Inline(Box::new(TextureDesc::Noise {
+            scale: self.scale,
+            perlin: Some(self.noise.describe()),
+        }))
+    }
+    fn max_value(&self) -> f64 {
+        1.
+    }
+}
+
+// gray turbulence of the noise, cut off at 1: wisps for the density of smoke and clouds
+#[derive(Clone)]
+pub struct TurbulenceTexture {
+    pub noise: Perlin,
+    pub scale: f64,
+}
+
+impl TurbulenceTexture {
+    pub fn new(scale: f64) -> Self {
+        Self {
+            noise: Perlin::new(),
+            scale,
+        }
+    }
+    pub fn new_from_perlin(noise: Perlin, scale: f64) -> Self {
+        Self { noise, scale }
+    }
+}
+
+impl Texture for TurbulenceTexture {
+    fn value(&self, _u: f64, _v: f64, p: &Point3) -> Color {
+        Color::grayscale(self.noise.turb7(&(*p * self.scale)).min(1.))
+    }
+    fn describe(&self) -> TextureRef {
+        TextureRef::Inline(Box::new(TextureDesc::Turbulence {
+            scale: self.scale,
+            perlin: Some(self.noise.describe()),
+        }))
+    }
+    fn max_value(&self) -> f64 {
+        1.
+    }
 }
 
 #[derive(Clone)]
 pub struct ImageTexture {
+    file_name: String,
     data: Arc<Vec<[u8; 3]>>,
     width: usize,
     height: usize,
+    max_value: f64,
 }
 
 impl ImageTexture {
@@ -90,12 +171,18 @@ impl ImageTexture {
                 data[i][j] = pixel[j];
             }
         }
+        let max_value = data.iter().flatten().max().copied().unwrap_or(0) as f64 / 255.;
         Self {
+            file_name: file_name.to_owned(),
             data: Arc::new(data),
             width,
             height,
+            max_value,
         }
     }
+    pub fn file_name(&self) -> &str {
+        &self.file_name
+    }
 }
 
 impl Texture for ImageTexture {
@@ -120,13 +207,23 @@ impl Texture for ImageTexture {
             self.data[index][2] as f64,
         ) / 255.
     }
+    fn describe(&self) -> TextureRef {
+        TextureRef::Inline(Box::new(TextureDesc::Image {
+            file: self.file_name.clone(),
+        }))
+    }
+    fn max_value(&self) -> f64 {
+        self.max_value
+    }
 }
 
 #[derive(Clone)]
 pub struct GrayImageTexture {
+    file_name: String,
     data: Arc<Vec<u8>>,
     width: usize,
     height: usize,
+    max_value: f64,
 }
 
 impl GrayImageTexture {
@@ -140,10 +237,13 @@ impl GrayImageTexture {
         for (i, pixel) in image.pixels().enumerate() {
             data[i] = pixel.0[0];
         }
+        let max_value = data.iter().max().copied().unwrap_or(0) as f64 / 255.;
         Self {
+            file_name: file_name.to_owned(),
             data: Arc::new(data),
             width,
             height,
+            max_value,
         }
     }
 }
@@ -166,6 +266,14 @@ impl Texture for GrayImageTexture {
         let index = j * self.width + i;
         Color::new(self.data[index] as f64 / 255., 0., 0.)
     }
+    fn describe(&self) -> TextureRef {
+        TextureRef::Inline(Box::new(TextureDesc::GrayImage {
+            file: self.file_name.clone(),
+        }))
+    }
+    fn max_value(&self) -> f64 {
+        self.max_value
+    }
 }
 
 #[derive(Clone)]
@@ -180,4 +288,16 @@ impl<T: Texture> Texture for MappingTexture<T> {
             Self::Texture(texture) => texture.value(u, v, p),
         }
     }
+    fn describe(&self) -> TextureRef {
+        match self {
+            Self::Color(color) => TextureRef::Color(color.to_array()),
+            Self::Texture(texture) => texture.describe(),
+        }
+    }
+    fn max_value(&self) -> f64 {
+        match self {
+            Self::Color(color) => color.x.max(color.y).max(color.z),
+            Self::Texture(texture) => texture.max_value(),
+        }
+    }
 }
@@ -1,5 +1,4 @@
-use crate::utility::*;
-use rand::prelude::SliceRandom;
+use crate::{scene::desc::PerlinDesc, utility::*};
 
 #[derive(Default, Clone)]
 pub struct Perlin {
@@ -30,12 +29,41 @@ impl Perlin {
         }
     }
 
+    // None if the tables have the wrong size or the permutations are out of range
+    pub fn from_desc(desc: &PerlinDesc) -> Option<Self> {
+        let valid_perm = |perm: &Vec<usize>| {
+            perm.len() == Self::POINT_COUNT && perm.iter().all(|&i| i < Self::POINT_COUNT)
+        };
+        if desc.ranvec.len() != Self::POINT_COUNT
+            || !valid_perm(&desc.perm_x)
+            || !valid_perm(&desc.perm_y)
+            || !valid_perm(&desc.perm_z)
+        {
+            return None;
+        }
+        Some(Self {
+            ranvec: desc.ranvec.iter().map(Vec3::from_array).collect(),
+            perm_x: desc.perm_x.clone(),
+            perm_y: desc.perm_y.clone(),
+            perm_z: desc.perm_z.clone(),
+        })
+    }
+
+    pub fn describe(&self) -> PerlinDesc {
+        PerlinDesc {
+            ranvec: self.ranvec.iter().map(|v| v.to_array()).collect(),
+            perm_x: self.perm_x.clone(),
+            perm_y: self.perm_y.clone(),
+            perm_z: self.perm_z.clone(),
+        }
+    }
+
     fn perlin_generate_perm() -> Vec<usize> {
         let mut p = vec![0; Self::POINT_COUNT];
         for (i, x) in p.iter_mut().enumerate() {
             *x = i;
         }
-        p.shuffle(&mut rand::thread_rng());
+        shuffle(&mut p);
         p
     }
 
@@ -0,0 +1,133 @@
+use crate::{scene::desc::*, texture::Texture, utility::*};
+use std::fs;
+
+// a 3d grid of values stretched over the box from min to max, interpolated trilinearly between
+// the centers of the voxels and clamped to the voxels at the edges. the file is raw binary,
+// little endian: the sizes nx, ny, nz and the channels (1 for a density, 3 for a color) as u32,
+// then the values as f32, x varying fastest, then y, then z
+#[derive(Clone)]
+pub struct VoxelTexture {
+    file_name: String,
+    data: Arc<Vec<f32>>,
+    size: [usize; 3],
+    channels: usize,
+    min: Point3,
+    max: Point3,
+    max_value: f64,
+}
+
+impl VoxelTexture {
+    pub fn new(file_name: &str, min: &Point3, max: &Point3) -> Result<Self, String> {
+        let bytes = fs::read(file_name).map_err(|e| e.to_string())?;
+        if bytes.len() < 16 {
+            return Err("a voxel file starts with 16 bytes of sizes and channels".to_owned());
+        }
+        let header: Vec<usize> = bytes[..16]
+            .chunks_exact(4)
+            .map(|b| u32::from_le_bytes([b[0], b[1], b[2], b[3]]) as usize)
+            .collect();
+        let size = [header[0], header[1], header[2]];
+        let channels = header[3];
+        if size.contains(&0) {
+            return Err("the sizes of a voxel grid must be positive".to_owned());
+        }
+        if channels != 1 && channels != 3 {
+            return Err(format!(
+                "a voxel grid has 1 or 3 channels, not {}",
+                channels
+            ));
+        }
+        let values = size
+            .iter()
+            .try_fold(channels, |n, &s| n.checked_mul(s))
+            .unwrap_or(usize::MAX);
+        if values.checked_mul(4) != Some(bytes.len() - 16) {
+            return Err(format!(
+                "a {}x{}x{} voxel grid with {} channels needs {} values after the header, found {} bytes",
+                size[0],
+                size[1],
+                size[2],
+                channels,
+                values,
+                bytes.len() - 16
+            ));
+        }
+        let data: Vec<f32> = bytes[16..]
+            .chunks_exact(4)
+            .map(|b| f32::from_le_bytes([b[0], b[1], b[2], b[3]]))
+            .collect();
+        if !data.iter().all(|v| v.is_finite() && *v >= 0.) {
+            return Err("the values of a voxel grid must be finite and not negative".to_owned());
+        }
+        let max_value = data.iter().fold(0., |m: f64, &v| m.max(v as f64));
+        Ok(Self {
+            file_name: file_name.to_owned(),
+            data: Arc::new(data),
+            size,
+            channels,
+            min: *min,
+            max: *max,
+            max_value,
+        })
+    }
+
+    fn voxel(&self, [x, y, z]: [usize; 3]) -> Color {
+        let i = ((z * self.size[1] + y) * self.size[0] + x) * self.channels;
+        if self.channels == 1 {
+            Color::grayscale(self.data[i] as f64)
+        } else {
+            Color::new(
+                self.data[i] as f64,
+                self.data[i + 1] as f64,
+                self.data[i + 2] as f64,
+            )
+        }
+    }
+}
+
+impl Texture for VoxelTexture {
+    fn value(&self, _u: f64, _v: f64, p: &Point3) -> Color {
+        let offset = (*p - self.min).to_array();
+        let extent = (self.max - self.min).to_array();
+        let mut cell = [0; 3];
+        let mut frac = [0.; 3];
+        for axis in 0..3 {
+            let last = (self.size[axis] - 1) as f64;
+            let x = clamp(
+                offset[axis] / extent[axis] * self.size[axis] as f64 - 0.5,
+                0.,
+                last,
+            );
+            cell[axis] = x as usize;
+            frac[axis] = x - cell[axis] as f64;
+        }
+        let mut color = Color::default();
+        for corner in 0..8 {
+            let mut weight = 1.;
+            let mut index = cell;
+            for axis in 0..3 {
+                if corner >> axis & 1 == 1 {
+                    weight *= frac[axis];
+                    index[axis] = (index[axis] + 1).min(self.size[axis] - 1);
+                } else {
+                    weight *= 1. - frac[axis];
+                }
+            }
+            if weight > 0. {
+                color += self.voxel(index) * weight;
+            }
+        }
+        color
+    }
+    fn describe(&self) -> TextureRef {
+        TextureRef::Inline(Box::new(TextureDesc::Voxel {
+            file: self.file_name.clone(),
+            min: self.min.to_array(),
+            max: self.max.to_array(),
+        }))
+    }
+    // interpolation stays within the voxels
+    fn max_value(&self) -> f64 {
+        self.max_value
+    }
+}
@@ -0,0 +1,99 @@
+use serde::Serialize;
+use std::{cell::Cell, ops::AddAssign};
+
+// render statistics, counted by each thread on its own and added up when it hands in a tile
+#[derive(Copy, Clone)]
+pub enum Counter {
+    PrimaryRays,
+    Rays, //every ray cast into the scene, shadow rays included
+    ShadowRays,
+    AabbTests,
+    PrimitiveTests, //spheres, rectangles and triangles
+    BvhVisits,
+    Paths,
+    PathVertices,
+    BvhBuildNanos,
+}
+
+const COUNTERS: usize = 9;
+
+thread_local! {
+    static COUNTS: [Cell<u64>; COUNTERS] = Default::default();
+}
+
+// the counts in the hot loops of the render; without the `stats` feature, nothing
+#[inline(always)]
+pub fn count(counter: Counter) {
+    if cfg!(feature = "stats") {
+        add(counter, 1);
+    }
+}
+
+pub fn add(counter: Counter, n: u64) {
+    COUNTS.with(|counts| {
+        let count = &counts[counter as usize];
+        count.set(count.get() + n);
+    });
+}
+
+// the counts of the current thread since the last call
+pub fn take_counts() -> Counts {
+    COUNTS.with(|counts| {
+        let take = |counter: Counter| counts[counter as usize].take();
+        Counts {
+            primary_rays: take(Counter::PrimaryRays),
+            rays: take(Counter::Rays),
+            shadow_rays: take(Counter::ShadowRays),
+            aabb_tests: take(Counter::AabbTests),
+            primitive_tests: take(Counter::PrimitiveTests),
+            bvh_visits: take(Counter::BvhVisits),
+            paths: take(Counter::Paths),
+            path_vertices: take(Counter::PathVertices),
+            bvh_build_nanos: take(Counter::BvhBuildNanos),
+        }
+    })
+}
+
+#[derive(Copy, Clone, Default, Debug, Serialize)]
+pub struct Counts {
+    pub primary_rays: u64,
+    pub rays: u64,
+    pub shadow_rays: u64,
+    pub aabb_tests: u64,
+    pub primitive_tests: u64,
+    pub bvh_visits: u64,
+    pub paths: u64,
+    pub path_vertices: u64,
+    #[serde(skip)]
+    pub bvh_build_nanos: u64,
+}
+
+impl AddAssign for Counts {
+    fn add_assign(&mut self, rhs: Self) {
+        self.primary_rays += rhs.primary_rays;
+        self.rays += rhs.rays;
+        self.shadow_rays += rhs.shadow_rays;
+        self.aabb_tests += rhs.aabb_tests;
+        self.primitive_tests += rhs.primitive_tests;
+        self.bvh_visits += rhs.bvh_visits;
+        self.paths += rhs.paths;
+        self.path_vertices += rhs.path_vertices;
+        self.bvh_build_nanos += rhs.bvh_build_nanos;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn counts_need_the_stats_feature() {
+        take_counts();
+        count(Counter::Rays);
+        add(Counter::BvhBuildNanos, 5);
+        let counts = take_counts();
+        assert_eq!(counts.rays, cfg!(feature = "stats") as u64);
+        assert_eq!(counts.bvh_build_nanos, 5);
+        assert_eq!(take_counts().rays, 0);
+    }
+}
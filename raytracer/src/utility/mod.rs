@@ -1,7 +1,12 @@
+pub mod counters;
 pub mod ray;
+pub mod rng;
 pub mod vec3;
 
+pub use counters::*;
 pub use ray::*;
+pub use rng::*;
+use std::cell::RefCell;
 pub use std::{
     f64::{
         consts::{PI, TAU},
@@ -14,12 +19,26 @@ pub use vec3::*;
 pub const TIME0: f64 = 0.;
 pub const TIME1: f64 = 1.;
 
+// the generator of the current thread, for building scenes;
+// rendering takes the `Sampler` of its path wherever it samples
+thread_local! {
+    static RNG: RefCell<Rng> = RefCell::new(Rng::from_entropy());
+}
+
+// reseed the random generator of the current thread
+pub fn seed(s: u64) {
+    RNG.with(|rng| *rng.borrow_mut() = Rng::new(s));
+}
+
 pub fn clamp(x: f64, min: f64, max: f64) -> f64 {
     x.clamp(min, max)
 }
 
 pub fn random() -> f64 {
-    rand::random::<f64>()
+    RNG.with(|rng| rng.borrow_mut().random())
+}
+pub fn lerp(a: f64, b: f64, t: f64) -> f64 {
+    a + (b - a) * t
 }
 pub fn randrange(min: f64, max: f64) -> f64 {
     min + (max - min) * random()
@@ -29,6 +48,9 @@ pub fn randint(min: i32, max: i32) -> i32 {
     randrange(min as f64, max as f64) as i32
     // rand::thread_rng().gen_range(min..max)
 }
+pub fn shuffle<T>(slice: &mut [T]) {
+    RNG.with(|rng| rng.borrow_mut().shuffle(slice));
+}
 
 pub fn eye3() -> ndarray::Array2<f64> {
     ndarray::Array2::eye(3)
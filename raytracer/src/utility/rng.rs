@@ -0,0 +1,56 @@
+use rand::{rngs::SmallRng, seq::SliceRandom, Rng as _, RngCore, SeedableRng};
+
+// a stream of random numbers; the renderer derives one for every sample of every pixel
+// from the global seed, so an image does not depend on how the work is split
+pub struct Rng(SmallRng);
+
+impl Rng {
+    pub fn new(seed: u64) -> Self {
+        Self(SmallRng::seed_from_u64(seed))
+    }
+
+    pub fn from_entropy() -> Self {
+        Self(SmallRng::from_entropy())
+    }
+
+    // the stream of the `sample`-th sample of a pixel
+    pub fn for_sample(seed: u64, pixel: u64, sample: u64) -> Self {
+        Self::new(splitmix64(splitmix64(seed ^ splitmix64(pixel)) ^ sample))
+    }
+
+    pub fn next_u64(&mut self) -> u64 {
+        self.0.next_u64()
+    }
+
+    pub fn random(&mut self) -> f64 {
+        self.0.gen::<f64>()
+    }
+
+    pub fn randrange(&mut self, min: f64, max: f64) -> f64 {
+        min + (max - min) * self.random()
+    }
+
+    // random int in [min,max)
+    pub fn randint(&mut self, min: i32, max: i32) -> i32 {
+        self.randrange(min as f64, max as f64) as i32
+    }
+
+    pub fn shuffle<T>(&mut self, slice: &mut [T]) {
+        slice.shuffle(&mut self.0);
+    }
+}
+
+// finalizer of SplitMix64, to decorrelate neighbouring seeds
+pub fn splitmix64(x: u64) -> u64 {
+    let mut z = x.wrapping_add(0x9E3779B97F4A7C15);
+    z = (z ^ (z >> 30)).wrapping_mul(0xBF58476D1CE4E5B9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94D049BB133111EB);
+    z ^ (z >> 31)
+}
+
+// FNV-1a, a hash that stays the same across runs and platforms
+pub fn fnv1a(bytes: &[u8]) -> u64 {
+    bytes.iter().fold(0xcbf29ce484222325, |hash, &byte| {
+        (hash ^ byte as u64).wrapping_mul(0x100000001b3)
+    })
+}
@@ -4,7 +4,7 @@ use std::ops::{
     Add, AddAssign, Div, DivAssign, Index, IndexMut, Mul, MulAssign, Neg, Sub, SubAssign,
 };
 
-use crate::utility::*;
+use crate::{sampler::Sampler, utility::*};
 
 pub type Point3 = Vec3;
 pub type Color = Vec3;
@@ -52,6 +52,9 @@ impl Vec3 {
     pub fn to_tuple(self) -> (f64, f64, f64) {
         (self.x, self.y, self.z)
     }
+    pub fn to_array(self) -> [f64; 3] {
+        [self.x, self.y, self.z]
+    }
     pub fn from_array<T: Index<usize, Output = f64>>(arr: &T) -> Self {
         Self::new(arr[0], arr[1], arr[2])
     }
@@ -60,16 +63,12 @@ impl Vec3 {
         Self::new(scale, scale, scale)
     }
 
-    pub fn random_in_unit_sphere() -> Vec3 {
-        loop {
-            let p = Vec3::randrange(-1., 1.);
-            if p.length_squared() < 1. {
-                return p;
-            }
-        }
+    // uniform in the ball: a direction and the cube root of a uniform radius
+    pub fn random_in_unit_sphere(sampler: &mut dyn Sampler) -> Vec3 {
+        Vec3::random_unit_vector(sampler) * sampler.get_1d().cbrt()
     }
-    pub fn random_in_hemisphere(normal: &Vec3) -> Vec3 {
-        let in_unit_sphere = Vec3::random_in_unit_sphere();
+    pub fn random_in_hemisphere(normal: &Vec3, sampler: &mut dyn Sampler) -> Vec3 {
+        let in_unit_sphere = Vec3::random_in_unit_sphere(sampler);
         if dot(&in_unit_sphere, normal) > 0. {
             in_unit_sphere
         } else {
@@ -77,18 +76,18 @@ impl Vec3 {
         }
     }
 
-    pub fn random_unit_vector() -> Vec3 {
-        let a = randrange(0., TAU);
-        let z = randrange(-1., 1.);
+    pub fn random_unit_vector(sampler: &mut dyn Sampler) -> Vec3 {
+        let (r1, r2) = sampler.get_2d();
+        let a = TAU * r1;
+        let z = 1. - 2. * r2;
         let r = (1. - z * z).sqrt();
         Vec3::new(r * a.cos(), r * a.sin(), z)
         //method 2: Normal Distribution
         //method 3: random_in_unit_sphere().unit()
     }
 
-    pub fn random_cosine_direction() -> Vec3 {
-        let r1 = random();
-        let r2 = random();
+    pub fn random_cosine_direction(sampler: &mut dyn Sampler) -> Vec3 {
+        let (r1, r2) = sampler.get_2d();
         let z = (1. - r2).sqrt();
         let phi = TAU * r1;
         let x = phi.cos() * r2.sqrt();
@@ -96,9 +95,8 @@ impl Vec3 {
         Vec3::new(x, y, z)
     }
 
-    pub fn random_to_sphere(radius: f64, distance_squared: f64) -> Vec3 {
-        let r1 = random();
-        let r2 = random();
+    pub fn random_to_sphere(radius: f64, distance_squared: f64, sampler: &mut dyn Sampler) -> Vec3 {
+        let (r1, r2) = sampler.get_2d();
         let z = 1. + r2 * ((1. - radius * radius / distance_squared).sqrt() - 1.);
         let phi = TAU * r1;
         let x = phi.cos() * (1. - z * z).sqrt();
@@ -106,13 +104,19 @@ impl Vec3 {
         Vec3::new(x, y, z)
     }
 
-    pub fn random_in_unit_disk() -> Vec3 {
-        loop {
-            let p = Vec3::new(randrange(-1., 1.), randrange(-1., 1.), 0.);
-            if p.length_squared() < 1. {
-                return p;
-            }
+    // concentric mapping of the square to the disk (Shirley & Chiu), which keeps strata intact
+    pub fn random_in_unit_disk(sampler: &mut dyn Sampler) -> Vec3 {
+        let (r1, r2) = sampler.get_2d();
+        let (a, b) = (2. * r1 - 1., 2. * r2 - 1.);
+        if a == 0. && b == 0. {
+            return Vec3::default();
         }
+        let (r, theta) = if a.abs() > b.abs() {
+            (a, PI / 4. * (b / a))
+        } else {
+            (b, PI / 2. - PI / 4. * (a / b))
+        };
+        Vec3::new(r * theta.cos(), r * theta.sin(), 0.)
     }
 
     pub fn gold() -> Self {
@@ -219,6 +223,13 @@ impl MulAssign<f64> for Vec3 {
         self.z *= rhs;
     }
 }
+impl MulAssign for Vec3 {
+    fn mul_assign(&mut self, rhs: Self) {
+        self.x *= rhs.x;
+        self.y *= rhs.y;
+        self.z *= rhs.z;
+    }
+}
 
 impl Div<f64> for Vec3 {
     type Output = Self;